@@ -0,0 +1,91 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Compares a single-shard LockSpace with sharded ones when many threads lock
+//! unrelated keys.
+//!
+//! These benchmarks were first run while a thread waiting for a key still
+//! held the outer lock of its shard. Since then, the rule is the opposite:
+//! nobody blocks on a key while holding the outer lock (see `Shard`). Results
+//! under the new rule, per iteration of 8 threads locking one key each, with
+//! `--warm-up-time 1 --measurement-time 3` on a machine with a single CPU:
+//!
+//! | shards | auto_cleanup | keep_unused |
+//! |-------:|-------------:|------------:|
+//! |      1 |      9.30 µs |    11.40 µs |
+//! |      4 |     10.57 µs |    15.19 µs |
+//! |     16 |      9.03 µs |    11.75 µs |
+//! |     64 |      8.88 µs |    10.72 µs |
+//!
+//! With one CPU, the threads never contend on the outer lock at the same
+//! time. So these numbers only show that sharding adds no real overhead, not
+//! how much it helps. Run the benchmarks on a machine with at least 8 CPUs to
+//! see that.
+
+#[macro_use] extern crate criterion;
+extern crate namedlock;
+
+use std::sync::{Arc,Barrier};
+use std::thread;
+use std::time::{Duration,Instant};
+
+use criterion::{BenchmarkId,Criterion};
+use namedlock::{LockSpace,AutoCleanup,KeepUnused,Cleanup};
+
+const THREADS: usize = 8;
+
+// Have THREADS threads lock `iters` keys each, every thread using its own
+// keys. Returns the time it took for all threads to finish.
+fn lock_unrelated(space: &LockSpace<usize,usize>, iters: u64) -> Duration {
+	let barrier=Arc::new(Barrier::new(THREADS+1));
+	let threads: Vec<_> = (0..THREADS).map(|t| {
+		let space=space.clone();
+		let barrier=barrier.clone();
+		thread::spawn(move||{
+			barrier.wait();
+			for i in 0..iters as usize {
+				*space.lock(i*THREADS+t,||0).unwrap()+=1;
+			}
+		})
+	}).collect();
+
+	barrier.wait();
+	let start=Instant::now();
+	for t in threads.into_iter() {
+		t.join().unwrap();
+	}
+	start.elapsed()
+}
+
+fn bench_cleanup(c: &mut Criterion, name: &str, cleanup: Cleanup) {
+	let mut group=c.benchmark_group(name);
+	for &shards in &[1,4,16,64] {
+		group.bench_with_input(BenchmarkId::from_parameter(shards),&shards,|b,&shards| {
+			let space=LockSpace::with_shards(cleanup,shards);
+			b.iter_custom(|iters|lock_unrelated(&space,iters));
+		});
+	}
+	group.finish();
+}
+
+fn sharding(c: &mut Criterion) {
+	bench_cleanup(c,"auto_cleanup",AutoCleanup);
+	bench_cleanup(c,"keep_unused",KeepUnused);
+}
+
+criterion_group!(benches,sharding);
+criterion_main!(benches);
@@ -0,0 +1,522 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Namespaces for named locks that can be awaited.
+//!
+//! `AsyncLockSpace` works just like `LockSpace`, except that waiting for a
+//! value to become available parks the task instead of the thread. It does not
+//! depend on any particular async runtime.
+
+use std::cell::UnsafeCell;
+use std::collections::{HashMap,VecDeque};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::Pin;
+use std::sync::{self,Arc};
+use std::task::{Context,Poll,Waker};
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))] use std::sync::Mutex;
+#[cfg(feature="spin")] use spin::Mutex;
+#[cfg(feature="parking_lot")] use parking_lot::Mutex;
+use core::hash::Hash;
+use core::ops::{Deref,DerefMut};
+
+use {Cleanup,LockSpaceRemoveResult,SpaceValue,get_or_insert,try_remove_internal,release,lock_unpoisoned};
+
+struct AsyncMutexState {
+	locked: bool,
+	next_id: u64,
+	// Waiting futures in FIFO order. The waker is None if the future hasn't
+	// been polled since it was queued.
+	waiters: VecDeque<(u64,Option<Waker>)>,
+	// The waiter that the lock was handed over to, which hasn't taken it yet
+	granted: Option<u64>,
+}
+
+// A mutex that queues its waiters and wakes them in order. When it's unlocked
+// while there are waiters, the lock is handed over directly to the first one.
+struct AsyncMutex<V> {
+	// This only protects the bookkeeping, so it's never held for long
+	state: sync::Mutex<AsyncMutexState>,
+	// None until the first holder creates the value, so that it isn't created
+	// while holding the outer lock
+	value: UnsafeCell<Option<V>>,
+}
+
+// SAFETY: Like with Mutex<V>, the value is only accessed by the holder of the
+// lock, one thread at a time, so V only needs to be Send
+unsafe impl<V: Send> Send for AsyncMutex<V> {}
+unsafe impl<V: Send> Sync for AsyncMutex<V> {}
+
+impl<V> AsyncMutex<V> {
+	fn new() -> AsyncMutex<V> {
+		AsyncMutex{
+			state:sync::Mutex::new(AsyncMutexState{locked:false,next_id:0,waiters:VecDeque::new(),granted:None}),
+			value:UnsafeCell::new(None),
+		}
+	}
+
+	fn state(&self) -> sync::MutexGuard<'_,AsyncMutexState> {
+		// The state is always consistent, even if someone panicked
+		self.state.lock().unwrap_or_else(sync::PoisonError::into_inner)
+	}
+
+	// Take the lock if it's available, otherwise join the queue and return
+	// the waiter id.
+	fn lock_or_enqueue(&self, waker: &Waker) -> ::core::result::Result<(),u64> {
+		let mut state=self.state();
+		if !state.locked {
+			state.locked=true;
+			return Ok(());
+		}
+		let id=state.next_id;
+		state.next_id+=1;
+		state.waiters.push_back((id,Some(waker.clone())));
+		Err(id)
+	}
+
+	// Check whether the lock was handed over to waiter `id`
+	fn poll_granted(&self, id: u64, waker: &Waker) -> bool {
+		let mut state=self.state();
+		if state.granted==Some(id) {
+			state.granted=None;
+			return true;
+		}
+		if let Some(waiter)=state.waiters.iter_mut().find(|w|w.0==id) {
+			waiter.1=Some(waker.clone());
+		}
+		false
+	}
+
+	// Leave the queue. If the lock had already been handed over to waiter
+	// `id`, pass it on instead.
+	fn cancel(&self, id: u64) {
+		let mut state=self.state();
+		if state.granted==Some(id) {
+			state.granted=None;
+			self.unlock_internal(state);
+		} else {
+			state.waiters.retain(|w|w.0!=id);
+		}
+	}
+
+	fn unlock(&self) {
+		self.unlock_internal(self.state())
+	}
+
+	fn unlock_internal(&self, mut state: sync::MutexGuard<AsyncMutexState>) {
+		match state.waiters.pop_front() {
+			Some((id,waker)) => {
+				// Stay locked, the lock now belongs to the waiter
+				state.granted=Some(id);
+				drop(state);
+				if let Some(waker)=waker {
+					waker.wake();
+				}
+			},
+			None => state.locked=false,
+		}
+	}
+}
+
+type AsyncLockSpaceValue<K,V> = SpaceValue<K,AsyncMutex<V>>;
+
+/// An RAII implementation of a "scoped lock" of an AsyncLockSpace value. When
+/// this structure is dropped (falls out of scope), the lock will be unlocked,
+/// and the reference count to the key will be decreased by 1.
+///
+/// The actual value can be accessed through this guard via its Deref and
+/// DerefMut implementations.
+///
+/// Like a `&mut V`, the guard can only be shared between threads if `V` is
+/// `Sync`:
+///
+/// ```compile_fail
+/// use std::cell::Cell;
+/// use namedlock::AsyncLockSpaceGuard;
+///
+/// fn is_sync<T: Sync>() {}
+/// is_sync::<AsyncLockSpaceGuard<'static,String,Cell<i32>>>();
+/// ```
+pub struct AsyncLockSpaceGuard<'a,K: 'a + Eq + Hash + Clone,V: 'a> {
+	owner: &'a AsyncLockSpace<K,V>,
+	target: Option<AsyncLockSpaceValue<K,V>>,
+	// AsyncMutex is Sync if V is only Send, but the guard hands out &V
+	_value: PhantomData<&'a mut V>,
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> AsyncLockSpaceGuard<'a,K,V> {
+	// Make a guard for the inner lock of `target`, which was just acquired.
+	// If the value doesn't exist yet, it's created by calling `initial`. The
+	// guard exists by then, so the lock is released if `initial` panics.
+	fn new<C>(owner: &'a AsyncLockSpace<K,V>, target: AsyncLockSpaceValue<K,V>, initial: C) -> AsyncLockSpaceGuard<'a,K,V>
+		where C: FnOnce() -> V
+	{
+		let mut guard=AsyncLockSpaceGuard{owner,target:Some(target),_value:PhantomData};
+		let value=guard.slot();
+		if value.is_none() {
+			*value=Some(initial());
+		}
+		guard
+	}
+
+	fn slot(&mut self) -> &mut Option<V> {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		match self.target {
+			// SAFETY: We hold the lock, so no one else is accessing the
+			// value, and the returned reference borrows the guard mutably
+			Some(ref target) => unsafe{&mut*target.lock.value.get()},
+			None => unreachable!(),
+		}
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> Deref for AsyncLockSpaceGuard<'a,K,V> {
+	type Target = V;
+	fn deref(&self) -> &V {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		let value=match self.target {
+			// SAFETY: We hold the lock, so no one is changing the value,
+			// and the returned reference borrows the guard
+			Some(ref target) => unsafe{&*target.lock.value.get()},
+			None => unreachable!(),
+		};
+		// This is always Some, because guards are only handed out once the value is created
+		value.as_ref().unwrap()
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> DerefMut for AsyncLockSpaceGuard<'a,K,V> {
+	fn deref_mut(&mut self) -> &mut V {
+		// This is always Some, because guards are only handed out once the value is created
+		self.slot().as_mut().unwrap()
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> Drop for AsyncLockSpaceGuard<'a,K,V> {
+	fn drop(&mut self) {
+		let target=self.target.take().unwrap();
+		// release inner lock, possibly handing it over to the next waiter
+		target.lock.unlock();
+		release(&self.owner.names,self.owner.cleanup,target);
+	}
+}
+
+enum AsyncLockState<K,V,C> {
+	Start(K,C),
+	// Queued, holding a reference to the inner lock like a guard would. The
+	// value may still need to be created once the lock is handed over.
+	Waiting(AsyncLockSpaceValue<K,V>,u64,C),
+	Done,
+}
+
+/// The future returned by `AsyncLockSpace::lock`.
+///
+/// If this future is dropped before it completes, it leaves the queue for
+/// the key, and the value is cleaned up if `AutoCleanup` is specified for the
+/// space and this was the last use.
+#[must_use = "futures do nothing unless polled"]
+pub struct AsyncLock<'a,K: 'a + Eq + Hash + Clone,V: 'a,C> {
+	owner: &'a AsyncLockSpace<K,V>,
+	state: AsyncLockState<K,V,C>,
+}
+
+// The future is never pinned structurally
+impl<'a,K: Eq + Hash + Clone,V: 'a,C> Unpin for AsyncLock<'a,K,V,C> {}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a,C> Future for AsyncLock<'a,K,V,C>
+	where C: FnOnce() -> V
+{
+	type Output = AsyncLockSpaceGuard<'a,K,V>;
+
+	fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+		let this=self.get_mut();
+		match mem::replace(&mut this.state,AsyncLockState::Done) {
+			AsyncLockState::Start(key,initial) => {
+				let mut map=lock_unpoisoned(&this.owner.names); // Acquire outer lock
+				let target=get_or_insert(&mut *map,&key,AsyncMutex::new);
+				let result=target.lock.lock_or_enqueue(cx.waker());
+				drop(map); // Explicitly release outer lock
+				match result {
+					Ok(()) => Poll::Ready(AsyncLockSpaceGuard::new(this.owner,target,initial)),
+					Err(id) => {
+						this.state=AsyncLockState::Waiting(target,id,initial);
+						Poll::Pending
+					}
+				}
+			},
+			AsyncLockState::Waiting(target,id,initial) => {
+				if target.lock.poll_granted(id,cx.waker()) {
+					Poll::Ready(AsyncLockSpaceGuard::new(this.owner,target,initial))
+				} else {
+					this.state=AsyncLockState::Waiting(target,id,initial);
+					Poll::Pending
+				}
+			},
+			AsyncLockState::Done => panic!("AsyncLock polled after completion"),
+		}
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a,C> Drop for AsyncLock<'a,K,V,C> {
+	fn drop(&mut self) {
+		if let AsyncLockState::Waiting(target,id,_)=mem::replace(&mut self.state,AsyncLockState::Done) {
+			target.lock.cancel(id);
+			release(&self.owner.names,self.owner.cleanup,target);
+		}
+	}
+}
+
+/// An `AsyncLockSpace<K,V>` holds many asynchronous mutexes containing a `V`,
+/// keyed by `K`.
+///
+/// Locking a value returns a future, which completes once the lock has been
+/// acquired. Tasks waiting for the same key are queued, and are woken up one
+/// at a time in order. Cleanup works exactly the same as for `LockSpace`,
+/// where a waiting future counts as a use of the key.
+///
+/// ```
+/// use std::future::Future;
+/// use std::pin::Pin;
+/// use std::sync::Arc;
+/// use std::task::{Context,Poll,Wake,Waker};
+/// use namedlock::{AsyncLockSpace,AutoCleanup};
+///
+/// struct NoopWaker;
+/// impl Wake for NoopWaker {
+///     fn wake(self: Arc<Self>) {}
+/// }
+/// let waker=Waker::from(Arc::new(NoopWaker));
+/// let mut cx=Context::from_waker(&waker);
+///
+/// let space=AsyncLockSpace::<String,i32>::new(AutoCleanup);
+/// let mut lock=space.lock("test".to_owned(),||0);
+/// match Pin::new(&mut lock).poll(&mut cx) {
+///     Poll::Ready(mut guard) => *guard+=1,
+///     Poll::Pending => unreachable!(),
+/// }
+/// drop(lock);
+/// ```
+pub struct AsyncLockSpace<K: Eq + Hash,V> {
+	// The outer lock is never held across an await point. The same reference
+	// counting invariants as for `LockSpace` apply here, with each guard and
+	// each queued future holding 1 reference to the inner lock.
+	names: Arc<Mutex<HashMap<K,AsyncLockSpaceValue<K,V>>>>,
+	cleanup: Cleanup,
+}
+
+// This needs to be implemented manually, since #[derive(Clone)] doesn't
+// understand that the type parameters are only used within the Arc<_>
+impl<K: Eq + Hash,V> Clone for AsyncLockSpace<K,V> {
+	fn clone(&self) -> AsyncLockSpace<K,V> {
+		AsyncLockSpace{names:self.names.clone(),cleanup:self.cleanup}
+	}
+}
+
+impl<K: Eq + Hash + Clone,V> AsyncLockSpace<K,V> {
+	/// Create a new AsyncLockSpace.
+	///
+	/// If `cleanup` is `AutoCleanup`, values will be deleted automatically when
+	/// the last lock is released and no one is waiting for it. Otherwise,
+	/// values will remain in the space until `try_remove()` returns `Success`.
+	pub fn new(cleanup: Cleanup) -> AsyncLockSpace<K,V> {
+		AsyncLockSpace{names:Arc::new(Mutex::new(HashMap::new())),cleanup}
+	}
+
+	/// Find the object by `key`, or create it by calling `initial` if it does
+	/// not exist. Then, lock it and return an AsyncLockSpaceGuard over the
+	/// object, once the returned future completes.
+	///
+	/// Nothing happens until the future is first polled. `initial` is called
+	/// while holding the lock for `key`, but not the lock of the space itself.
+	/// If it panics, the next future for `key` creates the value instead.
+	///
+	/// Since a guard isn't tied to a thread, a panic while it's held doesn't
+	/// poison the value.
+	pub fn lock<C>(&self, key: K, initial: C) -> AsyncLock<'_,K,V,C>
+		where C: FnOnce() -> V
+	{
+		AsyncLock{owner:self,state:AsyncLockState::Start(key,initial)}
+	}
+
+	/// Find the object by `key`, then delete it if it is not actively being
+	/// used. If it is locked or someone is waiting for it, `WouldBlock` will
+	/// be returned.
+	///
+	/// This is only useful if this `AsyncLockSpace` is of the `KeepUnused`
+	/// kind.
+	pub fn try_remove(&self, key: K) -> LockSpaceRemoveResult {
+		let mut map=lock_unpoisoned(&self.names); // Acquire outer lock
+		try_remove_internal(&mut *map,&key,0)
+		// Release outer lock
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::prelude::v1::*;
+	use std::future::Future;
+	use std::pin::Pin;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize,Ordering};
+	use std::task::{Context,Poll,Wake,Waker};
+	use std::thread::{self,Thread};
+	use super::*;
+	use {AutoCleanup,KeepUnused};
+
+	struct ThreadWaker(Thread);
+
+	impl Wake for ThreadWaker {
+		fn wake(self: Arc<Self>) {
+			self.0.unpark();
+		}
+	}
+
+	// A minimal executor that runs a single future on the current thread
+	fn block_on<F: Future>(future: F) -> F::Output {
+		let mut future=Box::pin(future);
+		let waker=Waker::from(Arc::new(ThreadWaker(thread::current())));
+		let mut cx=Context::from_waker(&waker);
+		loop {
+			match future.as_mut().poll(&mut cx) {
+				Poll::Ready(output) => return output,
+				Poll::Pending => thread::park(),
+			}
+		}
+	}
+
+	struct CountingWaker(AtomicUsize);
+
+	impl Wake for CountingWaker {
+		fn wake(self: Arc<Self>) {
+			self.0.fetch_add(1,Ordering::SeqCst);
+		}
+	}
+
+	fn poll_once<F: Future + Unpin>(future: &mut F, waker: &Waker) -> Poll<F::Output> {
+		Pin::new(future).poll(&mut Context::from_waker(waker))
+	}
+
+	#[test]
+	fn threads() {
+		let space=AsyncLockSpace::<String,usize>::new(AutoCleanup);
+		let mut threads=vec![];
+
+		for _ in 0..50 {
+			let space_clone=space.clone();
+			threads.push(thread::spawn(move||{
+				for _ in 0..10 {
+					*block_on(space_clone.lock("test".to_string(),||0))+=1;
+				}
+			}));
+		}
+
+		for t in threads.into_iter() {
+			t.join().unwrap();
+		}
+
+		// All threads are done, so the value should have been cleaned up
+		assert_eq!(*block_on(space.lock("test".to_string(),||1000)),1000);
+	}
+
+	#[test]
+	fn cancellation() {
+		let space=AsyncLockSpace::<String,usize>::new(AutoCleanup);
+		let counter=Arc::new(CountingWaker(AtomicUsize::new(0)));
+		let waker=Waker::from(counter.clone());
+
+		let guard=block_on(space.lock("test".to_string(),||1));
+		let mut second=space.lock("test".to_string(),||panic!("Value must exist"));
+		let mut third=space.lock("test".to_string(),||panic!("Value must exist"));
+		let mut fourth=space.lock("test".to_string(),||panic!("Value must exist"));
+		assert!(poll_once(&mut second,&waker).is_pending());
+		assert!(poll_once(&mut third,&waker).is_pending());
+		assert!(poll_once(&mut fourth,&waker).is_pending());
+
+		// Leaving the queue must not wake anyone
+		drop(third);
+		assert_eq!(counter.0.load(Ordering::SeqCst),0);
+
+		// The lock is handed over to `second`, which passes it on to `fourth`
+		// when it's dropped
+		drop(guard);
+		assert_eq!(counter.0.load(Ordering::SeqCst),1);
+		drop(second);
+		assert_eq!(counter.0.load(Ordering::SeqCst),2);
+		match poll_once(&mut fourth,&waker) {
+			Poll::Ready(guard) => assert_eq!(*guard,1),
+			Poll::Pending => panic!("Lock should have been handed over"),
+		}
+
+		// A future that never completes must not keep the value alive
+		let guard=block_on(space.lock("test".to_string(),||1));
+		let mut waiting=space.lock("test".to_string(),||panic!("Value must exist"));
+		assert!(poll_once(&mut waiting,&waker).is_pending());
+		drop(guard);
+		drop(waiting);
+		assert_eq!(*block_on(space.lock("test".to_string(),||2)),2);
+	}
+
+	#[test]
+	fn keep_unused() {
+		let space=AsyncLockSpace::<String,usize>::new(KeepUnused);
+		*block_on(space.lock("test".to_string(),||1))+=1;
+		assert_eq!(*block_on(space.lock("test".to_string(),||5)),2);
+		assert!(matches!(space.try_remove("test".to_string()),LockSpaceRemoveResult::Success));
+	}
+
+	#[test]
+	fn initial_outside_lock() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		let space=AsyncLockSpace::<String,usize>::new(KeepUnused);
+		// The space isn't locked while creating a value
+		let value=block_on(space.lock("a".to_string(),||*block_on(space.lock("b".to_string(),||1))+1));
+		assert_eq!(*value,2);
+		drop(value);
+
+		// If creating the value fails, the next locker creates it
+		let result=panic::catch_unwind(AssertUnwindSafe(||drop(block_on(space.lock("c".to_string(),||panic!("Initializer panicked"))))));
+		assert!(result.is_err());
+		assert_eq!(*block_on(space.lock("c".to_string(),||3)),3);
+	}
+
+	#[test]
+	#[cfg(all(not(feature="spin"),not(feature="parking_lot")))]
+	fn panic_isolation() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		// A panic in Clone happens while holding the outer lock
+		#[derive(PartialEq,Eq,Hash)]
+		struct Key(bool);
+		impl Clone for Key {
+			fn clone(&self) -> Key {
+				assert!(!self.0,"Clone panicked");
+				Key(self.0)
+			}
+		}
+
+		let space=AsyncLockSpace::<Key,usize>::new(KeepUnused);
+		*block_on(space.lock(Key(false),||0))+=1;
+		let result=panic::catch_unwind(AssertUnwindSafe(||drop(block_on(space.lock(Key(true),||0)))));
+		assert!(result.is_err());
+
+		// It doesn't poison the space
+		assert_eq!(*block_on(space.lock(Key(false),||0)),1);
+		assert!(matches!(space.try_remove(Key(false)),LockSpaceRemoveResult::Success));
+	}
+}
@@ -0,0 +1,505 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! A common interface to the different kinds of lock spaces.
+//!
+//! Code that only needs to lock and remove keys can be written against the
+//! `LockBackend` trait. Then, the in-memory `LockSpace`, the cross-process
+//! `FileLockSpace`, `ShmLockSpace` and `RemoteLockSpace`, or a `MockBackend`
+//! in tests, can be plugged in without changing it.
+//!
+//! The in-memory `LockSpace` is a backend for every storage of its keys, so
+//! its map can be replaced as well, see the `storage` module. The other
+//! spaces keep their keys outside the process, in files, shared memory or a
+//! lock server, and implement the trait on top of that.
+//!
+//! ```
+//! use namedlock::{LockSpace,KeepUnused};
+//! use namedlock::backend::{LockBackend,MockBackend,MockEvent};
+//!
+//! // Returns whether the job ran
+//! fn run_job<B: LockBackend<String>>(locks: &B, job: &str) -> bool {
+//!     match locks.try_acquire(job.to_owned()) {
+//!         Ok(guard) => {
+//!             // ...
+//!             locks.release(guard);
+//!             true
+//!         },
+//!         Err(_) => false,
+//!     }
+//! }
+//!
+//! assert!(run_job(&LockSpace::<String,()>::new(KeepUnused),"backup"));
+//!
+//! let mock=MockBackend::new();
+//! assert!(run_job(&mock,"backup"));
+//! assert_eq!(mock.events(),[MockEvent::Acquire("backup".to_owned()),MockEvent::Release("backup".to_owned())]);
+//! ```
+
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::Hash;
+use std::io;
+use std::sync::{self,Arc,Condvar};
+
+use lockresult::{LockError,TryLockError,PoisonError};
+use {LockSpace,LockSpaceGuard,LockSpaceRemoveResult,Storage};
+#[cfg(all(feature="file",unix))] use filelockspace::{self,FileLockSpace,FileLockGuard};
+#[cfg(all(feature="shm",target_os="linux"))] use shmlockspace::{self,ShmLockSpace,ShmLockGuard};
+#[cfg(unix)] use remote::{self,RemoteLockSpace,RemoteLockGuard};
+
+/// An enumeration of possible errors of the `LockBackend` methods.
+pub enum BackendError<G=()> {
+	/// The previous holder of the key panicked or its process died. If the
+	/// key was acquired anyway, the error carries the guard.
+	Poisoned(PoisonError<G>),
+	/// The key is held by someone else, and the method doesn't block.
+	WouldBlock,
+	/// The backend failed, for example because a lock file couldn't be
+	/// created.
+	Failed(io::Error),
+}
+
+impl<G> From<PoisonError<G>> for BackendError<G> {
+	fn from(err: PoisonError<G>) -> BackendError<G> {
+		BackendError::Poisoned(err)
+	}
+}
+
+impl<G> From<io::Error> for BackendError<G> {
+	fn from(err: io::Error) -> BackendError<G> {
+		BackendError::Failed(err)
+	}
+}
+
+impl<G> fmt::Debug for BackendError<G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			BackendError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			BackendError::WouldBlock => fmt.write_str("WouldBlock"),
+			BackendError::Failed(ref err) => write!(fmt,"Failed({:?})",err),
+		}
+	}
+}
+
+/// A `Result` type for the `LockBackend` methods.
+pub type BackendResult<T,G=()> = Result<T,BackendError<G>>;
+
+/// A common interface to lock spaces with keys of type `K`.
+///
+/// Each of the lock spaces implements the trait on top of its own storage.
+/// For `LockSpace`, that storage is pluggable in turn, see `Storage`.
+pub trait LockBackend<K> {
+	/// An RAII guard of a key. The key is released when the guard is
+	/// dropped.
+	type Guard<'a> where Self: 'a;
+
+	/// Acquire `key`, blocking the current thread until that's possible.
+	fn acquire<'a>(&'a self, key: K) -> BackendResult<Self::Guard<'a>,Self::Guard<'a>>;
+
+	/// Acquire `key` if that's possible without blocking. Otherwise,
+	/// `WouldBlock` is returned.
+	fn try_acquire<'a>(&'a self, key: K) -> BackendResult<Self::Guard<'a>,Self::Guard<'a>>;
+
+	/// Release a key acquired from this backend. This is the same as dropping
+	/// `guard`.
+	fn release<'a>(&'a self, guard: Self::Guard<'a>) {
+		drop(guard)
+	}
+
+	/// Remove `key` from the backend if it's not held, and return whether it
+	/// was there. If it is held, `WouldBlock` is returned.
+	fn remove(&self, key: K) -> BackendResult<bool>;
+}
+
+fn remove_result(result: LockSpaceRemoveResult) -> BackendResult<bool> {
+	match result {
+		LockSpaceRemoveResult::Success => Ok(true),
+		LockSpaceRemoveResult::NotFound => Ok(false),
+		LockSpaceRemoveResult::WouldBlock => Err(BackendError::WouldBlock),
+		LockSpaceRemoveResult::PoisonError => Err(BackendError::Poisoned(PoisonError::new(()))),
+	}
+}
+
+/// Objects are created with `V::default()`. A `Deadlock` error is returned
+/// as `Failed` with `ErrorKind::Deadlock`.
+impl<K: Eq + Hash + Clone,V: Default,S: Storage<K>> LockBackend<K> for LockSpace<K,V,S> {
+	type Guard<'a> = LockSpaceGuard<'a,K,V,S> where Self: 'a;
+
+	fn acquire<'a>(&'a self, key: K) -> BackendResult<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>> {
+		self.lock_checked(key,V::default).map_err(|err|match err {
+			LockError::Poisoned(err) => BackendError::Poisoned(err),
+			LockError::Deadlock(_) => BackendError::Failed(io::ErrorKind::Deadlock.into()),
+		})
+	}
+
+	fn try_acquire<'a>(&'a self, key: K) -> BackendResult<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>> {
+		self.try_lock(key,V::default).map_err(|err|match err {
+			TryLockError::Poisoned(err) => BackendError::Poisoned(err),
+			TryLockError::WouldBlock => BackendError::WouldBlock,
+		})
+	}
+
+	fn remove(&self, key: K) -> BackendResult<bool> {
+		remove_result(self.try_remove(key))
+	}
+}
+
+#[cfg(all(feature="file",unix))]
+impl<K: Eq + Hash + Clone + AsRef<[u8]>> LockBackend<K> for FileLockSpace<K> {
+	type Guard<'a> = FileLockGuard<'a,K> where Self: 'a;
+
+	fn acquire<'a>(&'a self, key: K) -> BackendResult<FileLockGuard<'a,K>,FileLockGuard<'a,K>> {
+		Ok(self.lock(key)?)
+	}
+
+	fn try_acquire<'a>(&'a self, key: K) -> BackendResult<FileLockGuard<'a,K>,FileLockGuard<'a,K>> {
+		self.try_lock(key).map_err(From::from)
+	}
+
+	fn remove(&self, key: K) -> BackendResult<bool> {
+		self.try_remove(key).map_err(From::from)
+	}
+}
+
+#[cfg(all(feature="file",unix))]
+impl<G> From<filelockspace::TryLockError> for BackendError<G> {
+	fn from(err: filelockspace::TryLockError) -> BackendError<G> {
+		match err {
+			filelockspace::TryLockError::WouldBlock => BackendError::WouldBlock,
+			filelockspace::TryLockError::Io(err) => BackendError::Failed(err),
+		}
+	}
+}
+
+/// A full space, a key that's too long and a `Deadlock` error are returned
+/// as `Failed`, with `ErrorKind::StorageFull`, `ErrorKind::InvalidInput` and
+/// `ErrorKind::Deadlock` respectively. So is an `Io` error, as is.
+#[cfg(all(feature="shm",target_os="linux"))]
+impl<K: AsRef<[u8]>> LockBackend<K> for ShmLockSpace<K> {
+	type Guard<'a> = ShmLockGuard<'a> where Self: 'a;
+
+	fn acquire<'a>(&'a self, key: K) -> BackendResult<ShmLockGuard<'a>,ShmLockGuard<'a>> {
+		self.lock(key).map_err(|err|match err {
+			shmlockspace::LockError::Poisoned(err) => BackendError::Poisoned(err),
+			shmlockspace::LockError::KeyTooLong => BackendError::Failed(io::ErrorKind::InvalidInput.into()),
+			shmlockspace::LockError::Full => BackendError::Failed(io::ErrorKind::StorageFull.into()),
+			shmlockspace::LockError::Deadlock => BackendError::Failed(io::ErrorKind::Deadlock.into()),
+			shmlockspace::LockError::Io(err) => BackendError::Failed(err),
+		})
+	}
+
+	fn try_acquire<'a>(&'a self, key: K) -> BackendResult<ShmLockGuard<'a>,ShmLockGuard<'a>> {
+		self.try_lock(key).map_err(|err|match err {
+			shmlockspace::TryLockError::Poisoned(err) => BackendError::Poisoned(err),
+			shmlockspace::TryLockError::WouldBlock => BackendError::WouldBlock,
+			shmlockspace::TryLockError::KeyTooLong => BackendError::Failed(io::ErrorKind::InvalidInput.into()),
+			shmlockspace::TryLockError::Full => BackendError::Failed(io::ErrorKind::StorageFull.into()),
+			shmlockspace::TryLockError::Io(err) => BackendError::Failed(err),
+		})
+	}
+
+	fn remove(&self, key: K) -> BackendResult<bool> {
+		remove_result(self.try_remove(key).map_err(BackendError::Failed)?)
+	}
+}
+
+/// A key that is held through the same connection can't be acquired again.
+/// `acquire()` fails with `Failed`, and `try_acquire()` with `WouldBlock`.
+#[cfg(unix)]
+impl LockBackend<String> for RemoteLockSpace {
+	type Guard<'a> = RemoteLockGuard<'a>;
+
+	fn acquire<'a>(&'a self, key: String) -> BackendResult<RemoteLockGuard<'a>,RemoteLockGuard<'a>> {
+		Ok(self.lock(key)?)
+	}
+
+	fn try_acquire<'a>(&'a self, key: String) -> BackendResult<RemoteLockGuard<'a>,RemoteLockGuard<'a>> {
+		self.try_lock(key).map_err(From::from)
+	}
+
+	fn remove(&self, key: String) -> BackendResult<bool> {
+		self.try_remove(key).map_err(From::from)
+	}
+}
+
+#[cfg(unix)]
+impl<G> From<remote::TryLockError> for BackendError<G> {
+	fn from(err: remote::TryLockError) -> BackendError<G> {
+		match err {
+			remote::TryLockError::WouldBlock => BackendError::WouldBlock,
+			remote::TryLockError::Io(err) => BackendError::Failed(err),
+		}
+	}
+}
+
+/// A call on a `MockBackend`, as returned by `MockBackend::events()`.
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum MockEvent<K> {
+	/// A key was acquired.
+	Acquire(K),
+	/// A key was released.
+	Release(K),
+	/// A key was removed.
+	Remove(K),
+}
+
+struct MockState<K> {
+	// Keys that have been acquired and not removed since
+	known: HashSet<K>,
+	held: HashSet<K>,
+	poisoned: HashSet<K>,
+	fail_next: Option<io::Error>,
+	events: Vec<MockEvent<K>>,
+}
+
+/// An in-memory `LockBackend` for tests, which records every successful
+/// call and can simulate failures.
+pub struct MockBackend<K: Eq + Hash> {
+	// The condition variable is signalled whenever a key is released
+	state: Arc<(sync::Mutex<MockState<K>>,Condvar)>,
+}
+
+// This needs to be implemented manually, since #[derive(Clone)] doesn't
+// understand that the type parameters are only used within the Arc<_>
+impl<K: Eq + Hash> Clone for MockBackend<K> {
+	fn clone(&self) -> MockBackend<K> {
+		MockBackend{state:self.state.clone()}
+	}
+}
+
+impl<K: Eq + Hash + Clone> Default for MockBackend<K> {
+	fn default() -> MockBackend<K> {
+		Self::new()
+	}
+}
+
+impl<K: Eq + Hash + Clone> MockBackend<K> {
+	/// Create a new MockBackend.
+	pub fn new() -> MockBackend<K> {
+		MockBackend{state:Arc::new((sync::Mutex::new(MockState{
+			known:HashSet::new(),
+			held:HashSet::new(),
+			poisoned:HashSet::new(),
+			fail_next:None,
+			events:Vec::new(),
+		}),Condvar::new()))}
+	}
+
+	fn state<'a>(&'a self) -> sync::MutexGuard<'a,MockState<K>> {
+		self.state.0.lock().unwrap_or_else(sync::PoisonError::into_inner)
+	}
+
+	/// The calls so far that succeeded, in order. Releases are recorded
+	/// whenever a guard is dropped.
+	pub fn events(&self) -> Vec<MockEvent<K>> {
+		self.state().events.clone()
+	}
+
+	/// Whether `key` is currently held.
+	pub fn is_held(&self, key: &K) -> bool {
+		self.state().held.contains(key)
+	}
+
+	/// Make the next call of `acquire()`, `try_acquire()` or `remove()` fail
+	/// with `err`.
+	pub fn fail_next(&self, err: io::Error) {
+		self.state().fail_next=Some(err);
+	}
+
+	/// Simulate a holder of `key` that died, so that it's poisoned until
+	/// `clear_poison()` is called.
+	pub fn poison(&self, key: K) {
+		self.state().poisoned.insert(key);
+	}
+
+	/// Clear the poisoned state of `key`.
+	pub fn clear_poison(&self, key: &K) {
+		self.state().poisoned.remove(key);
+	}
+
+	fn acquire_mock<'a>(&'a self, key: K, wait: bool) -> BackendResult<MockGuard<'a,K>,MockGuard<'a,K>> {
+		let mut state=self.state();
+		if let Some(err)=state.fail_next.take() {
+			return Err(BackendError::Failed(err));
+		}
+		while state.held.contains(&key) {
+			if !wait {
+				return Err(BackendError::WouldBlock);
+			}
+			state=self.state.1.wait(state).unwrap_or_else(sync::PoisonError::into_inner);
+		}
+		state.known.insert(key.clone());
+		state.held.insert(key.clone());
+		state.events.push(MockEvent::Acquire(key.clone()));
+		let poisoned=state.poisoned.contains(&key);
+		let guard=MockGuard{owner:self,key};
+		match poisoned {
+			false => Ok(guard),
+			true => Err(BackendError::Poisoned(PoisonError::new(guard))),
+		}
+	}
+}
+
+/// The guard of a key acquired from a `MockBackend`.
+pub struct MockGuard<'a,K: 'a + Eq + Hash + Clone> {
+	owner: &'a MockBackend<K>,
+	key: K,
+}
+
+impl<'a,K: Eq + Hash + Clone> MockGuard<'a,K> {
+	/// The key this guard was acquired for.
+	pub fn key(&self) -> &K {
+		&self.key
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone> Drop for MockGuard<'a,K> {
+	fn drop(&mut self) {
+		let mut state=self.owner.state();
+		state.held.remove(&self.key);
+		state.events.push(MockEvent::Release(self.key.clone()));
+		self.owner.state.1.notify_all();
+	}
+}
+
+impl<K: Eq + Hash + Clone> LockBackend<K> for MockBackend<K> {
+	type Guard<'a> = MockGuard<'a,K> where Self: 'a;
+
+	fn acquire<'a>(&'a self, key: K) -> BackendResult<MockGuard<'a,K>,MockGuard<'a,K>> {
+		self.acquire_mock(key,true)
+	}
+
+	fn try_acquire<'a>(&'a self, key: K) -> BackendResult<MockGuard<'a,K>,MockGuard<'a,K>> {
+		self.acquire_mock(key,false)
+	}
+
+	fn remove(&self, key: K) -> BackendResult<bool> {
+		let mut state=self.state();
+		if let Some(err)=state.fail_next.take() {
+			return Err(BackendError::Failed(err));
+		}
+		if state.held.contains(&key) {
+			return Err(BackendError::WouldBlock);
+		}
+		let found=state.known.remove(&key);
+		state.poisoned.remove(&key);
+		state.events.push(MockEvent::Remove(key));
+		Ok(found)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	#[cfg(all(feature="file",unix))] use std::env;
+	use std::fs;
+	#[cfg(any(feature="file",feature="shm"))] use std::process;
+	use std::thread;
+	use std::time::Duration;
+	use super::*;
+	use KeepUnused;
+	#[cfg(unix)] use testutil::start_server;
+
+	// Exercises a backend the way generic code would
+	fn exercise<B: LockBackend<String>>(backend: &B) {
+		let key=|s: &str|s.to_owned();
+		let guard=match backend.acquire(key("a")) {
+			Ok(guard) => guard,
+			Err(err) => panic!("{:?}",err),
+		};
+		assert!(matches!(backend.try_acquire(key("a")),Err(BackendError::WouldBlock)));
+		assert!(matches!(backend.remove(key("a")),Err(BackendError::WouldBlock)));
+		assert!(backend.try_acquire(key("b")).is_ok());
+		backend.release(guard);
+		assert!(backend.try_acquire(key("a")).is_ok());
+		assert!(backend.remove(key("a")).unwrap());
+		assert!(!backend.remove(key("a")).unwrap());
+	}
+
+	#[test]
+	fn lock_space() {
+		exercise(&LockSpace::<String,i32>::new(KeepUnused));
+	}
+
+	#[test]
+	#[cfg(all(feature="file",unix))]
+	fn file_lock_space() {
+		let dir=env::temp_dir().join(format!("namedlock-{}-backend",process::id()));
+		exercise(&FileLockSpace::<String>::new(&dir,KeepUnused).unwrap());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	#[cfg(all(feature="shm",target_os="linux"))]
+	fn shm_lock_space() {
+		let name=format!("/namedlock-test-{}-backend",process::id());
+		let _=shmlockspace::unlink(&name);
+		exercise(&ShmLockSpace::<String>::open(&name,16,8).unwrap());
+		shmlockspace::unlink(&name).unwrap();
+	}
+
+	#[test]
+	#[cfg(unix)]
+	fn remote_lock_space() {
+		let path=start_server("backend",KeepUnused);
+		exercise(&RemoteLockSpace::connect(&path).unwrap());
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn mock() {
+		let mock=MockBackend::new();
+		exercise(&mock);
+		let key=|s: &str|s.to_owned();
+		assert_eq!(mock.events(),[
+			MockEvent::Acquire(key("a")),
+			MockEvent::Acquire(key("b")),
+			MockEvent::Release(key("b")),
+			MockEvent::Release(key("a")),
+			MockEvent::Acquire(key("a")),
+			MockEvent::Release(key("a")),
+			MockEvent::Remove(key("a")),
+			MockEvent::Remove(key("a")),
+		]);
+
+		mock.fail_next(io::ErrorKind::PermissionDenied.into());
+		match mock.try_acquire(key("a")) {
+			Err(BackendError::Failed(err)) => assert_eq!(err.kind(),io::ErrorKind::PermissionDenied),
+			_ => panic!("The call must fail"),
+		}
+		mock.poison(key("a"));
+		match mock.acquire(key("a")) {
+			Err(BackendError::Poisoned(err)) => assert_eq!(err.into_inner().key(),"a"),
+			_ => panic!("The key must be poisoned"),
+		}
+		mock.clear_poison(&key("a"));
+		assert!(mock.acquire(key("a")).is_ok());
+	}
+
+	#[test]
+	fn mock_blocks() {
+		let mock=MockBackend::new();
+		let guard=mock.acquire("a".to_owned()).unwrap();
+		let mock_clone=mock.clone();
+		let waiter=thread::spawn(move||mock_clone.acquire("a".to_owned()).is_ok());
+		thread::sleep(Duration::from_millis(50));
+		assert!(!waiter.is_finished());
+		assert!(mock.is_held(&"a".to_owned()));
+		drop(guard);
+		assert!(waiter.join().unwrap());
+	}
+}
@@ -0,0 +1,74 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! A lock daemon serving a `LockSpace<String,()>` on a Unix domain socket.
+//!
+//! Usage: `namedlock-server [--keep-unused] <socket path>`
+//!
+//! By default, the space is of the `AutoCleanup` kind, since keys are
+//! arbitrary strings chosen by clients, and keeping every key that was ever
+//! locked would grow without bound. With `--keep-unused`, it's of the
+//! `KeepUnused` kind, and clients remove keys with `REMOVE`.
+//!
+//! See the `namedlock::remote` module for the protocol.
+
+extern crate namedlock;
+
+use std::process;
+
+#[cfg(unix)]
+fn run() -> Result<(),String> {
+	use std::env;
+	use std::fs;
+	use std::os::unix::fs::FileTypeExt;
+	use std::os::unix::net::{UnixListener,UnixStream};
+	use std::path::PathBuf;
+	use namedlock::{AutoCleanup,KeepUnused,LockServer};
+
+	let usage="Usage: namedlock-server [--keep-unused] <socket path>";
+	let mut cleanup=AutoCleanup;
+	let mut path=None;
+	for arg in env::args_os().skip(1) {
+		if arg=="--keep-unused" {
+			cleanup=KeepUnused;
+		} else if path.is_none() {
+			path=Some(PathBuf::from(arg));
+		} else {
+			return Err(usage.to_owned());
+		}
+	}
+	let path=path.ok_or_else(||usage.to_owned())?;
+	// Replace a socket left behind by a server that's no longer running
+	let is_socket=fs::symlink_metadata(&path).map(|meta|meta.file_type().is_socket()).unwrap_or(false);
+	if is_socket && UnixStream::connect(&path).is_err() {
+		let _=fs::remove_file(&path);
+	}
+	let listener=UnixListener::bind(&path).map_err(|err|format!("Can't listen on {}: {}",path.display(),err))?;
+	LockServer::new(cleanup).serve(&listener,|err|eprintln!("namedlock-server: Accepting a connection failed: {}",err))
+}
+
+#[cfg(not(unix))]
+fn run() -> Result<(),String> {
+	Err("namedlock-server requires Unix domain sockets".to_owned())
+}
+
+fn main() {
+	if let Err(err)=run() {
+		eprintln!("{}",err);
+		process::exit(1);
+	}
+}
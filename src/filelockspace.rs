@@ -0,0 +1,346 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Named locks that are shared between processes through lock files.
+//!
+//! A `FileLockSpace` maps every key to a lock file in a directory. Locking a
+//! key first locks it in a `LockSpace`, which excludes the other threads of
+//! this process, and then locks its file with `flock`, which excludes other
+//! processes using the same directory.
+//!
+//! ```
+//! use namedlock::{FileLockSpace,AutoCleanup};
+//! use namedlock::filelockspace::TryLockError;
+//!
+//! let dir=std::env::temp_dir().join("namedlock-doctest");
+//! let space=FileLockSpace::<String>::new(&dir,AutoCleanup).unwrap();
+//!
+//! let guard=space.lock("worker/queue".to_owned()).unwrap();
+//! assert_eq!(guard.path(),dir.join("worker%2Fqueue.lock"));
+//! match space.try_lock("worker/queue".to_owned()) {
+//!     Err(TryLockError::WouldBlock) => {},
+//!     _ => panic!("The key is locked"),
+//! }
+//! drop(guard);
+//! assert!(!dir.join("worker%2Fqueue.lock").exists());
+//! ```
+
+use std::fmt::Write;
+use std::fs::{self,File,OpenOptions};
+use std::hash::Hash;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path,PathBuf};
+use std::sync::Arc;
+
+use libc;
+
+use lockresult::PoisonError;
+use lockresult::TryLockError as SpaceTryLockError;
+use {LockSpace,LockSpaceGuard,Cleanup,AutoCleanup,fnv1a};
+
+/// An error returned by `FileLockSpace::try_lock()`.
+#[derive(Debug)]
+pub enum TryLockError {
+	/// The key is locked by another thread or process.
+	WouldBlock,
+	/// The lock file couldn't be opened or locked.
+	Io(io::Error),
+}
+
+impl From<io::Error> for TryLockError {
+	fn from(err: io::Error) -> TryLockError {
+		TryLockError::Io(err)
+	}
+}
+
+/// An RAII implementation of a "scoped lock" of a key in a FileLockSpace.
+/// When this structure is dropped (falls out of scope), the lock file is
+/// unlocked, and deleted if `AutoCleanup` is specified for the space. Then,
+/// the key is unlocked for the other threads of this process.
+pub struct FileLockGuard<'a,K: 'a + Eq + Hash + Clone> {
+	cleanup: Cleanup,
+	path: PathBuf,
+	file: File,
+	// Dropped after the lock file has been unlocked
+	_guard: LockSpaceGuard<'a,K,()>,
+}
+
+impl<'a,K: Eq + Hash + Clone> FileLockGuard<'a,K> {
+	/// The lock file of the key this guard was locked for.
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone> Drop for FileLockGuard<'a,K> {
+	fn drop(&mut self) {
+		// Delete the file while it's still locked. Other processes that
+		// opened it already notice that it's gone once they've locked it.
+		if self.cleanup==AutoCleanup {
+			let _=fs::remove_file(&self.path);
+		}
+		// Ignore errors on drop here. Closing the file unlocks it anyway.
+		unsafe {
+			libc::flock(self.file.as_raw_fd(),libc::LOCK_UN);
+		}
+	}
+}
+
+/// A `FileLockSpace<K>` is a namespace for locks that exclude both the
+/// threads of this process and other processes using the same directory.
+///
+/// Every key has a lock file in the directory, named after the key. Bytes of
+/// the key other than ASCII letters, digits, `-`, `_` and `.` are
+/// percent-encoded, and long keys are shortened and end in a hash of the
+/// whole key instead.
+///
+/// With `AutoCleanup`, lock files are deleted when they are unlocked, and
+/// created again by the next locker. With `KeepUnused`, they stay in the
+/// directory.
+pub struct FileLockSpace<K: Eq + Hash> {
+	space: LockSpace<K,()>,
+	dir: Arc<PathBuf>,
+	cleanup: Cleanup,
+}
+
+// This needs to be implemented manually, since #[derive(Clone)] doesn't
+// understand that the type parameters are only used within the Arc<_>
+impl<K: Eq + Hash> Clone for FileLockSpace<K> {
+	fn clone(&self) -> FileLockSpace<K> {
+		FileLockSpace{space:self.space.clone(),dir:self.dir.clone(),cleanup:self.cleanup}
+	}
+}
+
+impl<K: Eq + Hash + Clone + AsRef<[u8]>> FileLockSpace<K> {
+	/// Create a new FileLockSpace with its lock files in `dir`, which is
+	/// created if it doesn't exist yet.
+	pub fn new<P: AsRef<Path>>(dir: P, cleanup: Cleanup) -> io::Result<FileLockSpace<K>> {
+		fs::create_dir_all(&dir)?;
+		Ok(FileLockSpace{
+			space:LockSpace::new(cleanup),
+			dir:Arc::new(dir.as_ref().to_path_buf()),
+			cleanup,
+		})
+	}
+
+	/// The lock file of `key`.
+	pub fn path(&self, key: &K) -> PathBuf {
+		self.dir.join(file_name(key.as_ref()))
+	}
+
+	/// Lock `key`, blocking the current thread until no other thread or
+	/// process holds it, and return a FileLockGuard over it.
+	pub fn lock<'a>(&'a self, key: K) -> io::Result<FileLockGuard<'a,K>> {
+		self.acquire(key,true,true).map_err(|err|match err {
+			TryLockError::Io(err) => err,
+			// We only give up if we're not supposed to wait
+			TryLockError::WouldBlock => unreachable!(),
+		})
+	}
+
+	/// Lock `key` without blocking, and return a FileLockGuard over it.
+	///
+	/// If another thread or process holds `key`, `WouldBlock` is returned.
+	pub fn try_lock<'a>(&'a self, key: K) -> Result<FileLockGuard<'a,K>,TryLockError> {
+		self.acquire(key,false,true)
+	}
+
+	/// Delete the lock file of `key` if it is not locked, and return whether
+	/// there was one. If it is locked, `WouldBlock` is returned.
+	///
+	/// This is only useful if this `FileLockSpace` is of the `KeepUnused`
+	/// kind.
+	pub fn try_remove(&self, key: K) -> Result<bool,TryLockError> {
+		// Don't create the file, it might just have been removed concurrently
+		let guard=match self.acquire(key.clone(),false,false) {
+			Ok(guard) => guard,
+			Err(TryLockError::Io(ref err)) if err.kind()==io::ErrorKind::NotFound => return Ok(false),
+			Err(err) => return Err(err),
+		};
+		let result=fs::remove_file(&guard.path);
+		drop(guard);
+		self.space.try_remove(key);
+		result.map(|()|true).map_err(TryLockError::Io)
+	}
+
+	fn acquire<'a>(&'a self, key: K, wait: bool, create: bool) -> Result<FileLockGuard<'a,K>,TryLockError> {
+		let path=self.path(&key);
+		// There's no value that a panic could have left inconsistent, so
+		// poison is ignored
+		let guard=if wait {
+			self.space.lock(key,||()).unwrap_or_else(PoisonError::into_inner)
+		} else {
+			match self.space.try_lock(key,||()) {
+				Ok(guard) => guard,
+				Err(SpaceTryLockError::Poisoned(err)) => err.into_inner(),
+				Err(_) => return Err(TryLockError::WouldBlock),
+			}
+		};
+
+		loop {
+			let file=OpenOptions::new().read(true).write(true).create(create).truncate(false).open(&path)?;
+			lock_file(&file,wait)?;
+			// The previous holder may have deleted the file after we opened
+			// it. Then, someone else can lock a new file with the same name,
+			// so try again.
+			if same_file(&file,&path)? {
+				return Ok(FileLockGuard{cleanup:self.cleanup,path,file,_guard:guard});
+			}
+		}
+	}
+}
+
+// Lock `file` exclusively. The lock is released when the file is closed.
+fn lock_file(file: &File, wait: bool) -> Result<(),TryLockError> {
+	let operation=if wait { libc::LOCK_EX } else { libc::LOCK_EX|libc::LOCK_NB };
+	loop {
+		if unsafe { libc::flock(file.as_raw_fd(),operation) }==0 {
+			return Ok(());
+		}
+		let err=io::Error::last_os_error();
+		match err.raw_os_error() {
+			Some(libc::EINTR) => {},
+			Some(libc::EWOULDBLOCK) if !wait => return Err(TryLockError::WouldBlock),
+			_ => return Err(err.into()),
+		}
+	}
+}
+
+// Whether `path` still refers to `file`
+fn same_file(file: &File, path: &Path) -> io::Result<bool> {
+	let opened=file.metadata()?;
+	match fs::metadata(path) {
+		Ok(current) => Ok(current.dev()==opened.dev() && current.ino()==opened.ino()),
+		Err(ref err) if err.kind()==io::ErrorKind::NotFound => Ok(false),
+		Err(err) => Err(err),
+	}
+}
+
+// Longer file names are shortened, staying well below the limit of common
+// filesystems
+const MAX_NAME: usize = 200;
+
+// The name of the lock file of `key`. The `+` before the hash of a shortened
+// name can't occur in an encoded key, so the names of short keys and
+// shortened keys can't be the same.
+fn file_name(key: &[u8]) -> String {
+	let mut name=String::new();
+	for &b in key {
+		match b {
+			b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' => name.push(b as char),
+			_ => { let _=write!(name,"%{:02X}",b); },
+		}
+	}
+	if name.len()>MAX_NAME {
+		name.truncate(MAX_NAME-17);
+		let _=write!(name,"+{:016x}",fnv1a(key));
+	}
+	// The suffix also keeps keys like `..` from naming a directory
+	name.push_str(".lock");
+	name
+}
+
+#[cfg(test)]
+mod tests {
+	use std::env;
+	use std::process;
+	use std::thread;
+	use std::time::Duration;
+	use super::*;
+	use KeepUnused;
+
+	fn temp_dir(name: &str) -> PathBuf {
+		let dir=env::temp_dir().join(format!("namedlock-{}-{}",process::id(),name));
+		let _=fs::remove_dir_all(&dir);
+		dir
+	}
+
+	#[test]
+	fn file_names() {
+		assert_eq!(file_name(b"queue-1.a_b"),"queue-1.a_b.lock");
+		assert_eq!(file_name(b"../etc/passwd"),"..%2Fetc%2Fpasswd.lock");
+		assert_eq!(file_name(b".."),"...lock");
+		assert_eq!(file_name(b"a+b \xff"),"a%2Bb%20%FF.lock");
+
+		let long=file_name(&[b'x';300]);
+		assert_eq!(long.len(),MAX_NAME+5);
+		assert!(long.starts_with("xxx"));
+		assert_ne!(long,file_name(&[b'x';301]));
+	}
+
+	#[test]
+	fn excludes_other_files() {
+		let dir=temp_dir("excludes");
+		let space=FileLockSpace::<String>::new(&dir,AutoCleanup).unwrap();
+		let guard=space.lock("test".to_owned()).unwrap();
+		assert_eq!(guard.path(),space.path(&"test".to_owned()));
+
+		// Like another process, a separate handle to the file can't lock it
+		let other=File::open(guard.path()).unwrap();
+		assert!(matches!(lock_file(&other,false),Err(TryLockError::WouldBlock)));
+		assert!(matches!(space.try_lock("test".to_owned()),Err(TryLockError::WouldBlock)));
+		assert!(space.try_lock("other".to_owned()).is_ok());
+
+		drop(guard);
+		assert!(!space.path(&"test".to_owned()).exists());
+		lock_file(&other,false).unwrap();
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn keep_unused() {
+		let dir=temp_dir("keep");
+		let space=FileLockSpace::<Vec<u8>>::new(&dir,KeepUnused).unwrap();
+		drop(space.lock(b"test".to_vec()).unwrap());
+		assert!(dir.join("test.lock").exists());
+		let guard=space.try_lock(b"test".to_vec()).unwrap();
+		assert!(matches!(space.try_remove(b"test".to_vec()),Err(TryLockError::WouldBlock)));
+		drop(guard);
+		assert!(space.try_remove(b"test".to_vec()).unwrap());
+		assert!(!dir.join("test.lock").exists());
+		assert!(!space.try_remove(b"test".to_vec()).unwrap());
+		assert!(!dir.join("test.lock").exists());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	#[test]
+	fn deleted_while_waiting() {
+		// Separate spaces don't share their in-process locks, so these
+		// behave like different processes
+		let dir=temp_dir("deleted");
+		let first=FileLockSpace::<String>::new(&dir,AutoCleanup).unwrap();
+		let second=FileLockSpace::<String>::new(&dir,AutoCleanup).unwrap();
+		let guard=first.lock("test".to_owned()).unwrap();
+
+		let waiter=thread::spawn(move||{
+			let guard=second.lock("test".to_owned()).unwrap();
+			// The waiter must have locked the file that's there now
+			let current=File::open(guard.path()).unwrap();
+			assert!(matches!(lock_file(&current,false),Err(TryLockError::WouldBlock)));
+		});
+		thread::sleep(Duration::from_millis(50));
+		assert!(!waiter.is_finished());
+
+		// Deletes the file the waiter is waiting on
+		drop(guard);
+		waiter.join().unwrap();
+		assert!(!first.path(&"test".to_owned()).exists());
+		fs::remove_dir_all(&dir).unwrap();
+	}
+}
@@ -0,0 +1,478 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Multi-granularity locking of hierarchical keys.
+//!
+//! A `HierarchicalLockSpace` locks keys like `tenant/bucket/object` in one of
+//! the five modes of `LockMode`. Locking a key implicitly covers all keys
+//! below it, so an exclusive lock on `tenant/bucket` excludes any lock on
+//! `tenant/bucket/object`, and the other way around. To make this work, the
+//! matching intention mode is automatically locked on every ancestor of the
+//! key first, starting at the root.
+//!
+//! ```
+//! use namedlock::{HierarchicalLockSpace,LockMode};
+//! use namedlock::lockresult::TryLockError;
+//!
+//! let space=HierarchicalLockSpace::<String>::new();
+//!
+//! let object=space.lock("tenant/bucket/object".to_owned(),LockMode::Exclusive).unwrap();
+//! // Other objects in the same bucket can still be locked
+//! assert!(space.try_lock("tenant/bucket/other".to_owned(),LockMode::Exclusive).is_ok());
+//! // But not the bucket as a whole
+//! match space.try_lock("tenant/bucket".to_owned(),LockMode::Shared) {
+//!     Err(TryLockError::WouldBlock) => {},
+//!     _ => panic!("The object in the bucket is locked"),
+//! }
+//! drop(object);
+//! ```
+
+use std::hash::Hash;
+use std::path::PathBuf;
+#[cfg(feature="spin")] use std::thread;
+
+use lockresult::LockResult as Result;
+use lockresult::{PoisonError,TryLockError,TryLockResult};
+use {LockSpace,LockSpaceGuard,KeepUnused};
+
+/// A key that is part of a hierarchy, like a path.
+pub trait HierarchicalKey: Sized {
+	/// Returns the key directly above this one in the hierarchy, or `None`
+	/// if this is a root.
+	fn parent(&self) -> Option<Self>;
+}
+
+/// Keys are split into components on `/`.
+impl HierarchicalKey for String {
+	fn parent(&self) -> Option<String> {
+		self.rfind('/').map(|i|self[..i].to_owned())
+	}
+}
+
+/// Every element is a path component.
+impl<T: Clone> HierarchicalKey for Vec<T> {
+	fn parent(&self) -> Option<Vec<T>> {
+		match self.len() {
+			0 | 1 => None,
+			n => Some(self[..n-1].to_vec()),
+		}
+	}
+}
+
+impl HierarchicalKey for PathBuf {
+	fn parent(&self) -> Option<PathBuf> {
+		// The parent of a relative path with one component is empty
+		self.as_path().parent().filter(|p|!p.as_os_str().is_empty()).map(|p|p.to_path_buf())
+	}
+}
+
+/// The lock modes of a `HierarchicalLockSpace`.
+///
+/// Two modes are compatible if they can be held on the same key at the same
+/// time:
+///
+/// |     | IS  | IX  | S   | SIX | X   |
+/// |-----|-----|-----|-----|-----|-----|
+/// | IS  | yes | yes | yes | yes | no  |
+/// | IX  | yes | yes | no  | no  | no  |
+/// | S   | yes | no  | yes | no  | no  |
+/// | SIX | yes | no  | no  | no  | no  |
+/// | X   | no  | no  | no  | no  | no  |
+#[derive(PartialEq,Eq,Clone,Copy,Debug)]
+pub enum LockMode {
+	/// IS: Some key below this one will be locked in `Shared` mode.
+	IntentionShared,
+	/// IX: Some key below this one will be locked in `Exclusive` mode.
+	IntentionExclusive,
+	/// S: Shared access to this key and all keys below it.
+	Shared,
+	/// SIX: Like `Shared` and `IntentionExclusive` at the same time.
+	SharedIntentionExclusive,
+	/// X: Exclusive access to this key and all keys below it.
+	Exclusive,
+}
+
+const MODES: [LockMode;5] = [
+	LockMode::IntentionShared,
+	LockMode::IntentionExclusive,
+	LockMode::Shared,
+	LockMode::SharedIntentionExclusive,
+	LockMode::Exclusive,
+];
+
+impl LockMode {
+	/// Whether this mode can be held on a key at the same time as `other`.
+	pub fn compatible_with(self, other: LockMode) -> bool {
+		use self::LockMode::*;
+		match (self,other) {
+			(Exclusive,_) | (_,Exclusive) => false,
+			(IntentionShared,_) | (_,IntentionShared) => true,
+			(IntentionExclusive,IntentionExclusive) => true,
+			(Shared,Shared) => true,
+			_ => false,
+		}
+	}
+
+	/// The mode that is locked on the ancestors of a key that is locked in
+	/// this mode.
+	pub fn intention(self) -> LockMode {
+		match self {
+			LockMode::IntentionShared | LockMode::Shared => LockMode::IntentionShared,
+			_ => LockMode::IntentionExclusive,
+		}
+	}
+
+	fn index(self) -> usize {
+		self as usize
+	}
+}
+
+// The state of a single key: the number of holders of each mode
+struct Node {
+	held: [usize;5],
+}
+
+impl Node {
+	fn new() -> Node {
+		Node{held:[0;5]}
+	}
+
+	fn admits(&self, mode: LockMode) -> bool {
+		MODES.iter().all(|&other|self.held[other.index()]==0 || mode.compatible_with(other))
+	}
+
+	fn unused(&self) -> bool {
+		self.held.iter().all(|&n|n==0)
+	}
+}
+
+/// An RAII implementation of a "scoped lock" of a key in a
+/// HierarchicalLockSpace, including the intention locks on its ancestors. When
+/// this structure is dropped (falls out of scope), all those locks will be
+/// released.
+pub struct HierarchicalGuard<'a,K: 'a + Eq + Hash + Clone> {
+	owner: &'a HierarchicalLockSpace<K>,
+	// From the root down to the key itself
+	held: Vec<(K,LockMode)>,
+}
+
+impl<'a,K: Eq + Hash + Clone> HierarchicalGuard<'a,K> {
+	/// The key this guard was locked for.
+	pub fn key(&self) -> &K {
+		// There's always at least the key itself
+		&self.held[self.held.len()-1].0
+	}
+
+	/// The mode this guard's key was locked in.
+	pub fn mode(&self) -> LockMode {
+		self.held[self.held.len()-1].1
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone> Drop for HierarchicalGuard<'a,K> {
+	fn drop(&mut self) {
+		// Release from the key itself up to the root
+		while let Some((key,mode))=self.held.pop() {
+			self.owner.release(key,mode);
+		}
+	}
+}
+
+/// A `HierarchicalLockSpace<K>` is a lock manager for keys that form a
+/// hierarchy, supporting the multi-granularity lock modes of `LockMode`.
+///
+/// The lock state of each key is kept in a `LockSpace`, which cleans up keys
+/// that aren't locked anymore.
+///
+/// Locks are not associated with a thread, so locking a key below a key the
+/// current thread already holds is subject to the same compatibility rules as
+/// for any other thread. For example, with an `Exclusive` lock on
+/// `tenant/bucket`, locking `tenant/bucket/object` blocks forever.
+pub struct HierarchicalLockSpace<K: Eq + Hash> {
+	// Blocked lockers wait on the condition variable of the key they want to
+	// lock, which is signalled whenever a lock of that key is released.
+	nodes: LockSpace<K,Node>,
+}
+
+// This needs to be implemented manually, since #[derive(Clone)] doesn't
+// understand that the type parameters are only used within the Arc<_>
+impl<K: Eq + Hash> Clone for HierarchicalLockSpace<K> {
+	fn clone(&self) -> HierarchicalLockSpace<K> {
+		HierarchicalLockSpace{nodes:self.nodes.clone()}
+	}
+}
+
+impl<K: Eq + Hash + Clone + HierarchicalKey> Default for HierarchicalLockSpace<K> {
+	fn default() -> HierarchicalLockSpace<K> {
+		Self::new()
+	}
+}
+
+impl<K: Eq + Hash + Clone + HierarchicalKey> HierarchicalLockSpace<K> {
+	/// Create a new HierarchicalLockSpace.
+	pub fn new() -> HierarchicalLockSpace<K> {
+		HierarchicalLockSpace{nodes:LockSpace::new(KeepUnused)}
+	}
+
+	/// Lock `key` in `mode`, blocking the current thread until that's
+	/// possible, and return a HierarchicalGuard over it.
+	///
+	/// All ancestors of `key` are locked in the `intention()` of `mode` first,
+	/// from the root down. Keys are created when they are first locked.
+	pub fn lock<'a>(&'a self, key: K, mode: LockMode) -> Result<HierarchicalGuard<'a,K>> {
+		self.acquire(key,mode,true).map_err(|err|match err {
+			TryLockError::Poisoned(err) => err,
+			// We only give up if we're not supposed to wait
+			_ => unreachable!(),
+		})
+	}
+
+	/// Lock `key` in `mode` without blocking, and return a HierarchicalGuard
+	/// over it.
+	///
+	/// If `key` or any of its ancestors is currently locked in an
+	/// incompatible mode, `WouldBlock` is returned.
+	pub fn try_lock<'a>(&'a self, key: K, mode: LockMode) -> TryLockResult<HierarchicalGuard<'a,K>> {
+		self.acquire(key,mode,false)
+	}
+
+	fn acquire<'a>(&'a self, key: K, mode: LockMode, wait: bool) -> TryLockResult<HierarchicalGuard<'a,K>> {
+		let mut path=vec![(key,mode)];
+		while let Some(parent)=path[path.len()-1].0.parent() {
+			path.push((parent,mode.intention()));
+		}
+
+		// If anything fails, the locks acquired so far are released by the
+		// guard
+		let mut guard=HierarchicalGuard{owner:self,held:Vec::with_capacity(path.len())};
+		while let Some((key,mode))=path.pop() {
+			self.acquire_node(&key,mode,wait)?;
+			guard.held.push((key,mode));
+		}
+		Ok(guard)
+	}
+}
+
+// These don't need the HierarchicalKey bound, so that the guard can use them
+impl<K: Eq + Hash + Clone> HierarchicalLockSpace<K> {
+	// If the node is poisoned, the guard is still returned in the error. The
+	// guard doesn't poison the node: the counts are only changed by single
+	// statements, so a panic can't leave them inconsistent, and a panicking
+	// holder still needs to release its locks on the whole path.
+	fn node<'a>(&'a self, key: &K) -> Result<LockSpaceGuard<'a,K,Node>,LockSpaceGuard<'a,K,Node>> {
+		let unpoisoned=|mut node: LockSpaceGuard<'a,K,Node>|{
+			node.never_poison();
+			node
+		};
+		match self.nodes.lock(key.clone(),Node::new) {
+			Ok(node) => Ok(unpoisoned(node)),
+			Err(err) => Err(err.map_guard(unpoisoned)),
+		}
+	}
+
+	fn acquire_node(&self, key: &K, mode: LockMode, wait: bool) -> TryLockResult<()> {
+		let mut node=self.node(key).map_err(|err|err.map_guard(drop))?;
+		if !node.admits(mode) {
+			if !wait {
+				drop(node);
+				self.remove_if_unused(key);
+				return Err(TryLockError::WouldBlock);
+			}
+			// Only releases of this key wake us up. Our guard keeps the node
+			// from being removed in the meantime.
+			#[cfg(not(feature="spin"))]
+			node.wait_while(|node|!node.admits(mode))?;
+			// spin has no condition variables, so just back off
+			#[cfg(feature="spin")]
+			while !node.admits(mode) {
+				drop(node);
+				thread::yield_now();
+				node=self.node(key).map_err(|err|err.map_guard(drop))?;
+			}
+		}
+		node.held[mode.index()]+=1;
+		Ok(())
+	}
+
+	fn release(&self, key: K, mode: LockMode) {
+		// Ignore poison error on drop here. The count must be decremented
+		// regardless, or conflicting requests would wait forever.
+		let mut node=self.node(&key).unwrap_or_else(PoisonError::into_inner);
+		node.held[mode.index()]-=1;
+		#[cfg(not(feature="spin"))]
+		node.notify_all();
+		drop(node);
+		self.remove_if_unused(&key);
+	}
+
+	fn remove_if_unused(&self, key: &K) {
+		self.nodes.try_remove_if(key,Node::unused);
+	}
+
+	// Returns the number of holders of each mode of `key`
+	#[cfg(test)]
+	fn held(&self, key: &K) -> [usize;5] {
+		self.node(key).unwrap().held
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::thread;
+	use std::time::Duration;
+	use super::*;
+	use super::LockMode::*;
+	use LockSpaceRemoveResult;
+
+	// The expected compatibility of a held mode (row) and a requested mode
+	// (column), in the order of MODES
+	const MATRIX: [[bool;5];5] = [
+		//   IS    IX     S      SIX    X
+		[true, true,  true,  true,  false], // IS
+		[true, true,  false, false, false], // IX
+		[true, false, true,  false, false], // S
+		[true, false, false, false, false], // SIX
+		[false,false, false, false, false], // X
+	];
+
+	fn key(s: &str) -> String {
+		s.to_owned()
+	}
+
+	#[test]
+	fn compatibility_matrix() {
+		for (i,&held) in MODES.iter().enumerate() {
+			for (j,&requested) in MODES.iter().enumerate() {
+				assert_eq!(held.compatible_with(requested),MATRIX[i][j],"{:?} vs {:?}",held,requested);
+
+				// The same key
+				let space=HierarchicalLockSpace::<String>::new();
+				let guard=space.lock(key("a/b"),held).unwrap();
+				let result=space.try_lock(key("a/b"),requested);
+				assert_eq!(result.is_ok(),MATRIX[i][j],"{:?} then {:?}",held,requested);
+				if !MATRIX[i][j] {
+					assert!(matches!(result,Err(TryLockError::WouldBlock)));
+				}
+				drop(result);
+				drop(guard);
+				assert!(space.try_lock(key("a/b"),Exclusive).is_ok());
+			}
+		}
+	}
+
+	#[test]
+	fn compatibility_matrix_descendants() {
+		// A lock on a key conflicts with locks below it as determined by the
+		// intention mode on the key
+		for (i,&held) in MODES.iter().enumerate() {
+			for &requested in MODES.iter() {
+				let j=requested.intention().index();
+				for &(first,second) in &[("a","a/b/c"),("a/b/c","a")] {
+					let (first_mode,second_mode)=if first=="a" { (held,requested) } else { (requested,held) };
+					let space=HierarchicalLockSpace::<String>::new();
+					let guard=space.lock(key(first),first_mode).unwrap();
+					let result=space.try_lock(key(second),second_mode);
+					assert_eq!(result.is_ok(),MATRIX[i][j],"{:?} on a, then {:?} on a/b/c",held,requested);
+					drop(result);
+					drop(guard);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn siblings_independent() {
+		let space=HierarchicalLockSpace::<String>::new();
+		let a=space.lock(key("t/b/x"),Exclusive).unwrap();
+		let b=space.lock(key("t/b/y"),Exclusive).unwrap();
+		let c=space.lock(key("t/c"),Shared).unwrap();
+		assert_eq!(space.held(&key("t")),[1,2,0,0,0]);
+		assert_eq!(space.held(&key("t/b")),[0,2,0,0,0]);
+		assert_eq!(a.key(),"t/b/x");
+		assert_eq!(c.mode(),Shared);
+		drop((a,b,c));
+		assert!(matches!(space.nodes.try_remove(key("t")),LockSpaceRemoveResult::NotFound));
+	}
+
+	#[test]
+	fn blocks_until_released() {
+		let space=HierarchicalLockSpace::<String>::new();
+		let bucket=space.lock(key("t/b"),Exclusive).unwrap();
+
+		let space_clone=space.clone();
+		let waiter=thread::spawn(move||{
+			let object=space_clone.lock(key("t/b/o"),Exclusive).unwrap();
+			object.key().clone()
+		});
+		thread::sleep(Duration::from_millis(50));
+		assert!(!waiter.is_finished());
+
+		drop(bucket);
+		assert_eq!(waiter.join().unwrap(),"t/b/o");
+		assert!(space.try_lock(key("t/b"),Exclusive).is_ok());
+	}
+
+	#[test]
+	#[cfg(all(not(feature="spin"),not(feature="parking_lot")))]
+	fn release_poisoned() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		let space=HierarchicalLockSpace::<String>::new();
+		let guard=space.lock(key("t"),Exclusive).unwrap();
+		let result=panic::catch_unwind(AssertUnwindSafe(||{
+			let _node=space.nodes.lock(key("t"),Node::new);
+			panic!("Holder panicked");
+		}));
+		assert!(result.is_err());
+
+		// The node is poisoned, but releasing still decrements its count
+		drop(guard);
+		let node=match space.node(&key("t")) {
+			Err(err) => err.into_inner(),
+			_ => panic!("Node must be poisoned"),
+		};
+		assert_eq!(node.held,[0,0,0,0,0]);
+	}
+
+	#[test]
+	#[cfg(all(not(feature="spin"),not(feature="parking_lot")))]
+	fn panicking_holder() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		let space=HierarchicalLockSpace::<String>::new();
+		let result=panic::catch_unwind(AssertUnwindSafe(||{
+			let _guard=space.lock(key("t/b/o"),Exclusive).unwrap();
+			panic!("Holder panicked");
+		}));
+		assert!(result.is_err());
+
+		// Releasing the path during the panic doesn't poison it
+		for path in ["t","t/b","t/b/o"] {
+			assert!(space.try_lock(key(path),Exclusive).is_ok());
+		}
+		assert!(matches!(space.nodes.try_remove(key("t")),LockSpaceRemoveResult::NotFound));
+	}
+
+	#[test]
+	fn key_parents() {
+		assert_eq!(key("a/b/c").parent(),Some(key("a/b")));
+		assert_eq!(key("a").parent(),None);
+		assert_eq!(vec![1,2].parent(),Some(vec![1]));
+		assert_eq!(PathBuf::from("/a/b").parent(),Some(PathBuf::from("/a")));
+		assert_eq!(PathBuf::from("a").parent(),None);
+	}
+}
@@ -109,60 +109,172 @@ compile_error!("`spin` and `parking_lot` features can't be used together");
 
 #[cfg(feature="spin")] extern crate spin;
 #[cfg(feature="parking_lot")] extern crate parking_lot;
+#[cfg(any(feature="file",feature="shm"))] extern crate libc;
 #[cfg(feature="std")] extern crate core;
 #[cfg(not(feature="std"))] extern crate alloc;
 #[cfg(not(feature="std"))] extern crate core_collections;
 
-#[cfg(feature="std")] use std::collections::{hash_map,HashMap};
-#[cfg(not(feature="std"))] use core_collections::{hash_map,HashMap};
+#[cfg(feature="std")] use std::collections::{hash_map,HashSet,BTreeMap};
+#[cfg(not(feature="std"))] use core_collections::{hash_map,HashSet};
 #[cfg(feature="std")] use std::sync::Arc;
 #[cfg(not(feature="std"))] use alloc::arc::Arc;
-#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))] use std::sync::{Mutex,MutexGuard};
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))] use std::sync::{Mutex,MutexGuard,Condvar};
 #[cfg(feature="spin")] use spin::{Mutex,MutexGuard};
-#[cfg(feature="parking_lot")] use parking_lot::{Mutex,MutexGuard};
-use core::hash::Hash;
-use core::ops::{Deref,DerefMut};
+#[cfg(feature="parking_lot")] use parking_lot::{Mutex,MutexGuard,Condvar};
+#[cfg(feature="std")] use std::time::{Duration,Instant};
+#[cfg(all(feature="std",not(feature="parking_lot")))] use std::thread;
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))] use std::sync::RwLock;
+#[cfg(feature="spin")] use spin::RwLock;
+#[cfg(feature="parking_lot")] use parking_lot::RwLock;
+#[cfg(not(feature="std"))] use alloc::borrow::ToOwned;
+#[cfg(not(feature="std"))] use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::hash::{Hash,BuildHasher};
+use core::ops::{Deref,DerefMut,Index,IndexMut};
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
 use core::mem::drop;
+use core::convert::Infallible;
+#[cfg(feature="std")] use core::sync::atomic::{AtomicU64,Ordering};
 
 pub mod lockresult;
 use lockresult::LockResult as Result;
+use lockresult::TryLockResult;
+use lockresult::{LockError,LockSpaceResult,PoisonError};
+pub use lockresult::{CheckedInitError,CheckedInitResult,InitError,InitResult};
+#[cfg(feature="std")] use lockresult::{TryLockError,TimedLockError,TimedLockResult};
 
 pub mod ownedmutexguard;
-use ownedmutexguard::{OwnedMutex,OwnedMutexGuard};
+
+pub mod ownedrwlockguard;
+use ownedrwlockguard::OwnedRwLock;
+
+pub mod rwlockspace;
+pub use rwlockspace::{RwLockSpace,ReadGuard,WriteGuard};
+
+#[cfg(feature="std")] pub mod asynclockspace;
+#[cfg(feature="std")] pub use asynclockspace::{AsyncLockSpace,AsyncLockSpaceGuard};
+
+#[cfg(feature="std")] pub mod hierarchical;
+#[cfg(feature="std")] pub use hierarchical::{HierarchicalLockSpace,HierarchicalGuard,HierarchicalKey,LockMode};
+
+#[cfg(all(feature="file",unix))] pub mod filelockspace;
+#[cfg(all(feature="file",unix))] pub use filelockspace::{FileLockSpace,FileLockGuard};
+
+#[cfg(all(feature="shm",target_os="linux"))] pub mod shmlockspace;
+#[cfg(all(feature="shm",target_os="linux"))] pub use shmlockspace::{ShmLockSpace,ShmLockGuard};
+
+#[cfg(all(feature="std",unix))] pub mod remote;
+#[cfg(all(feature="std",unix))] pub use remote::{LockServer,RemoteLockSpace,RemoteLockGuard};
+
+#[cfg(feature="std")] pub mod backend;
+#[cfg(feature="std")] pub use backend::LockBackend;
+
+pub mod storage;
+pub use storage::{Storage,HashStorage};
+use storage::StorageMap;
+
+#[cfg(feature="std")] mod waitgraph;
+#[cfg(feature="lockdep")] mod lockdep;
+#[cfg(feature="metrics")] pub mod metrics;
+#[cfg(all(test,feature="std",unix))] mod testutil;
+#[cfg(feature="std")] use waitgraph::WaitGraph;
 
 mod private {
 	#[allow(unused_imports)]
-	use lockresult::{PoisonError,LockResult};
+	use lockresult::{PoisonError,LockResult,TryLockError,TryLockResult};
+
+	pub trait IntoResult<T>: Sized {
+		// A poisoned guard is returned in the error
+		fn into_guard_result(self) -> LockResult<T,T>;
+
+		fn into_result(self) -> LockResult<T> {
+			self.into_guard_result().map_err(|err|err.map_guard(drop))
+		}
+	}
 
-	pub trait IntoResult<T> {
-		fn into_result(self) -> LockResult<T>;
+	pub trait IntoTryResult<T>: Sized {
+		// A poisoned guard is returned in the error
+		fn into_guard_try_result(self) -> TryLockResult<T,T>;
 	}
 
 	#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
 	impl<T> IntoResult<T> for Result<T,::std::sync::PoisonError<T>> {
-		fn into_result(self) -> LockResult<T> {
+		fn into_guard_result(self) -> LockResult<T,T> {
 			match self {
 				Ok(v) => Ok(v),
-				Err(_) => Err(PoisonError),
+				Err(err) => Err(PoisonError::new(err.into_inner())),
+			}
+		}
+	}
+
+	#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+	impl<T> IntoTryResult<T> for Result<T,::std::sync::TryLockError<T>> {
+		fn into_guard_try_result(self) -> TryLockResult<T,T> {
+			match self {
+				Ok(v) => Ok(v),
+				Err(::std::sync::TryLockError::Poisoned(err)) => Err(TryLockError::Poisoned(PoisonError::new(err.into_inner()))),
+				Err(::std::sync::TryLockError::WouldBlock) => Err(TryLockError::WouldBlock),
+			}
+		}
+	}
+
+	// spin and parking_lot signal contention with `None` and have no poisoning
+	#[cfg(any(feature="spin",feature="parking_lot"))]
+	impl<T> IntoTryResult<T> for Option<T> {
+		fn into_guard_try_result(self) -> TryLockResult<T,T> {
+			match self {
+				Some(v) => Ok(v),
+				None => Err(TryLockError::WouldBlock),
 			}
 		}
 	}
 
 	#[cfg(feature="spin")]
 	impl<'a,T> IntoResult<::spin::MutexGuard<'a,T>> for ::spin::MutexGuard<'a,T> {
-		fn into_result(self) -> LockResult<::spin::MutexGuard<'a,T>> {
+		fn into_guard_result(self) -> LockResult<Self,Self> {
+			Ok(self)
+		}
+	}
+
+	#[cfg(feature="spin")]
+	impl<'a,T> IntoResult<::spin::RwLockReadGuard<'a,T>> for ::spin::RwLockReadGuard<'a,T> {
+		fn into_guard_result(self) -> LockResult<Self,Self> {
+			Ok(self)
+		}
+	}
+
+	#[cfg(feature="spin")]
+	impl<'a,T> IntoResult<::spin::RwLockWriteGuard<'a,T>> for ::spin::RwLockWriteGuard<'a,T> {
+		fn into_guard_result(self) -> LockResult<Self,Self> {
 			Ok(self)
 		}
 	}
 
 	#[cfg(feature="parking_lot")]
 	impl<'a,T> IntoResult<::parking_lot::MutexGuard<'a,T>> for ::parking_lot::MutexGuard<'a,T> {
-		fn into_result(self) -> LockResult<::parking_lot::MutexGuard<'a,T>> {
+		fn into_guard_result(self) -> LockResult<Self,Self> {
+			Ok(self)
+		}
+	}
+
+	#[cfg(feature="parking_lot")]
+	impl<'a,T> IntoResult<::parking_lot::RwLockReadGuard<'a,T>> for ::parking_lot::RwLockReadGuard<'a,T> {
+		fn into_guard_result(self) -> LockResult<Self,Self> {
+			Ok(self)
+		}
+	}
+
+	#[cfg(feature="parking_lot")]
+	impl<'a,T> IntoResult<::parking_lot::RwLockWriteGuard<'a,T>> for ::parking_lot::RwLockWriteGuard<'a,T> {
+		fn into_guard_result(self) -> LockResult<Self,Self> {
 			Ok(self)
 		}
 	}
 }
 use private::IntoResult;
+#[allow(unused_imports)]
+use private::IntoTryResult;
 
 /// An RAII implementation of a "scoped lock" of a a LockSpace value. When this
 /// structure is dropped (falls out of scope), the lock will be unlocked, and
@@ -170,13 +282,25 @@ use private::IntoResult;
 ///
 /// The actual value can be accessed through this guard via its Deref and
 /// DerefMut implementations.
-pub struct LockSpaceGuard<'a,K: 'a + Eq + Hash + Clone,V:'a> {
-    owner: &'a LockSpace<K,V>,
-    key: Option<K>,
-    guard: Option<OwnedMutexGuard<'a,V,Arc<Mutex<V>>>>,
+pub struct LockSpaceGuard<'a,K: 'a + Eq + Hash + Clone,V:'a,S: 'a + Storage<K>=HashStorage> {
+    owner: &'a LockSpace<K,V,S>,
+    shard: &'a Shard<K,V,S>,
+    guard: Option<EntryGuard<K,V>>,
+    #[cfg(feature="metrics")]
+    since: Instant,
+    // Deadlock detection and lock order validation keep track of the thread
+    // that holds a lock, so like std's MutexGuard, this can't be sent to
+    // another thread
+    _not_send: PhantomData<*mut V>,
 }
 
-impl<'a,K: Eq + Hash + Clone,V:'a> Deref for LockSpaceGuard<'a,K,V> {
+// SAFETY: Only `_not_send` keeps the guard from being Sync. The other fields
+// are Sync if the space and V are, and sharing the guard only gives access to
+// &V, like with std's MutexGuard.
+unsafe impl<'a,K: Eq + Hash + Clone + Send + Sync,V: Send + Sync,S: Storage<K>> Sync for LockSpaceGuard<'a,K,V,S>
+	where LockSpace<K,V,S>: Sync {}
+
+impl<'a,K: Eq + Hash + Clone,V:'a,S: Storage<K>> Deref for LockSpaceGuard<'a,K,V,S> {
 	type Target = V;
 	fn deref<'b>(&'b self) -> &'b V {
 		// This is always Some, because it's initialized as Some, and only drop() turns it into None
@@ -187,7 +311,7 @@ impl<'a,K: Eq + Hash + Clone,V:'a> Deref for LockSpaceGuard<'a,K,V> {
 	}
 }
 
-impl<'a,K: Eq + Hash + Clone,V:'a> DerefMut for LockSpaceGuard<'a,K,V> {
+impl<'a,K: Eq + Hash + Clone,V:'a,S: Storage<K>> DerefMut for LockSpaceGuard<'a,K,V,S> {
 	fn deref_mut<'b>(&'b mut self) -> &'b mut V {
 		// This is always Some, because it's initialized as Some, and only drop() turns it into None
 		match self.guard {
@@ -197,27 +321,328 @@ impl<'a,K: Eq + Hash + Clone,V:'a> DerefMut for LockSpaceGuard<'a,K,V> {
 	}
 }
 
-impl<'a,K: Eq + Hash + Clone,V:'a> Drop for LockSpaceGuard<'a,K,V> {
+impl<'a,K: Eq + Hash + Clone,V:'a,S: Storage<K>> Drop for LockSpaceGuard<'a,K,V,S> {
     fn drop(&mut self) {
+		{
+			let entry=&self.guard.as_ref().unwrap().target().lock;
+			#[cfg(feature="std")]
+			self.owner.touch(&entry.last_released);
+			#[cfg(feature="metrics")]
+			self.owner.record_released(entry,self.since);
+			let _=entry;
+		}
 		// release inner lock
 		let arc=self.guard.take().unwrap().into_inner();
-		// Ignore poison error on drop here
-		if let Ok(mut map)=self.owner.names.lock().into_result() { // Acquire outer lock
-			// Drop our reference to inner while holding the outer lock. This
-			// might drop the Arc reference count to 1, which will later allow
-			// Arc::try_unwrap to succeed.
-			drop(arc);
-			if self.owner.cleanup==AutoCleanup {
-				// The following should always match if invariants hold
-				if let hash_map::Entry::Occupied(oentry)=map.entry(self.key.take().unwrap()) {
-					LockSpace::<K,V>::try_remove_internal(oentry);
-				}
-			}
-		}
-		// Release outer lock
+		self.owner.released(&arc.key);
+		self.owner.release(self.shard,arc);
     }
 }
 
+impl<'a,K: Eq + Hash + Clone,V: 'a,S: Storage<K>> LockSpaceGuard<'a,K,V,S> {
+	/// Make a MappedLockSpaceGuard for a part of the locked value, such as
+	/// one of its fields. The key stays locked until the new guard is dropped.
+	///
+	/// This is an associated function, so that it doesn't conflict with a
+	/// method of the same name on `V`.
+	///
+	/// ```
+	/// use namedlock::LockSpaceGuard;
+	///
+	/// let space=namedlock::LockSpace::<String,(i32,i32)>::new(namedlock::KeepUnused);
+	///
+	/// let mut second=LockSpaceGuard::map(space.lock("test".to_owned(),||(0,0)).unwrap(),|v|&mut v.1);
+	/// *second+=1;
+	/// drop(second);
+	/// assert_eq!(*space.lock("test".to_owned(),||(0,0)).unwrap(),(0,1));
+	/// ```
+	pub fn map<U: ?Sized,F>(mut guard: Self, f: F) -> MappedLockSpaceGuard<'a,K,V,U,S>
+		where F: FnOnce(&mut V) -> &mut U
+	{
+		let value: *mut U=f(&mut *guard);
+		MappedLockSpaceGuard{guard,value}
+	}
+
+	/// Like `map()`, but `f` may fail to find the part of the value to map
+	/// to. In that case, the original guard is returned.
+	pub fn try_map<U: ?Sized,F>(mut guard: Self, f: F) -> core::result::Result<MappedLockSpaceGuard<'a,K,V,U,S>,Self>
+		where F: FnOnce(&mut V) -> Option<&mut U>
+	{
+		let value=f(&mut *guard).map(|value|value as *mut U);
+		match value {
+			Some(value) => Ok(MappedLockSpaceGuard{guard,value}),
+			None => Err(guard),
+		}
+	}
+}
+
+#[cfg(all(feature="std",not(feature="spin")))]
+impl<'a,K: Eq + Hash + Clone,V: 'a,S: Storage<K>> LockSpaceGuard<'a,K,V,S> {
+	/// Block the current thread while `condition` returns true for the
+	/// value. While blocked, the lock is released, and the thread waits until
+	/// another holder of the same key calls `notify_one()` or `notify_all()`.
+	/// The lock is reacquired before `condition` is checked again.
+	///
+	/// Every key has its own condition variable. While a thread is waiting,
+	/// the value is not cleaned up.
+	///
+	/// ```
+	/// use std::thread;
+	///
+	/// let space=namedlock::LockSpace::<String,bool>::new(namedlock::AutoCleanup);
+	///
+	/// let mut ready=space.lock("job".to_owned(),||false).unwrap();
+	/// let space_clone=space.clone();
+	/// let worker=thread::spawn(move||{
+	///     let mut ready=space_clone.lock("job".to_owned(),||false).unwrap();
+	///     *ready=true;
+	///     ready.notify_all();
+	/// });
+	/// ready.wait_while(|ready|!*ready).unwrap();
+	/// drop(ready);
+	/// worker.join().unwrap();
+	/// ```
+	pub fn wait_while<F>(&mut self, condition: F) -> Result<()>
+		where F: FnMut(&mut V) -> bool
+	{
+		self.wait(|entry|entry.wait_while(condition))
+	}
+
+	/// Like `wait_while()`, but gives up once `timeout` has passed. Returns
+	/// `true` if `condition` was still true at that point.
+	///
+	/// Like with std's Condvar, the lock still needs to be reacquired once the
+	/// timeout has passed, which may take longer.
+	pub fn wait_timeout_while<F>(&mut self, timeout: Duration, condition: F) -> Result<bool>
+		where F: FnMut(&mut V) -> bool
+	{
+		self.wait(|entry|entry.wait_timeout_while(timeout,condition))
+	}
+
+	/// Wake up one thread waiting in `wait_while()` or
+	/// `wait_timeout_while()` for this key.
+	pub fn notify_one(&self) {
+		self.entry().changed.notify_one();
+	}
+
+	/// Wake up all threads waiting in `wait_while()` or
+	/// `wait_timeout_while()` for this key.
+	pub fn notify_all(&self) {
+		self.entry().changed.notify_all();
+	}
+
+	fn entry(&self) -> &Entry<V> {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		&self.guard.as_ref().unwrap().target().lock
+	}
+
+	// Don't poison the value if this guard is dropped during a panic, for
+	// values that a panic can't leave inconsistent
+	pub(crate) fn never_poison(&mut self) {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		self.guard.as_mut().unwrap().poison=false;
+	}
+
+	// Release the inner lock while `f` waits, as far as deadlock detection and
+	// lock order validation are concerned
+	fn wait<F,R>(&mut self, f: F) -> R
+		where F: FnOnce(&Entry<V>) -> R
+	{
+		// `&mut self` makes sure the value isn't accessed through this guard
+		// while `f` runs
+		let target=self.guard.as_ref().unwrap().target();
+		self.owner.released(&target.key);
+		let result=f(&target.lock);
+		self.owner.acquired(target);
+		result
+	}
+}
+
+/// An RAII implementation of a "scoped lock" of a part of a LockSpace value,
+/// as returned by `LockSpaceGuard::map`. When this structure is dropped (falls
+/// out of scope), the lock will be unlocked, just like with the original
+/// guard.
+///
+/// The part of the value can be accessed through this guard via its Deref and
+/// DerefMut implementations.
+pub struct MappedLockSpaceGuard<'a,K: 'a + Eq + Hash + Clone,V: 'a,U: ?Sized + 'a,S: 'a + Storage<K>=HashStorage> {
+	guard: LockSpaceGuard<'a,K,V,S>,
+	// Points into the value locked by `guard`, which doesn't move
+	value: *mut U,
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a,U: ?Sized,S: Storage<K>> MappedLockSpaceGuard<'a,K,V,U,S> {
+	/// Make a MappedLockSpaceGuard for a part of this guard's value. See
+	/// `LockSpaceGuard::map`.
+	pub fn map<W: ?Sized,F>(guard: Self, f: F) -> MappedLockSpaceGuard<'a,K,V,W,S>
+		where F: FnOnce(&mut U) -> &mut W
+	{
+		let value: *mut W=f(unsafe{&mut *guard.value});
+		MappedLockSpaceGuard{guard:guard.guard,value}
+	}
+
+	/// Like `map()`, but `f` may fail to find the part of the value to map
+	/// to. In that case, the original guard is returned.
+	pub fn try_map<W: ?Sized,F>(guard: Self, f: F) -> core::result::Result<MappedLockSpaceGuard<'a,K,V,W,S>,Self>
+		where F: FnOnce(&mut U) -> Option<&mut W>
+	{
+		match f(unsafe{&mut *guard.value}).map(|value|value as *mut W) {
+			Some(value) => Ok(MappedLockSpaceGuard{guard:guard.guard,value}),
+			None => Err(guard),
+		}
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a,U: ?Sized,S: Storage<K>> Deref for MappedLockSpaceGuard<'a,K,V,U,S> {
+	type Target = U;
+	fn deref(&self) -> &U {
+		unsafe{&*self.value}
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a,U: ?Sized,S: Storage<K>> DerefMut for MappedLockSpaceGuard<'a,K,V,U,S> {
+	fn deref_mut(&mut self) -> &mut U {
+		unsafe{&mut *self.value}
+	}
+}
+
+/// An RAII implementation of a "scoped lock" of a LockSpace value that owns
+/// a handle to its space, as returned by `LockSpace::lock_owned`. When this
+/// structure is dropped (falls out of scope), the lock will be unlocked, and
+/// the reference count to the key will be decreased by 1.
+///
+/// Unlike a LockSpaceGuard, this guard can be sent to other threads. If it's
+/// dropped during a panic, the value is poisoned, just like with a
+/// LockSpaceGuard. Acquiring it is checked by deadlock detection and lock
+/// order validation, but since it isn't held by a particular thread, it
+/// doesn't count as held by any thread afterwards.
+///
+/// The actual value can be accessed through this guard via its Deref and
+/// DerefMut implementations.
+#[cfg(feature="std")]
+pub struct OwnedLockSpaceGuard<K: Eq + Hash + Clone,V,S: Storage<K>=HashStorage> {
+	space: LockSpace<K,V,S>,
+	guard: Option<EntryGuard<K,V>>,
+	#[cfg(feature="metrics")]
+	since: Instant,
+}
+
+#[cfg(feature="std")]
+impl<K: Eq + Hash + Clone,V,S: Storage<K>> OwnedLockSpaceGuard<K,V,S> {
+	/// The key of the locked value.
+	pub fn key(&self) -> &K {
+		&self.guard().target().key
+	}
+
+	/// Wake up one thread waiting in `LockSpaceGuard::wait_while()` or
+	/// `LockSpaceGuard::wait_timeout_while()` for this key.
+	#[cfg(not(feature="spin"))]
+	pub fn notify_one(&self) {
+		self.guard().target().lock.changed.notify_one();
+	}
+
+	/// Wake up all threads waiting in `LockSpaceGuard::wait_while()` or
+	/// `LockSpaceGuard::wait_timeout_while()` for this key.
+	#[cfg(not(feature="spin"))]
+	pub fn notify_all(&self) {
+		self.guard().target().lock.changed.notify_all();
+	}
+
+	fn guard(&self) -> &EntryGuard<K,V> {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		self.guard.as_ref().unwrap()
+	}
+}
+
+#[cfg(feature="std")]
+impl<K: Eq + Hash + Clone,V,S: Storage<K>> Deref for OwnedLockSpaceGuard<K,V,S> {
+	type Target = V;
+	fn deref(&self) -> &V {
+		self.guard()
+	}
+}
+
+#[cfg(feature="std")]
+impl<K: Eq + Hash + Clone,V,S: Storage<K>> DerefMut for OwnedLockSpaceGuard<K,V,S> {
+	fn deref_mut(&mut self) -> &mut V {
+		self.guard.as_mut().unwrap()
+	}
+}
+
+#[cfg(feature="std")]
+impl<K: Eq + Hash + Clone,V,S: Storage<K>> Drop for OwnedLockSpaceGuard<K,V,S> {
+	fn drop(&mut self) {
+		let guard=self.guard.take().unwrap();
+		let shard=self.space.shard(&guard.target().key);
+		self.space.touch(&guard.target().lock.last_released);
+		#[cfg(feature="metrics")]
+		self.space.record_released(&guard.target().lock,self.since);
+		// release inner lock
+		let target=guard.into_inner();
+		self.space.release(shard,target);
+	}
+}
+
+/// An RAII implementation of a "scoped lock" of multiple LockSpace values, as
+/// returned by `LockSpace::lock_many`. When this structure is dropped (falls
+/// out of scope), all locks will be unlocked.
+///
+/// The values can be accessed by key through this guard via its `get` and
+/// `get_mut` methods or its Index and IndexMut implementations.
+pub struct LockSetGuard<'a,K: 'a + Eq + Hash + Clone,V: 'a,S: 'a + Storage<K>=HashStorage> {
+	// Sorted, with the same order as `guards`
+	keys: Vec<K>,
+	guards: Vec<LockSpaceGuard<'a,K,V,S>>,
+}
+
+impl<'a,K: Eq + Hash + Clone + Ord,V: 'a,S: Storage<K>> LockSetGuard<'a,K,V,S> {
+	fn position<Q>(&self, key: &Q) -> Option<usize>
+		where K: Borrow<Q>, Q: ?Sized + Ord
+	{
+		self.keys.binary_search_by(|k|k.borrow().cmp(key)).ok()
+	}
+
+	/// Returns a reference to the value of `key`, or `None` if it's not part
+	/// of this set.
+	pub fn get<Q>(&self, key: &Q) -> Option<&V>
+		where K: Borrow<Q>, Q: ?Sized + Ord
+	{
+		self.position(key).map(|i|&*self.guards[i])
+	}
+
+	/// Returns a mutable reference to the value of `key`, or `None` if it's
+	/// not part of this set.
+	pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+		where K: Borrow<Q>, Q: ?Sized + Ord
+	{
+		match self.position(key) {
+			Some(i) => Some(&mut *self.guards[i]),
+			None => None,
+		}
+	}
+
+	/// The locked keys, in the order they were locked.
+	pub fn keys(&self) -> &[K] {
+		&self.keys
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone + Ord,V: 'a,Q,S: Storage<K>> Index<&Q> for LockSetGuard<'a,K,V,S>
+	where K: Borrow<Q>, Q: ?Sized + Ord
+{
+	type Output = V;
+	fn index(&self, key: &Q) -> &V {
+		self.get(key).expect("key not in LockSetGuard")
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone + Ord,V: 'a,Q,S: Storage<K>> IndexMut<&Q> for LockSetGuard<'a,K,V,S>
+	where K: Borrow<Q>, Q: ?Sized + Ord
+{
+	fn index_mut(&mut self, key: &Q) -> &mut V {
+		self.get_mut(key).expect("key not in LockSetGuard")
+	}
+}
+
 #[derive(PartialEq,Eq,Clone,Copy)]
 pub enum Cleanup {
 	KeepUnused,
@@ -226,89 +651,1805 @@ pub enum Cleanup {
 pub use Cleanup::KeepUnused;
 pub use Cleanup::AutoCleanup;
 
-type LockSpaceValue<V> = Option<Arc<Mutex<V>>>;
-type LockSpaceEntry<'a,K,V> = hash_map::OccupiedEntry<'a,K,LockSpaceValue<V>>;
+// An inner lock, along with the key it is stored under in the outer map. This
+// way, guards can find their entry again without needing their own copy of the
+// key.
+struct Named<K,L> {
+	key: K,
+	lock: L,
+}
 
-/// A `LockSpace<K,V>` holds many `Mutex<V>`'s, keyed by `K`.
-///
-/// All accesses to the internal value must go through one of the lock methods.
-///
-/// See the crate documentation for an example.
-///
-/// # Key parameters
-/// Most of the `LockSpace<K,V>` methods take a `key: K`. This is because we
-/// make a lot of use of the `HashMap::entry` API. If that API changes to accept
-/// e.g. Cow, this crate will adopt that too.
-pub struct LockSpace<K: Eq + Hash,V> {
-	// IMPORTANT: To avoid deadlocks, always acquire the inner lock while
-	// holding the outer lock. Once the inner lock is acquired, the outer lock
-	// can be released.
-	//
-	// Also, when the outer lock is not held, all values must be Some()
-	names: Arc<Mutex<HashMap<K,LockSpaceValue<V>>>>,
-	// IMPORTANT: We implement cleanup based on reference-counting. For this
-	// to work, there are a few invariants that must hold:
-	//   1. The lock space holds 1 reference to the inner Mutex
-	//   2. Each lock guard holds 1 reference to the inner Mutex
-	// No. 2 is guaranteed by only cloning it's Arc in a single circumstance,
-	// when creating a new lock. For synchronization, the number of references
-	// to an inner Mutex is only changed or evaluated while the outer Mutex is
-	// locked.
-	cleanup: Cleanup,
+// A reference to a named inner lock that derefs to the lock itself, for use
+// with the OwnedRwLock trait.
+struct NamedRef<K,L>(Arc<Named<K,L>>);
+
+impl<K,L> Deref for NamedRef<K,L> {
+	type Target = L;
+	fn deref(&self) -> &L {
+		&self.0.lock
+	}
 }
 
-pub enum LockSpaceRemoveResult {
-	Success,
-	NotFound,
-	PoisonError,
-	/// `remove()` would block.
-	WouldBlock,
+// The memory address of the lock doesn't change when the Arc is moved
+unsafe impl<K,T> OwnedRwLock<T> for NamedRef<K,RwLock<T>> {}
+
+// The inner lock of a LockSpace, along with the per-key state that goes with
+// it. Unlike a Mutex, the lock isn't tied to the thread that acquired it, so
+// that an OwnedLockSpaceGuard can be sent to another thread. Releasing the
+// lock and starting to wait on `changed` also need to happen atomically, see
+// `wait_changed()`, which a Mutex<V> can't do either.
+//
+// IMPORTANT: The lock is held exactly while `state.locked` is true. Only the
+// holder, which is the `EntryGuard` that set `locked`, may access `value`. The
+// only exceptions are `into_value()`, which owns the entry, and
+// `LockSpace::try_remove_if()`, which proves that no guard can exist.
+struct Entry<V> {
+	// This only protects the bookkeeping, so it's never held for long, and it
+	// may be acquired while holding the outer lock
+	state: Mutex<EntryState>,
+	// Paired with `state`. Signalled whenever a guard releases this lock.
+	#[cfg(all(feature="std",not(feature="spin")))]
+	unlocked: Condvar,
+	// Paired with `state`. Signalled by `notify_one()` and `notify_all()` on
+	// a guard.
+	#[cfg(all(feature="std",not(feature="spin")))]
+	changed: Condvar,
+	// Only accessed by the holder of this lock
+	value: UnsafeCell<V>,
+	// When a guard last acquired and released this lock, as nanoseconds since
+	// the epoch of the space plus 1. 0 means never.
+	#[cfg(feature="std")]
+	last_acquired: AtomicU64,
+	#[cfg(feature="std")]
+	last_released: AtomicU64,
+	// Where this entry is in the LRU list of its shard, or 0 if it's not in
+	// there. Only changed while holding the outer lock.
+	#[cfg(feature="std")]
+	lru_seq: AtomicU64,
+	#[cfg(feature="metrics")]
+	metrics: metrics::Counters,
 }
 
-// This needs to be implemented manually, since #[derive(Clone)] doesn't
-// understand that the type parameters are only used within the Arc<_>
-impl<K: Eq + Hash,V> Clone for LockSpace<K,V> {
-	fn clone(&self) -> LockSpace<K,V> {
-		LockSpace{names:self.names.clone(),cleanup:self.cleanup}
+struct EntryState {
+	locked: bool,
+	// Set when a guard is dropped during a panic, until `clear_poison()`.
+	// spin and parking_lot have no poisoning.
+	poisoned: bool,
+}
+
+// SAFETY: Sending the entry sends the value, so V must be Send. Sharing it
+// only gives access to the value through the lock, which hands out the value
+// to one thread at a time, so like with Mutex<V>, V only needs to be Send.
+// Sharing a guard hands out &V to several threads, so EntryGuard is only Sync
+// if V is.
+unsafe impl<V: Send> Send for Entry<V> {}
+unsafe impl<V: Send> Sync for Entry<V> {}
+
+impl<V> Entry<V> {
+	fn new(value: V) -> Entry<V> {
+		Entry{
+			state:Mutex::new(EntryState{locked:false,poisoned:false}),
+			#[cfg(all(feature="std",not(feature="spin")))]
+			unlocked:Condvar::new(),
+			#[cfg(all(feature="std",not(feature="spin")))]
+			changed:Condvar::new(),
+			value:UnsafeCell::new(value),
+			#[cfg(feature="std")]
+			last_acquired:AtomicU64::new(0),
+			#[cfg(feature="std")]
+			last_released:AtomicU64::new(0),
+			#[cfg(feature="std")]
+			lru_seq:AtomicU64::new(0),
+			#[cfg(feature="metrics")]
+			metrics:Default::default(),
+		}
+	}
+
+	fn state(&self) -> MutexGuard<'_,EntryState> {
+		// The state is always consistent, even if someone panicked
+		lock_unpoisoned(&self.state)
+	}
+
+	// Take this lock if it's not held. Returns whether it's poisoned, or
+	// `None` if it's held.
+	fn try_lock(&self) -> Option<bool> {
+		let mut state=self.state();
+		if state.locked {
+			return None;
+		}
+		state.locked=true;
+		Some(state.poisoned)
+	}
+
+	// Block until this lock is acquired. Returns whether it's poisoned.
+	fn lock(&self) -> bool {
+		let mut state=self.state();
+		while state.locked {
+			state=self.wait_unlocked(state);
+		}
+		state.locked=true;
+		state.poisoned
+	}
+
+	// Like `lock()`, but gives up at `deadline`, returning `None`
+	#[cfg(feature="std")]
+	fn lock_until(&self, deadline: Instant) -> Option<bool> {
+		let mut state=self.state();
+		while state.locked {
+			if Instant::now()>=deadline {
+				return None;
+			}
+			state=self.wait_unlocked_until(state,deadline);
+		}
+		state.locked=true;
+		Some(state.poisoned)
+	}
+
+	// Release this lock. If `poison` is set and the current thread is
+	// panicking, the lock is poisoned.
+	fn unlock(&self, poison: bool) {
+		let mut state=self.state();
+		state.locked=false;
+		#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+		{
+			state.poisoned|=poison && thread::panicking();
+		}
+		#[cfg(not(all(feature="std",not(feature="spin"),not(feature="parking_lot"))))]
+		let _=poison;
+		drop(state);
+		// Whoever is woken up either takes the lock, or someone else did
+		// and will wake up the next one
+		#[cfg(all(feature="std",not(feature="spin")))]
+		self.unlocked.notify_one();
+	}
+
+	// Release `state` until this lock may have been released. `state` is held
+	// again when this returns.
+	#[cfg(all(feature="std",not(feature="spin")))]
+	fn wait_unlocked<'a>(&self, state: MutexGuard<'a,EntryState>) -> MutexGuard<'a,EntryState> {
+		wait_unpoisoned(&self.unlocked,state)
+	}
+
+	#[cfg(feature="spin")]
+	fn wait_unlocked<'a>(&'a self, state: MutexGuard<'a,EntryState>) -> MutexGuard<'a,EntryState> {
+		// spin has no way to wait for a release, so just back off
+		drop(state);
+		#[cfg(feature="std")]
+		thread::yield_now();
+		#[cfg(not(feature="std"))]
+		core::hint::spin_loop();
+		self.state()
+	}
+
+	// Like `wait_unlocked()`, but returns at `deadline` at the latest
+	#[cfg(all(feature="std",not(feature="spin")))]
+	fn wait_unlocked_until<'a>(&self, state: MutexGuard<'a,EntryState>, deadline: Instant) -> MutexGuard<'a,EntryState> {
+		wait_unpoisoned_until(&self.unlocked,state,deadline)
+	}
+
+	#[cfg(all(feature="std",feature="spin"))]
+	fn wait_unlocked_until<'a>(&'a self, state: MutexGuard<'a,EntryState>, _deadline: Instant) -> MutexGuard<'a,EntryState> {
+		self.wait_unlocked(state)
+	}
+
+	fn into_value(self) -> V {
+		self.value.into_inner()
+	}
+
+	fn is_poisoned(&self) -> bool {
+		self.state().poisoned
+	}
+
+	fn clear_poison(&self) {
+		self.state().poisoned=false;
+	}
+
+	// Whether this lock is held by a guard
+	fn is_held(&self) -> bool {
+		self.state().locked
 	}
 }
 
-impl<K: Eq + Hash + Clone,V> LockSpace<K,V> {
-	/// Create a new LockSpace.
-	///
-	/// If `cleanup` is `AutoCleanup`, values will be deleted automatically when
-	/// the last lock is released. Otherwise, values will remain in the space
-	/// until `try_remove()` returns `Success`.
-	pub fn new(cleanup: Cleanup) -> LockSpace<K,V> {
-		LockSpace{names:Arc::new(Mutex::new(HashMap::new())),cleanup:cleanup}
+#[cfg(all(feature="std",not(feature="spin")))]
+impl<V> Entry<V> {
+	// Release this lock and wait on `changed`, until `deadline` if any, then
+	// reacquire the lock. Returns whether it's poisoned.
+	//
+	// IMPORTANT: The caller must hold this lock
+	fn wait_changed(&self, deadline: Option<Instant>) -> bool {
+		let mut state=self.state();
+		// Releasing the lock and starting to wait happen while holding
+		// `state`, so whoever acquires the lock next and notifies can't be
+		// missed
+		state.locked=false;
+		self.unlocked.notify_one();
+		state=match deadline {
+			Some(deadline) => wait_unpoisoned_until(&self.changed,state,deadline),
+			None => wait_unpoisoned(&self.changed,state),
+		};
+		while state.locked {
+			state=self.wait_unlocked(state);
+		}
+		state.locked=true;
+		state.poisoned
 	}
 
-	/// Find the object by `key`, or create it by calling `initial` if it does
-	/// not exist. Then, lock it and return a LockSpaceGuard over the object.
-	/// Once the guard is dropped, its object is unlocked, and if `AutoCleanup`
-	/// is specified for this space, removed if this is the last use.
-	///
-	/// ```
-	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	// Wait on `changed` while `condition` returns true. Fails if this lock is
+	// poisoned after waiting.
+	//
+	// IMPORTANT: The caller must hold this lock
+	fn wait_while<F>(&self, mut condition: F) -> Result<()>
+		where F: FnMut(&mut V) -> bool
+	{
+		let mut poisoned=false;
+		// SAFETY: The caller holds this lock whenever `condition` is called,
+		// and `wait_changed()` reacquires it before returning
+		while condition(unsafe{&mut *self.value.get()}) {
+			poisoned|=self.wait_changed(None);
+		}
+		if poisoned {
+			Err(PoisonError::new(()))
+		} else {
+			Ok(())
+		}
+	}
+
+	// Like `wait_while()`, but gives up after `timeout`. Returns whether the
+	// condition was still true at that point.
+	//
+	// IMPORTANT: The caller must hold this lock
+	fn wait_timeout_while<F>(&self, timeout: Duration, mut condition: F) -> Result<bool>
+		where F: FnMut(&mut V) -> bool
+	{
+		// A deadline too far in the future to represent never passes
+		let deadline=Instant::now().checked_add(timeout);
+		let mut poisoned=false;
+		let waiting=loop {
+			// SAFETY: The caller holds this lock whenever `condition` is
+			// called, and `wait_changed()` reacquires it before returning
+			let waiting=condition(unsafe{&mut *self.value.get()});
+			if !waiting || deadline.is_some_and(|deadline|Instant::now()>=deadline) {
+				break waiting;
+			}
+			poisoned|=self.wait_changed(deadline);
+		};
+		if poisoned {
+			Err(PoisonError::new(()))
+		} else {
+			Ok(waiting)
+		}
+	}
+}
+
+// Holds the inner lock of a LockSpace entry, along with a reference to the
+// entry. The lock is released when this is dropped, like with a MutexGuard,
+// but this isn't tied to the thread that acquired the lock.
+struct EntryGuard<K,V> {
+	target: Option<LockSpaceValue<K,V>>,
+	// Whether to poison the lock if this is dropped during a panic
+	poison: bool,
+	// Entry is Sync if V is only Send, but the guard hands out &V
+	_value: PhantomData<V>,
+}
+
+impl<K,V> EntryGuard<K,V> {
+	// Block until the inner lock of `target` is acquired. If it's poisoned,
+	// it's still acquired, and the guard is returned in the error.
+	fn lock(target: &LockSpaceValue<K,V>) -> LockSpaceInnerResult<K,V> {
+		let poisoned=target.lock.lock();
+		Self::locked(target,poisoned)
+	}
+
+	// Acquire the inner lock of `target` if it's not held
+	fn try_lock(target: &LockSpaceValue<K,V>) -> TryLockResult<EntryGuard<K,V>,EntryGuard<K,V>> {
+		match target.lock.try_lock() {
+			Some(poisoned) => Ok(Self::locked(target,poisoned)?),
+			None => Err(lockresult::TryLockError::WouldBlock),
+		}
+	}
+
+	// Like `lock()`, but gives up at `deadline`
+	#[cfg(feature="std")]
+	fn lock_until(target: &LockSpaceValue<K,V>, deadline: Instant) -> TimedLockResult<EntryGuard<K,V>,EntryGuard<K,V>> {
+		match target.lock.lock_until(deadline) {
+			Some(poisoned) => Ok(Self::locked(target,poisoned)?),
+			None => Err(TimedLockError::TimedOut),
+		}
+	}
+
+	// Make a guard with a new reference to `target`, whose inner lock was
+	// just acquired
+	fn locked(target: &LockSpaceValue<K,V>, poisoned: bool) -> LockSpaceInnerResult<K,V> {
+		let guard=EntryGuard{target:Some(target.clone(/*Invariants OK*/)),poison:true,_value:PhantomData};
+		if poisoned {
+			Err(PoisonError::new(guard))
+		} else {
+			Ok(guard)
+		}
+	}
+
+	fn target(&self) -> &LockSpaceValue<K,V> {
+		// This is always Some, because it's initialized as Some, and only drop() or into_inner() turns it into None
+		self.target.as_ref().unwrap()
+	}
+
+	// Release the inner lock, returning the reference to the entry
+	fn into_inner(mut self) -> LockSpaceValue<K,V> {
+		let target=self.target.take().unwrap();
+		target.lock.unlock(self.poison);
+		target
+	}
+}
+
+impl<K,V> Deref for EntryGuard<K,V> {
+	type Target = V;
+	fn deref(&self) -> &V {
+		// SAFETY: This guard holds the lock, and the returned reference
+		// borrows the guard, so it can't outlive the lock
+		unsafe{&*self.target().lock.value.get()}
+	}
+}
+
+impl<K,V> DerefMut for EntryGuard<K,V> {
+	fn deref_mut(&mut self) -> &mut V {
+		// SAFETY: This guard holds the lock, and the returned reference
+		// borrows the guard mutably, so it's the only one until it's dropped
+		unsafe{&mut *self.target().lock.value.get()}
+	}
+}
+
+impl<K,V> Drop for EntryGuard<K,V> {
+	fn drop(&mut self) {
+		if let Some(target)=self.target.take() {
+			target.lock.unlock(self.poison);
+		}
+	}
+}
+
+// The start of an attempt to acquire the inner lock of a LockSpace entry, for
+// metrics
+struct Attempt {
+	#[cfg(feature="metrics")]
+	start: Instant,
+	#[cfg(feature="metrics")]
+	contended: bool,
+}
+
+impl Attempt {
+	// The attempt is contended if anyone else uses `target`, which must be
+	// the caller's own reference.
+	//
+	// IMPORTANT: The caller must hold the outer lock
+	fn start<K,V>(target: &LockSpaceValue<K,V>) -> Attempt {
+		let _=target;
+		Attempt{
+			#[cfg(feature="metrics")]
+			start:Instant::now(),
+			// Don't count the references of the map and the caller
+			#[cfg(feature="metrics")]
+			contended:Arc::strong_count(target)>2,
+		}
+	}
+}
+
+// The map types are generic over the inner lock type `L` and the map type `M`,
+// so that the cleanup logic can be shared between the different kinds of lock
+// spaces and storage backends.
+type SpaceValue<K,L> = Arc<Named<K,L>>;
+// A held outer lock, along with a reference to one of its inner locks
+type SpaceFound<'a,M,K,L> = (MutexGuard<'a,M>,SpaceValue<K,L>);
+type LockSpaceValue<K,V> = SpaceValue<K,Entry<V>>;
+type LockSpaceMap<K,V,S> = <S as Storage<K>>::Map<LockSpaceValue<K,V>>;
+// A held outer lock
+type LockSpaceNames<'a,K,V,S> = MutexGuard<'a,LockSpaceMap<K,V,S>>;
+type LockSpaceFound<'a,K,V,S> = SpaceFound<'a,LockSpaceMap<K,V,S>,K,Entry<V>>;
+// The result of acquiring an inner lock, which carries the guard if poisoned
+type LockSpaceInnerResult<K,V> = Result<EntryGuard<K,V>,EntryGuard<K,V>>;
+
+// Find the inner lock for `key`, or create it by calling `initial` if it does
+// not exist, and return a new reference to it. The key is only converted to an
+// owned `K` if a new entry is created.
+//
+// IMPORTANT: The caller must hold the outer lock
+fn get_or_insert<K,Q,L,M,C>(map: &mut M, key: &Q, initial: C) -> SpaceValue<K,L>
+	where M: StorageMap<K,SpaceValue<K,L>>, K: Eq + Hash + Clone + Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> L
+{
+	match try_get_or_insert(map,key,||Ok::<L,Infallible>(initial())) {
+		Ok(arc) => arc,
+		Err(never) => match never {},
+	}
+}
+
+// Like `get_or_insert()`, but `initial` may fail, in which case nothing is
+// inserted and its error is returned.
+//
+// IMPORTANT: The caller must hold the outer lock
+fn try_get_or_insert<K,Q,L,M,E,C>(map: &mut M, key: &Q, initial: C) -> core::result::Result<SpaceValue<K,L>,E>
+	where M: StorageMap<K,SpaceValue<K,L>>, K: Eq + Hash + Clone + Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> core::result::Result<L,E>
+{
+	if let Some(arc)=map.get(key) {
+		return Ok(arc.clone(/*Invariants OK*/));
+	}
+	let lock=initial()?;
+	let key=key.to_owned();
+	let arc=Arc::new(Named{key:key.clone(),lock});
+	map.insert(key,arc.clone(/*Invariants OK*/));
+	Ok(arc)
+}
+
+// Remove `key` from `map` if its inner lock isn't used anymore, and return the
+// removed entry. `held` is the number of references to the inner lock that the
+// caller itself still holds and that will be dropped right after this returns.
+//
+// IMPORTANT: The caller must hold the outer lock
+// to guard target--and therefore map--against data races
+fn take_unused<K,Q,L,M>(map: &mut M, key: &Q, held: usize) -> core::result::Result<SpaceValue<K,L>,LockSpaceRemoveResult>
+	where M: StorageMap<K,SpaceValue<K,L>>, K: Eq + Hash + Borrow<Q>, Q: ?Sized + Eq + Hash
+{
+	let unused=match map.get(key) {
+		Some(arc) => Arc::strong_count(arc)==1+held,
+		None => return Err(LockSpaceRemoveResult::NotFound),
+	};
+	if unused {
+		Ok(map.remove(key).unwrap())
+	} else {
+		Err(LockSpaceRemoveResult::WouldBlock)
+	}
+}
+
+// Like `take_unused()`, but drops the removed entry right away.
+//
+// IMPORTANT: The caller must hold the outer lock
+fn try_remove_internal<K,Q,L,M>(map: &mut M, key: &Q, held: usize) -> LockSpaceRemoveResult
+	where M: StorageMap<K,SpaceValue<K,L>>, K: Eq + Hash + Borrow<Q>, Q: ?Sized + Eq + Hash
+{
+	match take_unused(map,key,held) {
+		Ok(_) => LockSpaceRemoveResult::Success,
+		Err(result) => result,
+	}
+}
+
+// Drop a guard's reference `arc` to an inner lock, removing it from `names` if
+// `cleanup` is `AutoCleanup` and this was the last use. The inner lock must
+// already have been released. The removed entry is returned, so that the
+// caller can dispose of it without holding the outer lock.
+fn release<K: Eq + Hash,L,M: StorageMap<K,SpaceValue<K,L>>>(names: &Mutex<M>, cleanup: Cleanup, arc: SpaceValue<K,L>) -> Option<SpaceValue<K,L>> {
+	let mut removed=None;
+	let mut map=lock_unpoisoned(names); // Acquire outer lock
+	if cleanup==AutoCleanup {
+		// The key lives in the entry, so keep our reference until after the
+		// entry has been removed
+		removed=take_unused(&mut *map,&arc.key,1).ok();
+	}
+	// Drop our reference to inner while holding the outer lock
+	drop(arc);
+	drop(map);
+	// Release outer lock
+	removed
+}
+
+// Acquire an outer lock, recovering it if it's poisoned. The outer lock only
+// protects a map that's changed with single HashMap operations, so a panic
+// while it's held, which can only come from the `Hash`, `Eq` or `Clone`
+// implementation of a key, can't leave it inconsistent.
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+fn lock_unpoisoned<'a,T>(mutex: &'a Mutex<T>) -> MutexGuard<'a,T> {
+	mutex.lock().unwrap_or_else(|err|{
+		mutex.clear_poison();
+		err.into_inner()
+	})
+}
+
+#[cfg(any(feature="spin",feature="parking_lot"))]
+fn lock_unpoisoned<'a,T>(mutex: &'a Mutex<T>) -> MutexGuard<'a,T> {
+	mutex.lock()
+}
+
+// Wait on `condvar`, which is paired with the mutex of `guard`, ignoring
+// poison like `lock_unpoisoned()`
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+fn wait_unpoisoned<'a,T>(condvar: &Condvar, guard: MutexGuard<'a,T>) -> MutexGuard<'a,T> {
+	condvar.wait(guard).unwrap_or_else(|err|err.into_inner())
+}
+
+#[cfg(feature="parking_lot")]
+fn wait_unpoisoned<'a,T>(condvar: &Condvar, mut guard: MutexGuard<'a,T>) -> MutexGuard<'a,T> {
+	condvar.wait(&mut guard);
+	guard
+}
+
+// Like `wait_unpoisoned()`, but returns at `deadline` at the latest
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+fn wait_unpoisoned_until<'a,T>(condvar: &Condvar, guard: MutexGuard<'a,T>, deadline: Instant) -> MutexGuard<'a,T> {
+	match condvar.wait_timeout(guard,deadline.saturating_duration_since(Instant::now())) {
+		Ok((guard,_)) => guard,
+		Err(err) => err.into_inner().0,
+	}
+}
+
+#[cfg(feature="parking_lot")]
+fn wait_unpoisoned_until<'a,T>(condvar: &Condvar, mut guard: MutexGuard<'a,T>, deadline: Instant) -> MutexGuard<'a,T> {
+	condvar.wait_until(&mut guard,deadline);
+	guard
+}
+
+// Only the `_checked()` lock methods report deadlocks. The other blocking lock
+// methods would wait forever, so they panic instead.
+fn deadlocked<K>(cycle: &[K]) -> ! {
+	panic!("deadlock: waiting for this key would close a cycle of {} keys, use the _checked() lock methods to handle this",cycle.len())
+}
+
+// Hash bytes for the lock files and shared-memory tables. Unlike the hashers
+// of HashMap, this gives the same result in every process.
+#[cfg(all(any(feature="file",feature="shm"),unix))]
+fn fnv1a(bytes: &[u8]) -> u64 {
+	let mut hash=0xcbf29ce484222325u64;
+	for &b in bytes {
+		hash^=b as u64;
+		hash=hash.wrapping_mul(0x100000001b3);
+	}
+	hash
+}
+
+// A hook that is called with every value removed from a LockSpace
+type EvictHook<K,V> = Arc<dyn Fn(K,V) + Send + Sync>;
+
+// Entries that have been removed from a LockSpace while holding the outer
+// lock. When this is dropped, the removed values are passed to the `on_evict`
+// hook of the space.
+//
+// IMPORTANT: This must be dropped after the outer lock is released. Declare it
+// before the guard of the outer lock, so that it's dropped after it.
+struct Evicted<'a,K: 'a + Eq + Hash,V: 'a,S: 'a + Storage<K>> {
+	space: &'a LockSpace<K,V,S>,
+	entries: Vec<LockSpaceValue<K,V>>,
+}
+
+impl<'a,K: Eq + Hash,V,S: Storage<K>> Evicted<'a,K,V,S> {
+	fn new(space: &'a LockSpace<K,V,S>) -> Evicted<'a,K,V,S> {
+		Evicted{space,entries:Vec::new()}
+	}
+
+	// Like `try_remove_internal()`, but keeps the removed entry. `map` is the
+	// map of `shard`.
+	//
+	// IMPORTANT: The caller must hold the outer lock
+	fn try_remove<Q>(&mut self, shard: &Shard<K,V,S>, map: &mut LockSpaceMap<K,V,S>, key: &Q, held: usize) -> LockSpaceRemoveResult
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		match take_unused(map,key,held) {
+			Ok(arc) => {
+				#[cfg(feature="std")]
+				if arc.lock.lru_seq.load(Ordering::Relaxed)!=0 {
+					if let Some(ref mut lru)=*shard.lru() {
+						lru.remove(&arc.lock);
+					}
+				}
+				#[cfg(not(feature="std"))]
+				let _=shard;
+				self.entries.push(arc);
+				LockSpaceRemoveResult::Success
+			},
+			Err(result) => result,
+		}
+	}
+}
+
+// A placeholder for a key whose value is being created without holding the
+// outer lock. Other lockers of the key wait until the placeholder is removed.
+// If the value couldn't be created, for example because the initializer failed
+// or panicked, the placeholder is removed when it's dropped.
+struct Placeholder<'a,K: 'a + Eq + Hash,M: 'a> {
+	names: &'a Mutex<M>,
+	initializing: &'a Initializing<K>,
+	// `None` once the value has been inserted
+	key: Option<K>,
+}
+
+impl<'a,K: Eq + Hash + Clone,M> Placeholder<'a,K,M> {
+	// IMPORTANT: The caller must hold the outer lock `names`
+	fn new(names: &'a Mutex<M>, initializing: &'a Initializing<K>, key: K) -> Placeholder<'a,K,M> {
+		lock_unpoisoned(&initializing.keys).insert(key.clone());
+		Placeholder{names,initializing,key:Some(key)}
+	}
+
+	// Replace the placeholder with `lock` in `map`, which is the map of
+	// `names`, and return a new reference to it.
+	//
+	// IMPORTANT: The caller must hold the outer lock
+	fn insert<L>(mut self, map: &mut M, lock: L) -> SpaceValue<K,L>
+		where M: StorageMap<K,SpaceValue<K,L>>
+	{
+		let key=self.key.take().unwrap();
+		self.initializing.remove(&key);
+		let arc=Arc::new(Named{key:key.clone(),lock});
+		map.insert(key,arc.clone(/*Invariants OK*/));
+		arc
+	}
+}
+
+impl<'a,K: Eq + Hash,M> Drop for Placeholder<'a,K,M> {
+	fn drop(&mut self) {
+		if let Some(ref key)=self.key {
+			let map=lock_unpoisoned(self.names); // Acquire outer lock
+			self.initializing.remove(key);
+			drop(map);
+			// Release outer lock
+		}
+	}
+}
+
+// The keys of a space whose values are being created, see `Placeholder`
+struct Initializing<K> {
+	// Only changed while holding the outer lock
+	keys: Mutex<HashSet<K>>,
+	// Signalled whenever a placeholder is removed, paired with the outer
+	// lock
+	#[cfg(all(feature="std",not(feature="spin")))]
+	released: Condvar,
+}
+
+impl<K: Eq + Hash> Initializing<K> {
+	fn new() -> Initializing<K> {
+		Initializing{
+			keys:Mutex::new(HashSet::new()),
+			#[cfg(all(feature="std",not(feature="spin")))]
+			released:Condvar::new(),
+		}
+	}
+
+	// Whether the value for `key` is being created
+	//
+	// IMPORTANT: The caller must hold the outer lock
+	fn contains<Q>(&self, key: &Q) -> bool
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		lock_unpoisoned(&self.keys).contains(key)
+	}
+
+	// Remove the placeholder for `key` and wake up anyone waiting for it
+	//
+	// IMPORTANT: The caller must hold the outer lock
+	fn remove(&self, key: &K) {
+		lock_unpoisoned(&self.keys).remove(key);
+		#[cfg(all(feature="std",not(feature="spin")))]
+		self.released.notify_all();
+	}
+
+	// Release the outer lock `names`, held as `map`, until a placeholder is
+	// removed. The outer lock is held again when this returns.
+	#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+	fn wait<'m,T>(&self, _names: &'m Mutex<T>, map: MutexGuard<'m,T>) -> MutexGuard<'m,T> {
+		self.released.wait(map).unwrap_or_else(|err|err.into_inner())
+	}
+
+	#[cfg(feature="spin")]
+	fn wait<'m,T>(&self, names: &'m Mutex<T>, map: MutexGuard<'m,T>) -> MutexGuard<'m,T> {
+		// spin has no way to wait for a release, so just back off
+		drop(map);
+		#[cfg(feature="std")]
+		thread::yield_now();
+		#[cfg(not(feature="std"))]
+		core::hint::spin_loop();
+		names.lock()
+	}
+
+	#[cfg(feature="parking_lot")]
+	fn wait<'m,T>(&self, _names: &'m Mutex<T>, mut map: MutexGuard<'m,T>) -> MutexGuard<'m,T> {
+		self.released.wait(&mut map);
+		map
+	}
+
+	// Like `wait()`, but gives up at `deadline`, returning `None`
+	#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+	fn wait_until<'m,T>(&self, _names: &'m Mutex<T>, map: MutexGuard<'m,T>, deadline: Instant) -> Option<MutexGuard<'m,T>> {
+		let now=Instant::now();
+		if now>=deadline {
+			return None;
+		}
+		match self.released.wait_timeout(map,deadline-now) {
+			Ok((map,_)) => Some(map),
+			Err(err) => Some(err.into_inner().0),
+		}
+	}
+
+	#[cfg(all(feature="std",feature="spin"))]
+	fn wait_until<'m,T>(&self, names: &'m Mutex<T>, map: MutexGuard<'m,T>, deadline: Instant) -> Option<MutexGuard<'m,T>> {
+		if Instant::now()>=deadline {
+			return None;
+		}
+		Some(self.wait(names,map))
+	}
+
+	#[cfg(feature="parking_lot")]
+	fn wait_until<'m,T>(&self, _names: &'m Mutex<T>, mut map: MutexGuard<'m,T>, deadline: Instant) -> Option<MutexGuard<'m,T>> {
+		if self.released.wait_until(&mut map,deadline).timed_out() {
+			return None;
+		}
+		Some(map)
+	}
+}
+
+// Find the inner lock for `key` in `map`, which is the outer lock `names`, or
+// create it by calling `initial` if it does not exist, and return a new
+// reference to it.
+//
+// `initial` is called without holding the outer lock. Meanwhile, a
+// placeholder in `initializing` makes other lockers of `key` call their
+// `wait`, which releases the outer lock until the placeholder is removed, or
+// gives up. If `initial` fails, nothing is inserted and its error is returned.
+// The outer lock is held again when this returns successfully.
+fn get_or_create<'m,K,Q,L,M,E,C,W>(names: &'m Mutex<M>, initializing: &'m Initializing<K>, mut map: MutexGuard<'m,M>, key: &Q, initial: C, mut wait: W) -> core::result::Result<SpaceFound<'m,M,K,L>,E>
+	where M: StorageMap<K,SpaceValue<K,L>>, K: Eq + Hash + Clone + Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> core::result::Result<L,E>,
+		W: FnMut(MutexGuard<'m,M>) -> core::result::Result<MutexGuard<'m,M>,E>
+{
+	loop {
+		if let Some(target)=map.get(key).cloned(/*Invariants OK*/) {
+			return Ok((map,target));
+		}
+		if !initializing.contains(key) {
+			let placeholder=Placeholder::new(names,initializing,key.to_owned());
+			drop::<MutexGuard<_>>(map); // Release outer lock
+			// On failure, dropping the placeholder rolls it back
+			let lock=initial()?;
+			map=lock_unpoisoned(names); // Acquire outer lock
+			let target=placeholder.insert(&mut *map,lock);
+			return Ok((map,target));
+		}
+		map=wait(map)?;
+	}
+}
+
+impl<'a,K: Eq + Hash,V,S: Storage<K>> Drop for Evicted<'a,K,V,S> {
+	fn drop(&mut self) {
+		for arc in self.entries.drain(..) {
+			if let Some(ref hook)=self.space.on_evict {
+				// Nobody else has a reference to a removed entry anymore
+				if let Ok(named)=Arc::try_unwrap(arc) {
+					hook(named.key,named.lock.into_value());
+				}
+			}
+		}
+	}
+}
+
+/// A `LockSpace<K,V,S>` holds many values of type `V`, keyed by `K`, each of
+/// which has its own lock, like a `Mutex<V>`.
+///
+/// All accesses to the internal value must go through one of the lock methods.
+///
+/// See the crate documentation for an example.
+///
+/// # Key parameters
+/// Most of the `LockSpace<K,V,S>` methods take a `key: K`, which is only used to
+/// look up the value. The `_ref` variants of those methods take a borrowed
+/// form of the key instead, like `&str` for `String` keys, and only create an
+/// owned `K` if a new value needs to be inserted.
+///
+/// # Sharding
+/// Every operation briefly locks the map that holds the key. By default, a
+/// LockSpace consists of a single map, which means that many threads using
+/// unrelated keys will still contend on that lock. Use `with_shards()` to
+/// split the space into multiple independently locked maps.
+///
+/// Each shard upholds the cleanup invariants on its own. A thread that waits
+/// for a key doesn't hold the lock of its map while waiting. Earlier versions
+/// of this crate did hold it. That changed so that a thread blocked on one
+/// key can't keep the holder of that key from locking other keys in the same
+/// shard.
+///
+/// # Storage
+/// The maps are created by the storage backend `S`, which by default is a
+/// `HashMap` with std's hasher. Use `with_storage()` to plug in a different
+/// one, see the `storage` module.
+///
+/// # Deadlock detection
+/// A space created with `with_deadlock_detection()` keeps track of which
+/// thread holds which key, and which key each blocked thread is waiting for.
+/// If `lock_checked()` would wait for a key that is held by a thread which is
+/// (indirectly) waiting for a key held by the current thread, it returns a
+/// `Deadlock` error instead of blocking forever. So do the other `_checked()`
+/// methods, like `lock_many_checked()`. Their counterparts without `_checked`,
+/// like `lock()`, panic in that case. The timed and non-blocking lock
+/// methods don't report deadlocks, since they don't wait forever.
+///
+/// # Lock order validation
+/// With the `lockdep` feature, every `lock()` checks the order in which locks
+/// are acquired, much like the Linux kernel's lockdep. Each key belongs to a
+/// lock class, see `with_lock_class()` and `with_key_classes()`. Whenever a
+/// thread locks a key of class B while holding a key of class A, the order
+/// A -> B is recorded. If the opposite order was seen before, directly or via
+/// other classes, `lock()` panics, even if no deadlock happened in that run.
+/// Locking multiple keys of the same class is not checked.
+///
+/// # Metrics
+/// With the `metrics` feature, every space counts acquisitions, contention,
+/// wait times and hold times, per key and in total. See `stats()` and the
+/// `metrics` module.
+pub struct LockSpace<K: Eq + Hash,V,S: Storage<K>=HashStorage> {
+	shards: Arc<LockSpaceShards<K,V,S>>,
+	// IMPORTANT: We implement cleanup based on reference-counting. For this
+	// to work, there are a few invariants that must hold, per shard:
+	//   1. The lock space holds 1 reference to the inner lock (`Entry`)
+	//   2. Each lock guard holds 1 reference to the inner lock
+	//   3. Each thread waiting for the inner lock holds 1 reference to it
+	// No. 2 and 3 are guaranteed by only cloning it's Arc when creating a new
+	// lock or starting to wait for one. For synchronization, the number of
+	// references to an inner lock is only changed or evaluated while the
+	// outer lock of its shard is held. The only exception is that a
+	// reference may be dropped by someone who holds another one, since that
+	// can't make the entry unused.
+	//
+	// No. 3 replaces the old rule of acquiring the inner lock while holding
+	// the outer lock. Now, a thread never blocks on an inner lock while
+	// holding an outer lock, see `Shard`.
+	cleanup: Cleanup,
+	#[cfg(feature="std")]
+	eviction: Eviction,
+	on_evict: Option<EvictHook<K,V>>,
+	#[cfg(feature="lockdep")]
+	classes: lockdep::Classifier<K>,
+}
+
+struct LockSpaceShards<K,V,S: Storage<K>> {
+	shards: Vec<Shard<K,V,S>>,
+	// Selects the shard for a key. Only used if there's more than 1 shard.
+	hasher: hash_map::RandomState,
+	// The reference point for the `Entry` timestamps
+	#[cfg(feature="std")]
+	epoch: Instant,
+	// Only present if deadlock detection is enabled. This spans all shards,
+	// since a cycle can span keys in different shards. It is never held while
+	// acquiring another lock.
+	#[cfg(feature="std")]
+	wait_graph: Option<Mutex<WaitGraph<K>>>,
+	// The statistics of the whole space
+	#[cfg(feature="metrics")]
+	metrics: metrics::Counters,
+}
+
+// One of the independently locked parts of a LockSpace. Everything in this
+// crate that talks about "the outer lock" refers to the lock of the shard that
+// the key in question belongs to.
+struct Shard<K,V,S: Storage<K>> {
+	// IMPORTANT: To avoid deadlocks, never block on an inner lock while
+	// holding the outer lock. Instead, take a new reference to the inner lock
+	// while holding the outer lock, then release the outer lock and wait.
+	//
+	// Originally, the rule was the opposite: always acquire the inner lock
+	// while holding the outer lock. But a thread blocked that way keeps the
+	// holder of the inner lock from locking any other key of the shard, so
+	// holding more than one key at a time, as `lock_many()` does, could
+	// deadlock. The old rule made sure that the entry couldn't be removed by
+	// `AutoCleanup` or `try_remove()` while a thread was about to lock it,
+	// which would leave that thread holding an inner lock that's no longer in
+	// the map, while a new one for the same key is handed out. Invariant 3 of
+	// `LockSpace` covers that instead: the reference of the waiting thread
+	// keeps the entry from being unused until it has acquired the lock or
+	// given up.
+	names: Mutex<LockSpaceMap<K,V,S>>,
+	// The keys of this shard whose values are being created
+	initializing: Initializing<K>,
+	// When idle values were last evicted from this shard, in the format of the
+	// `Entry` timestamps. Only changed while holding the outer lock.
+	#[cfg(feature="std")]
+	swept: AtomicU64,
+	// The unused values of this shard, least recently used first, so that
+	// eviction doesn't have to look at every value. `None` until an eviction
+	// policy is set. Only locked while holding the outer lock.
+	#[cfg(feature="std")]
+	lru: Mutex<Option<Lru<K>>>,
+}
+
+// The unused values of a shard in the order they were last released. A value
+// is on the list exactly while only the map references it.
+#[cfg(feature="std")]
+struct Lru<K> {
+	// Keyed by the `last_released` timestamp of the entry, which doesn't
+	// change while it's unused, and the `lru_seq` of the entry, which tells
+	// apart entries released at the same time
+	order: BTreeMap<(u64,u64),K>,
+	next: u64,
+}
+
+#[cfg(feature="std")]
+impl<K> Lru<K> {
+	fn new() -> Lru<K> {
+		Lru{order:BTreeMap::new(),next:0}
+	}
+
+	fn remove<V>(&mut self, entry: &Entry<V>) {
+		let seq=entry.lru_seq.swap(0,Ordering::Relaxed);
+		if seq!=0 {
+			self.order.remove(&(entry.last_released.load(Ordering::Relaxed),seq));
+		}
+	}
+}
+
+#[cfg(feature="std")]
+impl<K: Clone> Lru<K> {
+
+	fn insert<V>(&mut self, named: &Named<K,Entry<V>>) {
+		self.next+=1;
+		named.lock.lru_seq.store(self.next,Ordering::Relaxed);
+		self.order.insert((named.lock.last_released.load(Ordering::Relaxed),self.next),named.key.clone());
+	}
+
+	// The least recently used value
+	fn oldest(&self) -> Option<&K> {
+		self.order.values().next()
+	}
+
+	// The values that were last released at or before `time`
+	fn released_by(&self, time: u64) -> Vec<K> {
+		self.order.range(..=(time,u64::MAX)).map(|(_,key)|key.clone()).collect()
+	}
+}
+
+/// The eviction policy of a `KeepUnused` LockSpace, see
+/// `LockSpace::with_eviction()`.
+///
+/// Values are only ever evicted if no guard holds them and no thread is
+/// waiting for them.
+#[cfg(feature="std")]
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct Eviction {
+	/// Evict the least recently used values if the space holds more than
+	/// this many values.
+	///
+	/// The limit applies to each shard separately: every shard holds at most
+	/// `max_entries` divided by the number of shards, rounding up. So with
+	/// more than one shard, the space as a whole can hold up to `shards-1`
+	/// values more than `max_entries`, and if most keys fall in a few shards,
+	/// values are evicted before the space holds `max_entries` values.
+	pub max_entries: Option<usize>,
+	/// Evict values that haven't been used for this long.
+	pub idle_ttl: Option<Duration>,
+}
+
+pub enum LockSpaceRemoveResult {
+	Success,
+	NotFound,
+	PoisonError,
+	/// `remove()` would block.
+	WouldBlock,
+}
+
+/// The state of a single key of a LockSpace, as returned by
+/// `LockSpace::snapshot()`.
+#[derive(Clone,Debug)]
+pub struct KeySnapshot<K> {
+	pub key: K,
+	/// Whether a guard currently holds the lock.
+	pub locked: bool,
+	/// The number of guards and waiting threads that reference this key.
+	pub references: usize,
+	/// When a guard last acquired the lock, or `None` if it never did.
+	#[cfg(feature="std")]
+	pub last_acquired: Option<Instant>,
+}
+
+// This needs to be implemented manually, since #[derive(Clone)] doesn't
+// understand that the type parameters are only used within the Arc<_>
+impl<K: Eq + Hash,V,S: Storage<K>> Clone for LockSpace<K,V,S> {
+	fn clone(&self) -> LockSpace<K,V,S> {
+		LockSpace{
+			shards:self.shards.clone(),
+			cleanup:self.cleanup,
+			#[cfg(feature="std")]
+			eviction:self.eviction,
+			on_evict:self.on_evict.clone(),
+			#[cfg(feature="lockdep")]
+			classes:self.classes,
+		}
+	}
+}
+
+impl<K: Eq + Hash,V,S: Storage<K>> Shard<K,V,S> {
+
+	fn lock_names<'m>(&'m self) -> LockSpaceNames<'m,K,V,S> {
+		lock_unpoisoned(&self.names)
+	}
+
+	// IMPORTANT: The caller must hold the outer lock
+	#[cfg(feature="std")]
+	fn lru(&self) -> MutexGuard<'_,Option<Lru<K>>> {
+		lock_unpoisoned(&self.lru)
+	}
+
+	// Release the outer lock until a placeholder is removed. The outer lock is
+	// held again when this returns.
+	fn wait_initialized<'m>(&'m self, map: LockSpaceNames<'m,K,V,S>) -> LockSpaceNames<'m,K,V,S> {
+		self.initializing.wait(&self.names,map)
+	}
+
+	// Like `wait_initialized()`, but gives up at `deadline`, returning `None`
+	#[cfg(feature="std")]
+	fn wait_initialized_until<'m>(&'m self, map: LockSpaceNames<'m,K,V,S>, deadline: Instant) -> Option<LockSpaceNames<'m,K,V,S>> {
+		self.initializing.wait_until(&self.names,map,deadline)
+	}
+
+}
+
+
+impl<K: Eq + Hash + Clone,V> LockSpace<K,V> {
+	/// Create a new LockSpace.
+	///
+	/// If `cleanup` is `AutoCleanup`, values will be deleted automatically when
+	/// the last lock is released. Otherwise, values will remain in the space
+	/// until `try_remove()` returns `Success`.
+	pub fn new(cleanup: Cleanup) -> LockSpace<K,V> {
+		Self::with_shards(cleanup,1)
+	}
+
+	/// Create a new LockSpace that is split into `shards` independently locked
+	/// maps. Keys are assigned to a shard based on their hash.
+	///
+	/// This reduces contention when many threads use the space at the same
+	/// time, at the cost of hashing every key one more time. Apart from that,
+	/// the space behaves exactly the same as one created with `new()`.
+	///
+	/// # Panics
+	/// Panics if `shards` is 0.
+	///
+	/// ```
+	/// let space=namedlock::LockSpace::<String,i32>::with_shards(namedlock::AutoCleanup,16);
+	///
+	/// space.with_lock("test".to_owned(),||0,|i|*i+=1).unwrap();
+	/// ```
+	pub fn with_shards(cleanup: Cleanup, shards: usize) -> LockSpace<K,V> {
+		Self::build(cleanup,shards,false,HashStorage::new())
+	}
+
+	/// Create a new LockSpace like `with_shards()`, that also detects
+	/// deadlocks between threads waiting for each other's keys. See the
+	/// `LockSpace` documentation for details.
+	///
+	/// Keeping track of the holders and waiters adds some overhead to every
+	/// lock and unlock. All shards share the same bookkeeping.
+	///
+	/// # Panics
+	/// Panics if `shards` is 0.
+	///
+	/// ```
+	/// use namedlock::lockresult::LockError;
+	///
+	/// let space=namedlock::LockSpace::<String,i32>::with_deadlock_detection(namedlock::AutoCleanup,1);
+	///
+	/// let value=space.lock("test".to_owned(),||0).unwrap();
+	/// match space.lock_checked("test".to_owned(),||0) {
+	///     Err(LockError::Deadlock(cycle)) => assert_eq!(cycle,["test"]),
+	///     _ => panic!("Lock is held by this thread"),
+	/// }
+	/// drop(value);
+	/// ```
+	#[cfg(feature="std")]
+	pub fn with_deadlock_detection(cleanup: Cleanup, shards: usize) -> LockSpace<K,V> {
+		Self::build(cleanup,shards,true,HashStorage::new())
+	}
+}
+
+impl<K: Eq + Hash + Clone,V,S: Storage<K>> LockSpace<K,V,S> {
+	/// Create a new LockSpace like `with_shards()`, that keeps its keys in
+	/// maps created by the storage backend `storage`, see the `storage`
+	/// module.
+	///
+	/// # Panics
+	/// Panics if `shards` is 0.
+	pub fn with_storage(cleanup: Cleanup, shards: usize, storage: S) -> LockSpace<K,V,S> {
+		Self::build(cleanup,shards,false,storage)
+	}
+
+	/// Create a new LockSpace like `with_storage()`, that also detects
+	/// deadlocks like `with_deadlock_detection()`.
+	///
+	/// # Panics
+	/// Panics if `shards` is 0.
+	#[cfg(feature="std")]
+	pub fn with_storage_and_deadlock_detection(cleanup: Cleanup, shards: usize, storage: S) -> LockSpace<K,V,S> {
+		Self::build(cleanup,shards,true,storage)
+	}
+
+	fn build(cleanup: Cleanup, shards: usize, detect_deadlocks: bool, storage: S) -> LockSpace<K,V,S> {
+		assert!(shards>0,"A LockSpace needs at least 1 shard");
+		let shards=(0..shards).map(|_|Shard{
+			names:Mutex::new(storage.new_map()),
+			initializing:Initializing::new(),
+			#[cfg(feature="std")]
+			swept:AtomicU64::new(0),
+			#[cfg(feature="std")]
+			lru:Mutex::new(None),
+		}).collect();
+		#[cfg(not(feature="std"))]
+		let _=detect_deadlocks;
+		LockSpace{
+			shards:Arc::new(LockSpaceShards{
+				shards,
+				hasher:hash_map::RandomState::new(),
+				#[cfg(feature="std")]
+				epoch:Instant::now(),
+				#[cfg(feature="std")]
+				wait_graph:if detect_deadlocks { Some(Mutex::new(WaitGraph::new())) } else { None },
+				#[cfg(feature="metrics")]
+				metrics:Default::default(),
+			}),
+			cleanup,
+			#[cfg(feature="std")]
+			eviction:Eviction::default(),
+			on_evict:None,
+			#[cfg(feature="lockdep")]
+			classes:lockdep::Classifier::Space(lockdep::LockClass::anonymous()),
+		}
+	}
+
+	/// Set the lock class of this space to `name`, for lock order validation.
+	///
+	/// By default, every LockSpace (including its clones) forms its own lock
+	/// class. Spaces with the same name form a single class, which is useful
+	/// if similar spaces are created more than once. Only affects this
+	/// LockSpace and the clones that are made of it afterwards.
+	#[cfg(feature="lockdep")]
+	pub fn with_lock_class(mut self, name: &'static str) -> LockSpace<K,V,S> {
+		self.classes=lockdep::Classifier::Space(lockdep::LockClass::Named(name));
+		self
+	}
+
+	/// Assign the keys of this space to lock classes by name, for lock order
+	/// validation. For example, the keys `tenant/bucket` and `tenant/bucket/object`
+	/// could be assigned to the `bucket` and `object` classes.
+	///
+	/// Only affects this LockSpace and the clones that are made of it
+	/// afterwards.
+	#[cfg(feature="lockdep")]
+	pub fn with_key_classes(mut self, classify: fn(&K) -> &'static str) -> LockSpace<K,V,S> {
+		self.classes=lockdep::Classifier::Key(classify);
+		self
+	}
+
+	/// Call `hook` with the key and value of every value that is removed from
+	/// the space, whether that's by `AutoCleanup`, `try_remove()` or the
+	/// eviction policy. The hook is called after the space has been unlocked
+	/// again, so it may take its time, or even use the space itself.
+	///
+	/// Like `with_lock_class()`, this only affects this LockSpace and the
+	/// clones that are made of it afterwards.
+	///
+	/// ```
+	/// use std::sync::{Arc,Mutex};
+	///
+	/// let flushed=Arc::new(Mutex::new(vec![]));
+	/// let flushed_clone=flushed.clone();
+	/// let space=namedlock::LockSpace::<String,Vec<u8>>::new(namedlock::AutoCleanup)
+	///     .on_evict(move|key,buffer|flushed_clone.lock().unwrap().push((key,buffer)));
+	///
+	/// space.with_lock("log".to_owned(),Vec::new,|buf|buf.extend_from_slice(b"data")).unwrap();
+	/// assert_eq!(*flushed.lock().unwrap(),[("log".to_owned(),b"data".to_vec())]);
+	/// ```
+	pub fn on_evict<F>(mut self, hook: F) -> LockSpace<K,V,S>
+		where F: Fn(K,V) + Send + Sync + 'static
+	{
+		self.on_evict=Some(Arc::new(hook));
+		self
+	}
+
+	/// Set the eviction policy of this space, so that values that are no
+	/// longer in use are removed even though it is a `KeepUnused` space. This
+	/// has no effect on `AutoCleanup` spaces.
+	///
+	/// The policy is applied whenever a key is looked up. Call `evict()` to
+	/// apply it at other times. Like `with_lock_class()`, this only affects
+	/// this LockSpace and the clones that are made of it afterwards.
+	///
+	/// Once a policy is set, every shard of the space keeps its unused values
+	/// in the order they were last used, so that finding the values to evict
+	/// doesn't need to look at the values that stay.
+	///
+	/// ```
+	/// use namedlock::Eviction;
+	///
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused)
+	///     .with_eviction(Eviction{max_entries:Some(1),..Eviction::default()});
+	///
+	/// space.with_lock("a".to_owned(),||0,|_|{}).unwrap();
+	/// space.with_lock("b".to_owned(),||0,|_|{}).unwrap();
+	/// assert!(!space.contains_key("a"));
+	/// ```
+	#[cfg(feature="std")]
+	pub fn with_eviction(mut self, eviction: Eviction) -> LockSpace<K,V,S> {
+		self.eviction=eviction;
+		if self.cleanup==KeepUnused && eviction!=Eviction::default() {
+			// Start keeping track of the unused values, if no other handle
+			// of this space has yet
+			for shard in &self.shards.shards {
+				let map=shard.lock_names(); // Acquire outer lock
+				let mut lru=shard.lru();
+				if lru.is_none() {
+					let lru=lru.get_or_insert_with(Lru::new);
+					for arc in map.values().filter(|arc|Arc::strong_count(arc)==1) {
+						lru.insert(arc);
+					}
+				}
+				// Release outer lock
+			}
+		}
+		self
+	}
+
+	/// Evict all values that are eligible according to the eviction policy
+	/// of this space now, such as values that have become idle since the
+	/// last lookup.
+	#[cfg(feature="std")]
+	pub fn evict(&self) {
+		for shard in &self.shards.shards {
+			let mut evicted=Evicted::new(self);
+			let mut map=shard.lock_names(); // Acquire outer lock
+			self.evict_from(shard,&mut map,true,&mut evicted);
+			// Release outer lock
+		}
+	}
+
+	// Find the shard that `key` belongs to. Since `K: Borrow<Q>`, this is the
+	// same shard for a key and its borrowed forms.
+	fn shard<Q: ?Sized + Hash>(&self, key: &Q) -> &Shard<K,V,S> {
+		let shards=&self.shards.shards;
+		if shards.len()==1 {
+			return &shards[0];
+		}
+		&shards[(self.shards.hasher.hash_one(key) as usize)%shards.len()]
+	}
+
+	// Update the wait-for graph, if deadlock detection is enabled. A poisoned
+	// graph is ignored, like in `release()`.
+	#[cfg(feature="std")]
+	fn track<F,R>(&self, f: F) -> Option<R>
+		where F: FnOnce(&mut WaitGraph<K>) -> R
+	{
+		match self.shards.wait_graph {
+			Some(ref graph) => graph.lock().into_result().ok().map(|mut graph|f(&mut graph)),
+			None => None,
+		}
+	}
+
+	// Record that the current thread acquired the inner lock of `target`, for
+	// introspection, deadlock detection and lock order validation
+	fn acquired(&self, target: &LockSpaceValue<K,V>) {
+		#[cfg(feature="std")]
+		{
+			self.touch(&target.lock.last_acquired);
+			self.track(|graph|graph.acquired(&target.key));
+		}
+		#[cfg(feature="lockdep")]
+		lockdep::acquired(self.classes.class(&target.key));
+		let _=target;
+	}
+
+	// The current time, in the format of the `Entry` timestamps
+	#[cfg(feature="std")]
+	fn now(&self) -> u64 {
+		(self.shards.epoch.elapsed().as_nanos() as u64).saturating_add(1)
+	}
+
+	// Set `timestamp` of an `Entry` to the current time
+	#[cfg(feature="std")]
+	fn touch(&self, timestamp: &AtomicU64) {
+		timestamp.store(self.now(),Ordering::Relaxed);
+	}
+
+	// Record that the current thread released the inner lock of `key`
+	fn released(&self, key: &K) {
+		#[cfg(feature="std")]
+		self.track(|graph|graph.released(key));
+		#[cfg(feature="lockdep")]
+		lockdep::released(self.classes.class(key));
+		let _=key;
+	}
+
+	// Bookkeeping before the current thread blocks on the inner lock of
+	// `target`, which is returned again. Fails with the cycle if that would
+	// deadlock, and panics on a lock order inversion. Either way, `target` is
+	// released first, so that an `AutoCleanup` entry isn't left behind.
+	fn will_wait(&self, shard: &Shard<K,V,S>, target: LockSpaceValue<K,V>) -> core::result::Result<LockSpaceValue<K,V>,Vec<K>> {
+		#[cfg(feature="std")]
+		{
+			if let Some(Err(cycle))=self.track(|graph|graph.start_wait(&target.key)) {
+				self.release(shard,target);
+				return Err(cycle);
+			}
+		}
+		#[cfg(feature="lockdep")]
+		{
+			if let Err(inversion)=lockdep::will_lock(self.classes.class(&target.key)) {
+				self.track(|graph|graph.stop_wait());
+				self.release(shard,target);
+				panic!("{}",inversion);
+			}
+		}
+		Ok(target)
+	}
+
+	// Find the inner lock for `key`, or create it by calling `initial` if it
+	// does not exist, and return a new reference to it, along with the start
+	// of the attempt to acquire it.
+	fn find<Q,C>(&self, shard: &Shard<K,V,S>, key: &Q, initial: C) -> (LockSpaceValue<K,V>,Attempt)
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> V
+	{
+		match self.try_find(shard,key,||Ok::<V,Infallible>(initial())) {
+			Ok(found) => found,
+			Err(never) => match never {},
+		}
+	}
+
+	// Like `find()`, but `initial` may fail, in which case nothing is inserted
+	// and its error is returned.
+	fn try_find<Q,E,C>(&self, shard: &Shard<K,V,S>, key: &Q, initial: C) -> core::result::Result<(LockSpaceValue<K,V>,Attempt),E>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> core::result::Result<V,E>
+	{
+		let mut evicted=Evicted::new(self);
+		let map=shard.lock_names(); // Acquire outer lock
+		let (map,target)=self.get_or_insert(shard,map,key,initial,&mut evicted,|map|Ok(shard.wait_initialized(map)))?;
+		let attempt=Attempt::start(&target);
+		drop::<MutexGuard<_>>(map); // Explicitly release outer lock
+		Ok((target,attempt))
+	}
+
+	// Find the inner lock for `key` in `map`, which is the outer lock of
+	// `shard`, or create it by calling `initial` if it does not exist, and
+	// return a new reference to it. Also applies the eviction policy to the
+	// shard.
+	//
+	// `initial` is called without holding the outer lock, see
+	// `get_or_create()`.
+	fn get_or_insert<'m,Q,E,C,W>(&self, shard: &'m Shard<K,V,S>, map: LockSpaceNames<'m,K,V,S>, key: &Q, initial: C, evicted: &mut Evicted<K,V,S>, wait: W) -> core::result::Result<LockSpaceFound<'m,K,V,S>,E>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> core::result::Result<V,E>,
+			W: FnMut(LockSpaceNames<'m,K,V,S>) -> core::result::Result<LockSpaceNames<'m,K,V,S>,E>
+	{
+		let (mut map,target)=get_or_create(&shard.names,&shard.initializing,map,key,||Ok(Entry::new(initial()?)),wait)?;
+		// Our reference protects `target` from being evicted, so it's no
+		// longer unused
+		#[cfg(feature="std")]
+		{
+			if target.lock.lru_seq.load(Ordering::Relaxed)!=0 {
+				if let Some(ref mut lru)=*shard.lru() {
+					lru.remove(&target.lock);
+				}
+			}
+			self.evict_from(shard,&mut map,false,evicted);
+		}
+		#[cfg(not(feature="std"))]
+		let _=evicted;
+		Ok((map,target))
+	}
+
+	// Apply the eviction policy to `map`, which is the map of `shard`. Idle
+	// values are only looked for once per `idle_ttl`, unless `sweep` is set.
+	//
+	// IMPORTANT: The caller must hold the outer lock
+	#[cfg(feature="std")]
+	fn evict_from(&self, shard: &Shard<K,V,S>, map: &mut LockSpaceMap<K,V,S>, sweep: bool, evicted: &mut Evicted<K,V,S>) {
+		if self.cleanup==AutoCleanup {
+			return;
+		}
+		if let Some(ttl)=self.eviction.idle_ttl {
+			let now=self.now();
+			let ttl=ttl.as_nanos().min(u64::MAX as u128) as u64;
+			if (sweep || now.saturating_sub(shard.swept.load(Ordering::Relaxed))>=ttl) && now>=ttl {
+				shard.swept.store(now,Ordering::Relaxed);
+				let idle=match *shard.lru() {
+					Some(ref lru) => lru.released_by(now-ttl),
+					None => Vec::new(),
+				};
+				for key in idle {
+					evicted.try_remove(shard,map,&key,0);
+				}
+			}
+		}
+		if let Some(max)=self.eviction.max_entries {
+			let max=max.div_ceil(self.shards.shards.len());
+			while map.len()>max {
+				let oldest=shard.lru().as_ref().and_then(|lru|lru.oldest().cloned());
+				// Values on the LRU list are unused, so removing them succeeds
+				let removed=match oldest {
+					Some(key) => evicted.try_remove(shard,map,&key,0),
+					// Everything is in use
+					None => break,
+				};
+				debug_assert!(matches!(removed,LockSpaceRemoveResult::Success));
+				if !matches!(removed,LockSpaceRemoveResult::Success) {
+					break;
+				}
+			}
+		}
+	}
+
+	// Drop a reference to the inner lock of `shard` like `release()`, passing
+	// the removed value to the `on_evict` hook, if any. For `KeepUnused`, a
+	// value that's no longer used is put on the LRU list of the shard instead.
+	fn release(&self, shard: &Shard<K,V,S>, arc: LockSpaceValue<K,V>) {
+		let mut evicted=Evicted::new(self);
+		#[cfg(feature="std")]
+		{
+			if self.cleanup==KeepUnused {
+				let map=shard.lock_names(); // Acquire outer lock
+				// Only the map and we reference the value
+				if Arc::strong_count(&arc)==2 {
+					if let Some(ref mut lru)=*shard.lru() {
+						lru.insert(&arc);
+					}
+				}
+				// Drop our reference to inner while holding the outer lock
+				drop(arc);
+				drop(map);
+				// Release outer lock
+				return;
+			}
+		}
+		evicted.entries.extend(release(&shard.names,self.cleanup,arc));
+	}
+
+	// Make a guard for the inner lock `guard`, which was just acquired
+	fn guard<'a>(&'a self, shard: &'a Shard<K,V,S>, guard: EntryGuard<K,V>, attempt: Attempt) -> LockSpaceGuard<'a,K,V,S> {
+		self.acquired(guard.target());
+		#[cfg(feature="metrics")]
+		let since=self.record_acquired(&guard.target().lock,attempt);
+		#[cfg(not(feature="metrics"))]
+		let _=attempt;
+		LockSpaceGuard{
+			owner:self,
+			shard,
+			guard:Some(guard),
+			#[cfg(feature="metrics")]
+			since,
+			_not_send:PhantomData,
+		}
+	}
+
+	// Record the acquisition of `entry` in the statistics, returning when it
+	// was acquired
+	#[cfg(feature="metrics")]
+	fn record_acquired(&self, entry: &Entry<V>, attempt: Attempt) -> Instant {
+		let now=Instant::now();
+		let wait=now-attempt.start;
+		entry.metrics.acquired(wait,attempt.contended);
+		self.shards.metrics.acquired(wait,attempt.contended);
+		now
+	}
+
+	// Record the release of `entry` in the statistics
+	#[cfg(feature="metrics")]
+	fn record_released(&self, entry: &Entry<V>, since: Instant) {
+		let hold=since.elapsed();
+		entry.metrics.released(hold);
+		self.shards.metrics.released(hold);
+	}
+
+	// Like `guard()`, but for the result of acquiring the inner lock. The
+	// guard is returned in the error if the inner lock is poisoned.
+	fn checked_guard<'a>(&'a self, shard: &'a Shard<K,V,S>, result: LockSpaceInnerResult<K,V>, attempt: Attempt) -> Result<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>> {
+		match result {
+			Ok(guard) => Ok(self.guard(shard,guard,attempt)),
+			Err(err) => Err(err.map_guard(|guard|self.guard(shard,guard,attempt))),
+		}
+	}
+
+	// Block until the inner lock of `target` is acquired. While waiting,
+	// `target` is an additional reference to the inner lock. Once the inner
+	// lock is acquired, it can be dropped since the guard holds its own
+	// reference.
+	fn wait_for<'a>(&'a self, shard: &'a Shard<K,V,S>, target: LockSpaceValue<K,V>, attempt: Attempt) -> Result<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>> {
+		self.checked_guard(shard,EntryGuard::lock(&target),attempt)
+	}
+
+	/// Find the object by `key`, or create it by calling `initial` if it does
+	/// not exist. Then, lock it and return a LockSpaceGuard over the object.
+	/// Once the guard is dropped, its object is unlocked, and if `AutoCleanup`
+	/// is specified for this space, removed if this is the last use.
+	///
+	/// `initial` doesn't block access to other keys while it runs. Other
+	/// threads locking the same key wait until it returns. If it panics,
+	/// nothing is inserted, and the next thread to lock the key creates the
+	/// object instead.
+	///
+	/// If a guard of the object was dropped during a panic, the object is
+	/// poisoned. It's locked anyway, and the guard is returned in the
+	/// `Poisoned` error, until `clear_poison()` is called. Other objects in
+	/// the space are not affected.
+	///
+	/// ```
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// let value=space.lock("test".to_owned(),||0);
+	/// *value.unwrap()+=1;
+	/// let value=space.lock("test".to_owned(),||0);
+	/// assert_eq!(*value.unwrap(),1);
+	/// ```
+	///
+	/// # Panics
+	/// If deadlock detection is enabled for this space, panics if waiting for
+	/// the object would deadlock. Use `lock_checked()` to handle that instead.
+	pub fn lock<'a,C>(&'a self, key: K, initial: C) -> Result<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>>
+		where C: FnOnce() -> V
+	{
+		self.lock_ref(&key,initial)
+	}
+
+	/// Like `lock()`, but takes a borrowed form of the key. The key is only
+	/// converted to an owned `K` if the object needs to be created.
+	///
+	/// ```
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// *space.lock_ref("test",||0).unwrap()+=1;
+	/// assert_eq!(*space.lock_ref("test",||0).unwrap(),1);
+	/// ```
+	pub fn lock_ref<'a,Q,C>(&'a self, key: &Q, initial: C) -> Result<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> V
+	{
+		match self.lock_checked_ref(key,initial) {
+			Ok(guard) => Ok(guard),
+			Err(LockError::Poisoned(err)) => Err(err),
+			Err(LockError::Deadlock(cycle)) => deadlocked(&cycle),
+		}
+	}
+
+	/// Like `lock()`, but if deadlock detection is enabled for this space and
+	/// waiting for the object would deadlock, returns a `Deadlock` error
+	/// instead of panicking. See `with_deadlock_detection()` for an example.
+	pub fn lock_checked<'a,C>(&'a self, key: K, initial: C) -> LockSpaceResult<LockSpaceGuard<'a,K,V,S>,K,LockSpaceGuard<'a,K,V,S>>
+		where C: FnOnce() -> V
+	{
+		self.lock_checked_ref(&key,initial)
+	}
+
+	/// Like `lock_checked()`, but takes a borrowed form of the key, like
+	/// `lock_ref()`.
+	pub fn lock_checked_ref<'a,Q,C>(&'a self, key: &Q, initial: C) -> LockSpaceResult<LockSpaceGuard<'a,K,V,S>,K,LockSpaceGuard<'a,K,V,S>>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash + ToOwned<Owned=K>, C: FnOnce() -> V
+	{
+		let shard=self.shard(key);
+		let (target,attempt)=self.find(shard,key,initial);
+		let target=self.will_wait(shard,target).map_err(LockError::Deadlock)?;
+		Ok(self.wait_for(shard,target,attempt)?)
+	}
+
+	/// Like `lock()`, but the object is created by calling `initial`, which
+	/// may fail. If it does, nothing is inserted into the space, and its error
+	/// is returned as `InitError::Init`. `initial` is not called if the object
+	/// exists already.
+	///
+	/// ```
+	/// use std::ffi::OsString;
+	/// use std::fs::File;
+	/// use namedlock::InitError;
+	///
+	/// let space=namedlock::LockSpace::<OsString,File>::new(namedlock::AutoCleanup);
+	///
+	/// let path=OsString::from("/nonexistent/namedlock-test");
+	/// match space.lock_or_try_init(path.clone(),||File::open(&path)) {
+	///     Err(InitError::Init(err)) => assert_eq!(err.kind(),std::io::ErrorKind::NotFound),
+	///     _ => panic!("the file must not exist"),
+	/// }
+	/// assert!(space.is_empty());
+	/// ```
+	///
+	/// # Panics
+	/// If deadlock detection is enabled for this space, panics if waiting for
+	/// the object would deadlock. Use `lock_or_try_init_checked()` to handle
+	/// that instead.
+	pub fn lock_or_try_init<'a,E,C>(&'a self, key: K, initial: C) -> InitResult<LockSpaceGuard<'a,K,V,S>,E>
+		where C: FnOnce() -> core::result::Result<V,E>
+	{
+		match self.lock_or_try_init_checked(key,initial) {
+			Ok(guard) => Ok(guard),
+			Err(CheckedInitError::Poisoned(err)) => Err(InitError::Poisoned(err)),
+			Err(CheckedInitError::Init(err)) => Err(InitError::Init(err)),
+			Err(CheckedInitError::Deadlock(cycle)) => deadlocked(&cycle),
+		}
+	}
+
+	/// Like `lock_or_try_init()`, but if deadlock detection is enabled for
+	/// this space and waiting for the object would deadlock, returns a
+	/// `Deadlock` error instead of panicking.
+	pub fn lock_or_try_init_checked<'a,E,C>(&'a self, key: K, initial: C) -> CheckedInitResult<LockSpaceGuard<'a,K,V,S>,E,K>
+		where C: FnOnce() -> core::result::Result<V,E>
+	{
+		let shard=self.shard(&key);
+		let (target,attempt)=self.try_find(shard,&key,||initial().map_err(CheckedInitError::Init))?;
+		let target=self.will_wait(shard,target).map_err(CheckedInitError::Deadlock)?;
+		Ok(self.wait_for(shard,target,attempt)?)
+	}
+
+	/// Like `lock()`, but returns an OwnedLockSpaceGuard, which doesn't
+	/// borrow the space. It can be stored anywhere, and sent to and dropped on
+	/// another thread.
+	///
+	/// Waiting for the value is checked by deadlock detection like with
+	/// `lock()`, but once acquired, the guard doesn't count as held by the
+	/// current thread.
+	///
+	/// ```
+	/// use std::thread;
+	///
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// let mut value=space.lock_owned("test".to_owned(),||0).unwrap();
+	/// *value+=1;
+	/// thread::spawn(move||*value+=1).join().unwrap();
+	/// assert_eq!(*space.lock("test".to_owned(),||0).unwrap(),2);
+	/// ```
+	///
+	/// # Panics
+	/// If deadlock detection is enabled for this space, panics if waiting for
+	/// the object would deadlock. Use `lock_owned_checked()` to handle that
+	/// instead.
+	#[cfg(feature="std")]
+	pub fn lock_owned<C>(&self, key: K, initial: C) -> Result<OwnedLockSpaceGuard<K,V,S>,OwnedLockSpaceGuard<K,V,S>>
+		where C: FnOnce() -> V
+	{
+		match self.lock_owned_checked(key,initial) {
+			Ok(guard) => Ok(guard),
+			Err(LockError::Poisoned(err)) => Err(err),
+			Err(LockError::Deadlock(cycle)) => deadlocked(&cycle),
+		}
+	}
+
+	/// Like `lock_owned()`, but if deadlock detection is enabled for this
+	/// space and waiting for the object would deadlock, returns a `Deadlock`
+	/// error instead of panicking.
+	#[cfg(feature="std")]
+	pub fn lock_owned_checked<C>(&self, key: K, initial: C) -> LockSpaceResult<OwnedLockSpaceGuard<K,V,S>,K,OwnedLockSpaceGuard<K,V,S>>
+		where C: FnOnce() -> V
+	{
+		let shard=self.shard(&key);
+		let (target,attempt)=self.find(shard,&key,initial);
+		let target=self.will_wait(shard,target).map_err(LockError::Deadlock)?;
+		let result=EntryGuard::lock(&target);
+		// The lock won't be held by this thread
+		self.track(|graph|graph.stop_wait());
+		self.touch(&target.lock.last_acquired);
+		#[cfg(feature="metrics")]
+		let since=self.record_acquired(&target.lock,attempt);
+		#[cfg(not(feature="metrics"))]
+		let _=attempt;
+		// The guard holds its own reference
+		drop(target);
+		let owned=|guard|OwnedLockSpaceGuard{
+			space:self.clone(),
+			guard:Some(guard),
+			#[cfg(feature="metrics")]
+			since,
+		};
+		match result {
+			Ok(guard) => Ok(owned(guard)),
+			Err(err) => Err(LockError::Poisoned(err.map_guard(owned))),
+		}
+	}
+
+	/// Find the object by `key`, or create it by calling `initial` if it does
+	/// not exist. Then, try to lock it without blocking and return a
+	/// LockSpaceGuard over the object.
+	///
+	/// If the object is currently locked by another guard, `WouldBlock` is
+	/// returned. The object is still created if it did not exist, but it is
+	/// subject to the same cleanup rules as with `lock()`.
+	///
+	/// ```
+	/// use namedlock::lockresult::TryLockError;
+	///
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// let value=space.try_lock("test".to_owned(),||0).unwrap();
+	/// match space.try_lock("test".to_owned(),||0) {
+	///     Err(TryLockError::WouldBlock) => {},
+	///     _ => panic!("Lock should be held"),
+	/// }
+	/// drop(value);
+	/// assert!(space.try_lock("test".to_owned(),||0).is_ok());
+	/// ```
+	pub fn try_lock<'a,C>(&'a self, key: K, initial: C) -> TryLockResult<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>>
+		where C: FnOnce() -> V
+	{
+		let shard=self.shard(&key);
+		let mut evicted=Evicted::new(self);
+		let map=shard.lock_names(); // Acquire outer lock
+
+		// Don't wait for the value to be created by someone else
+		let (mut map,target)=self.get_or_insert(shard,map,&key,||Ok(initial()),&mut evicted,|_|Err(TryLockError::WouldBlock))?;
+		let attempt=Attempt::start(&target);
+		// Try to acquire inner lock. The guard takes its own reference, so our
+		// reference is dropped while still holding the outer lock.
+		let result=EntryGuard::try_lock(&target);
+		drop(target);
+		match result {
+			Ok(guard) => {
+				drop::<MutexGuard<_>>(map); // Explicitly release outer lock
+				self.checked_guard(shard,Ok(guard),attempt).map_err(TryLockError::Poisoned)
+			},
+			Err(TryLockError::Poisoned(err)) => {
+				drop::<MutexGuard<_>>(map); // Explicitly release outer lock
+				self.checked_guard(shard,Err(err),attempt).map_err(TryLockError::Poisoned)
+			},
+			Err(_) => {
+				if self.cleanup==AutoCleanup {
+					evicted.try_remove(shard,&mut map,&key,0);
+				}
+				Err(TryLockError::WouldBlock)
+			}
+		}
+		// Release outer lock
+	}
+
+	/// Find the object by `key`, or create it by calling `initial` if it does
+	/// not exist. Then, lock it, waiting at most `timeout`, and return a
+	/// LockSpaceGuard over the object.
+	///
+	/// If the object could not be locked in time, `TimedOut` is returned.
+	///
+	/// ```
+	/// use std::time::Duration;
+	/// use namedlock::lockresult::TimedLockError;
+	///
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// let value=space.lock("test".to_owned(),||0).unwrap();
+	/// match space.lock_timeout("test".to_owned(),||0,Duration::from_millis(10)) {
+	///     Err(TimedLockError::TimedOut) => {},
+	///     _ => panic!("Lock should be held"),
+	/// }
+	/// drop(value);
+	/// ```
+	#[cfg(feature="std")]
+	pub fn lock_timeout<'a,C>(&'a self, key: K, initial: C, timeout: Duration) -> TimedLockResult<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>>
+		where C: FnOnce() -> V
+	{
+		match Instant::now().checked_add(timeout) {
+			Some(deadline) => self.lock_deadline(key,initial,deadline),
+			// The deadline is too far in the future to represent, so it will
+			// never pass. Like other timed locks, this doesn't take part in
+			// deadlock detection.
+			None => {
+				let shard=self.shard(&key);
+				let (target,attempt)=self.find(shard,&key,initial);
+				self.wait_for(shard,target,attempt).map_err(TimedLockError::Poisoned)
+			},
+		}
+	}
+
+	/// Find the object by `key`, or create it by calling `initial` if it does
+	/// not exist. Then, lock it, waiting until `deadline` at most, and return
+	/// a LockSpaceGuard over the object.
 	///
-	/// let value=space.lock("test".to_owned(),||0);
-	/// *value.unwrap()+=1;
-	/// let value=space.lock("test".to_owned(),||0);
-	/// assert_eq!(*value.unwrap(),1);
-	pub fn lock<'a,C>(&'a self, key: K, initial: C) -> Result<LockSpaceGuard<'a,K,V>>
+	/// If the object could not be locked in time, `TimedOut` is returned. If
+	/// `AutoCleanup` is specified for this space and no one else is using the
+	/// object anymore at that point, it is removed.
+	#[cfg(feature="std")]
+	pub fn lock_deadline<'a,C>(&'a self, key: K, initial: C, deadline: Instant) -> TimedLockResult<LockSpaceGuard<'a,K,V,S>,LockSpaceGuard<'a,K,V,S>>
 		where C: FnOnce() -> V
 	{
-		let mut map=try!(self.names.lock().into_result()); // Acquire outer lock
+		let shard=self.shard(&key);
+		let mut evicted=Evicted::new(self);
+		let map=shard.lock_names(); // Acquire outer lock
 
-		let target={
-			map.entry(key.clone())
-				.or_insert_with(|| Some(Arc::new(Mutex::new(initial()))))
-				.clone(/*Invariants OK*/).unwrap()
-		};
-		let guard=try!(target.owned_lock()); // Acquire inner lock, moving our reference
+		// While waiting, `target` is an additional reference to the inner
+		// lock. Once the inner lock is acquired, it can be dropped since the
+		// guard holds its own reference.
+		let (map,target)=self.get_or_insert(shard,map,&key,||Ok(initial()),&mut evicted,|map|shard.wait_initialized_until(map,deadline).ok_or(TimedLockError::TimedOut))?;
+		let attempt=Attempt::start(&target);
 		drop::<MutexGuard<_>>(map); // Explicitly release outer lock
-
-		Ok(LockSpaceGuard{owner:self,key:Some(key),guard:Some(guard)})
+		match EntryGuard::lock_until(&target,deadline) {
+			Ok(guard) => {
+				drop(target);
+				self.checked_guard(shard,Ok(guard),attempt).map_err(TimedLockError::Poisoned)
+			},
+			Err(TimedLockError::Poisoned(err)) => {
+				drop(target);
+				self.checked_guard(shard,Err(err),attempt).map_err(TimedLockError::Poisoned)
+			},
+			Err(TimedLockError::TimedOut) => {
+				// This drops our reference while holding the outer lock
+				self.release(shard,target);
+				Err(TimedLockError::TimedOut)
+			}
+		}
 	}
 
 	/// Find the object by `key`, or create it by calling `initial` if it does
@@ -322,24 +2463,96 @@ impl<K: Eq + Hash + Clone,V> LockSpace<K,V> {
 	pub fn with_lock<F,R,C>(&self, key: K, initial: C, f: F) -> Result<R>
 		where C: FnOnce() -> V, F: FnOnce(&mut V) -> R
 	{
-		self.lock(key,initial).map(|mut guard|f(&mut guard))
+		match self.lock(key,initial) {
+			Ok(mut guard) => Ok(f(&mut guard)),
+			Err(err) => Err(err.map_guard(drop)),
+		}
 	}
 
-	// IMPORTANT: The caller must hold the outer lock
-	// to guard target--and therefore map--against data races
-	fn try_remove_internal<'a>(mut entry: LockSpaceEntry<'a,K,V>) -> LockSpaceRemoveResult
-	{
-		let arc=entry.get_mut().take().unwrap();
-		match Arc::try_unwrap(arc) {
-			Ok(_) => {
-				entry.remove();
-				return LockSpaceRemoveResult::Success
-			},
-			Err(arc) => {
-				*entry.get_mut()=Some(arc);
-				return LockSpaceRemoveResult::WouldBlock
-			}
+	/// Lock all objects in `keys`, creating them by calling `initial` if they
+	/// do not exist, and return a LockSetGuard over all of them.
+	///
+	/// The keys are locked one by one in sorted order, regardless of the order
+	/// they're passed in. Duplicate keys are only locked once. As long as all
+	/// threads that need to hold multiple locks at the same time use this
+	/// function, they can't deadlock each other.
+	///
+	/// ```
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// space.with_lock("from".to_owned(),||10,|_|{}).unwrap();
+	/// {
+	///     let mut set=space.lock_many(vec!["to".to_owned(),"from".to_owned()],|_|0).unwrap();
+	///     set["from"]-=3;
+	///     set["to"]+=3;
+	/// }
+	/// assert_eq!(*space.lock("to".to_owned(),||0).unwrap(),3);
+	/// ```
+	///
+	/// # Panics
+	/// If deadlock detection is enabled for this space, panics if waiting for
+	/// one of the objects would deadlock. Use `lock_many_checked()` to handle
+	/// that instead.
+	pub fn lock_many<'a,I,C>(&'a self, keys: I, initial: C) -> Result<LockSetGuard<'a,K,V,S>>
+		where I: IntoIterator<Item=K>, K: Ord, C: FnMut(&K) -> V
+	{
+		match self.lock_many_checked(keys,initial) {
+			Ok(set) => Ok(set),
+			Err(LockError::Poisoned(err)) => Err(err),
+			Err(LockError::Deadlock(cycle)) => deadlocked(&cycle),
+		}
+	}
+
+	/// Like `lock_many()`, but if deadlock detection is enabled for this space
+	/// and waiting for one of the objects would deadlock, returns a `Deadlock`
+	/// error instead of panicking. The objects locked so far are released.
+	///
+	/// Locking in sorted order only prevents deadlocks between threads that
+	/// do so for all their locks. This still detects deadlocks with threads
+	/// that don't, or with locks the current thread already holds.
+	pub fn lock_many_checked<'a,I,C>(&'a self, keys: I, mut initial: C) -> LockSpaceResult<LockSetGuard<'a,K,V,S>,K>
+		where I: IntoIterator<Item=K>, K: Ord, C: FnMut(&K) -> V
+	{
+		let mut keys: Vec<K>=keys.into_iter().collect();
+		keys.sort();
+		keys.dedup();
+
+		let mut guards=Vec::with_capacity(keys.len());
+		for key in &keys {
+			// If this fails, the guards acquired so far are released
+			guards.push(self.lock_checked_ref(key,||initial(key)).map_err(|err|err.map_guard(drop))?);
+		}
+		Ok(LockSetGuard{keys,guards})
+	}
+
+	/// Clear the poisoned state of the object of `key`, after a guard of it
+	/// was dropped during a panic. Use this once the object has been repaired
+	/// through the guard in the `PoisonError`, or is known to be fine.
+	///
+	/// With the `parking_lot` feature, panics don't poison objects.
+	///
+	#[cfg_attr(not(feature="parking_lot"),doc="```")]
+	#[cfg_attr(feature="parking_lot",doc="```ignore")]
+	/// use std::panic::{self,AssertUnwindSafe};
+	///
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// let _=panic::catch_unwind(AssertUnwindSafe(||space.with_lock("test".to_owned(),||0,|i|{*i=-1; panic!()})));
+	/// match space.lock("test".to_owned(),||0) {
+	///     Err(err) => *err.into_inner()=0,
+	///     _ => panic!("The lock must be poisoned"),
+	/// }
+	/// space.clear_poison("test");
+	/// assert_eq!(*space.lock("test".to_owned(),||0).unwrap(),0);
+	/// ```
+	pub fn clear_poison<Q>(&self, key: &Q)
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		let map=self.shard(key).lock_names(); // Acquire outer lock
+		if let Some(target)=map.get(key) {
+			target.lock.clear_poison();
 		}
+		// Release outer lock
 	}
 
 	/// Find the object by `key`, then delete it if it is not actively being
@@ -348,18 +2561,148 @@ impl<K: Eq + Hash + Clone,V> LockSpace<K,V> {
 	/// This is only useful if this `LockSpace` is of the `KeepUnused` kind.
 	pub fn try_remove(&self, key: K) -> LockSpaceRemoveResult
 	{
-		match self.names.lock().into_result() {
-			Ok(mut map) => { // Acquired outer lock
-				if let hash_map::Entry::Occupied(entry)=map.entry(key) {
-					Self::try_remove_internal(entry)
-				} else {
-					LockSpaceRemoveResult::NotFound
-				}
-				// Release outer lock
+		self.try_remove_ref(&key)
+	}
+
+	/// Like `try_remove()`, but takes a borrowed form of the key.
+	pub fn try_remove_ref<Q>(&self, key: &Q) -> LockSpaceRemoveResult
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		let mut evicted=Evicted::new(self);
+		let shard=self.shard(key);
+		let mut map=shard.lock_names(); // Acquire outer lock
+		evicted.try_remove(shard,&mut map,key,0)
+		// Release outer lock
+	}
+
+	// Like `try_remove_ref()`, but the value is only removed if `unused`
+	// returns true for it. For values that carry state from one lock to the
+	// next, this checks that state and removes the value atomically.
+	#[cfg(feature="std")]
+	fn try_remove_if<Q,F>(&self, key: &Q, unused: F) -> LockSpaceRemoveResult
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash, F: FnOnce(&V) -> bool
+	{
+		let mut evicted=Evicted::new(self);
+		let shard=self.shard(key);
+		let mut map=shard.lock_names(); // Acquire outer lock
+		let remove=match map.get(key) {
+			Some(arc) if Arc::strong_count(arc)==1 => if arc.lock.is_poisoned() {
+				return LockSpaceRemoveResult::PoisonError;
+			} else {
+				// SAFETY: No one else holds a reference to the entry, and
+				// by invariant 2 of `LockSpace`, every guard holds one, so no
+				// one holds the inner lock either. No new reference can be
+				// taken while we hold the outer lock.
+				unused(unsafe{&*arc.lock.value.get()})
 			},
-			Err(_) => LockSpaceRemoveResult::PoisonError
+			Some(_) => false,
+			None => return LockSpaceRemoveResult::NotFound,
+		};
+		if remove {
+			evicted.try_remove(shard,&mut map,key,0)
+		} else {
+			LockSpaceRemoveResult::WouldBlock
+		}
+		// Release outer lock
+	}
+
+	// Call `f` on the map of every shard in turn
+	fn each_shard<F>(&self, mut f: F)
+		where F: FnMut(&LockSpaceMap<K,V,S>)
+	{
+		for shard in &self.shards.shards {
+			let map=shard.lock_names(); // Acquire outer lock
+			f(&map);
+			// Release outer lock
 		}
 	}
+
+	/// The keys that are currently in the space.
+	///
+	/// Like the other introspection methods, this looks at one shard at a
+	/// time, so the result may be out of date as soon as it's returned.
+	pub fn keys(&self) -> Vec<K> {
+		let mut keys=Vec::new();
+		self.each_shard(|map|keys.extend(map.values().map(|arc|arc.key.clone())));
+		keys
+	}
+
+	/// The number of keys that are currently in the space.
+	pub fn len(&self) -> usize {
+		let mut len=0;
+		self.each_shard(|map|len+=map.len());
+		len
+	}
+
+	/// Whether the space is currently empty.
+	pub fn is_empty(&self) -> bool {
+		self.len()==0
+	}
+
+	/// Whether `key` is currently in the space.
+	pub fn contains_key<Q>(&self, key: &Q) -> bool
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		let map=self.shard(key).lock_names(); // Acquire outer lock
+		map.contains_key(key)
+		// Release outer lock
+	}
+
+	/// Whether `key` is currently locked by a guard. Returns `false` if the
+	/// key is not in the space.
+	///
+	/// ```
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::AutoCleanup);
+	///
+	/// let value=space.lock("test".to_owned(),||0).unwrap();
+	/// assert!(space.is_locked("test"));
+	/// drop(value);
+	/// assert!(!space.is_locked("test"));
+	/// ```
+	pub fn is_locked<Q>(&self, key: &Q) -> bool
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		let map=self.shard(key).lock_names(); // Acquire outer lock
+		map.get(key).is_some_and(|arc|arc.lock.is_held())
+		// Release outer lock
+	}
+
+	/// The state of every key that is currently in the space, such as whether
+	/// it's locked and how many threads are using it.
+	///
+	/// ```
+	/// let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+	///
+	/// let value=space.lock("test".to_owned(),||0).unwrap();
+	/// let snapshot=space.snapshot();
+	/// assert_eq!(snapshot[0].key,"test");
+	/// assert!(snapshot[0].locked);
+	/// assert_eq!(snapshot[0].references,1);
+	/// ```
+	pub fn snapshot(&self) -> Vec<KeySnapshot<K>> {
+		let mut snapshot=Vec::new();
+		self.each_shard(|map|snapshot.extend(map.values().map(|arc|KeySnapshot{
+			key:arc.key.clone(),
+			locked:arc.lock.is_held(),
+			// Don't count the reference held by the map itself
+			references:Arc::strong_count(arc)-1,
+			#[cfg(feature="std")]
+			last_acquired:match arc.lock.last_acquired.load(Ordering::Relaxed) {
+				0 => None,
+				nanos => Some(self.shards.epoch+Duration::from_nanos(nanos-1)),
+			},
+		})));
+		snapshot
+	}
+
+	/// The lock statistics of the space and of every key that is currently in
+	/// it. See the `metrics` module.
+	#[cfg(feature="metrics")]
+	pub fn stats(&self) -> metrics::SpaceStats<K> {
+		let mut keys=Vec::new();
+		self.each_shard(|map|keys.extend(map.values().map(|arc|(arc.key.clone(),arc.lock.metrics.stats()))));
+		metrics::SpaceStats{total:self.shards.metrics.stats(),keys}
+	}
 }
 
 #[cfg(test)]
@@ -368,6 +2711,7 @@ mod tests {
 	use std::thread;
 	use std::sync::Arc;
 	use super::*;
+	use lockresult::TryLockError;
 
 	#[cfg(feature="spin")]      const TEST_THREADS: usize = 50;
 	#[cfg(not(feature="spin"))] const TEST_THREADS: usize = 1000;
@@ -448,4 +2792,651 @@ mod tests {
 		file.read_to_string(&mut buf).unwrap();
 		assert_eq!(count,usize::from_str(&buf).unwrap());
 	}
+
+	#[test]
+	fn sharded() {
+		let space=LockSpace::<usize,usize>::with_shards(KeepUnused,8);
+		let mut threads=vec![];
+
+		for _ in 0..16 {
+			let space_clone=space.clone();
+			threads.push(thread::spawn(move||{
+				for key in 0..64 {
+					*space_clone.lock(key,||0).unwrap()+=1;
+				}
+			}));
+		}
+
+		for t in threads.into_iter() {
+			t.join().unwrap();
+		}
+
+		for key in 0..64 {
+			assert_eq!(*space.lock(key,||panic!("Value must exist")).unwrap(),16);
+			assert!(matches!(space.try_remove(key),LockSpaceRemoveResult::Success));
+		}
+	}
+
+	#[test]
+	fn lock_ref_clones_only_on_insert() {
+		use std::sync::atomic::{AtomicUsize,Ordering};
+
+		static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+		#[derive(PartialEq,Eq,Hash,Debug)]
+		struct Key(u32);
+
+		impl Clone for Key {
+			fn clone(&self) -> Key {
+				CLONES.fetch_add(1,Ordering::SeqCst);
+				Key(self.0)
+			}
+		}
+
+		let space=LockSpace::<Key,i32>::new(AutoCleanup);
+		let guard=space.lock_ref(&Key(1),||0).unwrap();
+		let inserted=CLONES.load(Ordering::SeqCst);
+		assert!(inserted>0);
+
+		for _ in 0..10 {
+			assert!(space.try_lock(Key(1),||0).is_err());
+			drop(space.lock_ref(&Key(2),||0).unwrap());
+		}
+		drop(guard);
+		let clones=CLONES.load(Ordering::SeqCst);
+		// Key(2) is inserted and cleaned up 10 times, Key(1) not at all
+		assert_eq!(clones-inserted,10*inserted);
+		assert!(matches!(space.try_remove_ref(&Key(1)),LockSpaceRemoveResult::NotFound));
+	}
+
+	#[test]
+	fn nested_lock_while_contended() {
+		use std::time::Duration;
+
+		let space=Arc::new(LockSpace::<String,i32>::new(AutoCleanup));
+		let a=space.lock("a".to_string(),||1).unwrap();
+
+		let space_clone=space.clone();
+		let waiter=thread::spawn(move||*space_clone.lock("a".to_string(),||panic!("Value must exist")).unwrap());
+		thread::sleep(Duration::from_millis(50));
+
+		// The waiter must not stop us from locking something else
+		let b=space.lock("b".to_string(),||2).unwrap();
+		drop(b);
+		drop(a);
+		assert_eq!(waiter.join().unwrap(),1);
+	}
+
+	#[test]
+	fn lock_many() {
+		let space=LockSpace::<String,i32>::with_shards(KeepUnused,4);
+		let keys=["a","b","c"];
+		let mut threads=vec![];
+
+		// Move values around between keys, locking them in different orders
+		for i in 0..TEST_THREADS/10 {
+			let space_clone=space.clone();
+			threads.push(thread::spawn(move||{
+				let from=keys[i%3].to_string();
+				let to=keys[(i+1)%3].to_string();
+				let mut set=space_clone.lock_many(vec![to.clone(),from.clone(),to.clone()],|_|0).unwrap();
+				assert_eq!(set.keys().len(),2);
+				set[&from]-=1;
+				*set.get_mut(&to).unwrap()+=1;
+			}));
+		}
+		for t in threads.into_iter() {
+			t.join().unwrap();
+		}
+
+		let set=space.lock_many(keys.iter().map(|k|k.to_string()),|_|0).unwrap();
+
+		assert_eq!(set.get("a").unwrap()+set.get("b").unwrap()+set["c"],0);
+		assert!(set.get("d").is_none());
+	}
+
+	#[test]
+	fn deadlock_detection() {
+		use std::panic::{self,AssertUnwindSafe};
+		use std::time::Duration;
+		use lockresult::LockError;
+
+		let space=Arc::new(LockSpace::<String,i32>::with_deadlock_detection(AutoCleanup,4));
+		let a=space.lock("a".to_string(),||1).unwrap();
+
+		let space_clone=space.clone();
+		let other=thread::spawn(move||{
+			let b=space_clone.lock("b".to_string(),||2).unwrap();
+			let a=space_clone.lock("a".to_string(),||panic!("Value must exist")).unwrap();
+			*a+*b
+		});
+		thread::sleep(Duration::from_millis(50));
+
+		match space.lock_checked("b".to_string(),||panic!("Value must exist")) {
+			Err(LockError::Deadlock(cycle)) => assert_eq!(cycle,["b","a"]),
+			_ => panic!("Locking b should deadlock"),
+		}
+		match space.lock_many_checked(vec!["b".to_string(),"a0".to_string()],|_|3) {
+			Err(LockError::Deadlock(cycle)) => assert_eq!(cycle,["b","a"]),
+			_ => panic!("Locking b should deadlock"),
+		}
+		// The set acquired so far is released
+		assert!(!space.contains_key("a0"));
+		match space.lock_or_try_init_checked("b".to_string(),||Err(())) {
+			Err(CheckedInitError::Deadlock(cycle)) => assert_eq!(cycle,["b","a"]),
+			_ => panic!("Locking b should deadlock"),
+		}
+		match space.lock_owned_checked("b".to_string(),||panic!("Value must exist")) {
+			Err(LockError::Deadlock(cycle)) => assert_eq!(cycle,["b","a"]),
+			_ => panic!("Locking b should deadlock"),
+		}
+		// The methods that don't report deadlocks panic instead of hanging
+		let result=panic::catch_unwind(AssertUnwindSafe(||space.lock("b".to_string(),||panic!("Value must exist"))));
+		assert!(result.is_err());
+		let result=panic::catch_unwind(AssertUnwindSafe(||space.lock_many(vec!["b".to_string()],|_|panic!("Value must exist"))));
+		assert!(result.is_err());
+		// Resolve the deadlock by backing off
+		drop(a);
+		assert_eq!(other.join().unwrap(),3);
+		assert!(space.lock_many(vec!["a".to_string(),"b".to_string()],|_|0).is_ok());
+	}
+
+	#[test]
+	#[cfg(feature="lockdep")]
+	fn lockdep_inversion_cleanup() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		let a=LockSpace::<u32,()>::new(AutoCleanup).with_lock_class("cleanup a");
+		let b=LockSpace::<u32,()>::new(AutoCleanup).with_lock_class("cleanup b");
+		{
+			let _a=a.lock(0,||()).unwrap();
+			let _b=b.lock(0,||()).unwrap();
+		}
+		let result=panic::catch_unwind(AssertUnwindSafe(||{
+			let _b=b.lock(0,||()).unwrap();
+			let _a=a.lock(0,||()).unwrap();
+		}));
+		assert!(result.is_err());
+		// The entry that was about to be locked was released before panicking
+		assert!(a.is_empty());
+		assert!(b.is_empty());
+	}
+
+	#[test]
+	#[cfg(feature="lockdep")]
+	#[should_panic(expected="lock order inversion: locking test a while holding test c, but the order test a -> test b -> test c was seen before")]
+	fn lockdep_inversion() {
+		let a=LockSpace::<u32,()>::new(AutoCleanup).with_lock_class("test a");
+		let b=LockSpace::<u32,()>::new(AutoCleanup).with_lock_class("test b");
+		let c=LockSpace::<u32,()>::new(AutoCleanup).with_lock_class("test c");
+		{
+			let _a=a.lock(0,||()).unwrap();
+			let _b=b.lock(0,||()).unwrap();
+		}
+		{
+			let _b=b.lock(0,||()).unwrap();
+			let _c=c.lock(0,||()).unwrap();
+		}
+		// This can't deadlock in a single thread, but it could with the above
+		let _c=c.lock(0,||()).unwrap();
+		let _a=a.lock(0,||()).unwrap();
+	}
+
+	#[test]
+	#[cfg(feature="lockdep")]
+	fn lockdep_key_classes() {
+		let space=LockSpace::<String,()>::new(AutoCleanup)
+			.with_key_classes(|key|if key.contains('/') { "test object" } else { "test bucket" });
+		for _ in 0..2 {
+			let _bucket=space.lock("bucket".to_string(),||()).unwrap();
+			// Same class, not checked
+			let _objects=space.lock_many(vec!["bucket/1".to_string(),"bucket/2".to_string()],|_|()).unwrap();
+		}
+		let result=thread::spawn(move||{
+			let _object=space.lock("bucket/1".to_string(),||()).unwrap();
+			let _bucket=space.lock("bucket".to_string(),||()).unwrap();
+		}).join();
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn lock_owned() {
+		use std::time::Duration;
+
+		fn owned<T: Send + Sync + 'static>(t: T) -> T { t }
+
+		let space=LockSpace::<String,i32>::new(AutoCleanup);
+		let mut guard=owned(space.lock_owned("a".to_string(),||1).unwrap());
+		*guard+=1;
+		assert_eq!(guard.key(),"a");
+
+		assert!(matches!(space.try_lock("a".to_string(),||0),Err(TryLockError::WouldBlock)));
+		assert!(matches!(space.lock_timeout("a".to_string(),||0,Duration::from_millis(10)),Err(TimedLockError::TimedOut)));
+
+		let space_clone=space.clone();
+		let waiter=thread::spawn(move||*space_clone.lock("a".to_string(),||panic!("Value must exist")).unwrap());
+		thread::sleep(Duration::from_millis(50));
+
+		// Hand the guard to another thread, which releases it
+		thread::spawn(move||*guard+=1).join().unwrap();
+		assert_eq!(waiter.join().unwrap(),3);
+		assert!(matches!(space.try_remove("a".to_string()),LockSpaceRemoveResult::NotFound));
+	}
+
+	#[test]
+	fn map_guard() {
+		let space=LockSpace::<String,(i32,Vec<i32>)>::new(AutoCleanup);
+
+		let guard=space.lock("a".to_string(),||(1,vec![])).unwrap();
+		let guard=match LockSpaceGuard::try_map(guard,|v|v.1.first_mut()) {
+			Ok(_) => panic!("The vector is empty"),
+			Err(guard) => guard,
+		};
+		let mut list=LockSpaceGuard::map(guard,|v|&mut v.1);
+		list.push(2);
+		let mut first=MappedLockSpaceGuard::try_map(list,|v|v.first_mut()).ok().unwrap();
+		*first+=1;
+		assert!(space.try_lock("a".to_string(),||(0,vec![])).is_err());
+		drop(first);
+
+		// The value was cleaned up after the mapped guard was dropped
+		assert!(matches!(space.try_remove("a".to_string()),LockSpaceRemoveResult::NotFound));
+		let guard=space.lock("a".to_string(),||(1,vec![3])).unwrap();
+		let slice: MappedLockSpaceGuard<_,_,[i32]>=LockSpaceGuard::map(guard,|v|&mut v.1[..]);
+		assert_eq!(&*slice,[3]);
+	}
+
+	#[test]
+	#[cfg(not(feature="spin"))]
+	fn wait_while() {
+		use std::time::Duration;
+		use std::sync::mpsc;
+
+		let space=LockSpace::<String,i32>::new(AutoCleanup);
+		let (tx,rx)=mpsc::channel();
+		let mut threads=vec![];
+		for _ in 0..4 {
+			let space_clone=space.clone();
+			let tx=tx.clone();
+			threads.push(thread::spawn(move||{
+				let mut value=space_clone.lock("a".to_string(),||0).unwrap();
+				tx.send(()).unwrap();
+				value.wait_while(|v|*v==0).unwrap();
+				*value+=1;
+				*value
+			}));
+		}
+		for _ in 0..4 {
+			rx.recv().unwrap();
+		}
+
+		// The waiters don't hold the lock, but keep the value alive
+		let mut value=space.lock("a".to_string(),||panic!("Value must exist")).unwrap();
+		*value=1;
+		value.notify_all();
+		drop(value);
+		let mut results: Vec<i32>=threads.into_iter().map(|t|t.join().unwrap()).collect();
+		results.sort();
+		assert_eq!(results,[2,3,4,5]);
+
+		let mut value=space.lock("b".to_string(),||0).unwrap();
+		assert!(value.wait_timeout_while(Duration::from_millis(10),|v|*v==0).unwrap());
+		assert!(!value.wait_timeout_while(Duration::from_millis(10),|v|*v==1).unwrap());
+	}
+
+	#[test]
+	#[cfg(not(feature="spin"))]
+	fn wait_while_owned() {
+		use std::time::Duration;
+
+		let space=LockSpace::<String,i32>::new(AutoCleanup);
+		let space_clone=space.clone();
+		let mut value=space.lock("a".to_string(),||0).unwrap();
+		let waiter=thread::spawn(move||{
+			let mut value=space_clone.lock_owned("a".to_string(),||0).unwrap();
+			// Hand the guard to another thread, which notifies the waiter
+			thread::spawn(move||{
+				thread::sleep(Duration::from_millis(50));
+				*value=1;
+				value.notify_all();
+			}).join().unwrap();
+		});
+		value.wait_while(|v|*v==0).unwrap();
+		assert_eq!(*value,1);
+		drop(value);
+		waiter.join().unwrap();
+	}
+
+	#[test]
+	#[cfg(all(not(feature="spin"),not(feature="parking_lot")))]
+	fn lock_owned_poisoned() {
+		let space=LockSpace::<String,i32>::new(KeepUnused);
+		let value=space.lock_owned("a".to_string(),||0).unwrap();
+		// The guard is dropped during a panic on another thread
+		let result=thread::spawn(move||{
+			let _value=value;
+			panic!("Holder panicked");
+		}).join();
+		assert!(result.is_err());
+
+		assert!(space.lock("a".to_string(),||0).is_err());
+		assert!(space.lock_owned("a".to_string(),||0).is_err());
+	}
+
+	#[test]
+	#[cfg(not(feature="spin"))]
+	fn wait_while_lock_timeout() {
+		use std::time::{Duration,Instant};
+
+		let space=LockSpace::<String,bool>::new(AutoCleanup);
+		let mut value=space.lock("a".to_string(),||false).unwrap();
+		let space_clone=space.clone();
+		let locker=thread::spawn(move||{
+			let start=Instant::now();
+			let result=space_clone.lock_timeout("a".to_string(),||false,Duration::from_secs(5));
+			let elapsed=start.elapsed();
+			// Don't leave the waiter hanging if this fails
+			let mut value=result.unwrap_or_else(|_|space_clone.lock("a".to_string(),||false).unwrap());
+			*value=true;
+			value.notify_all();
+			elapsed
+		});
+		thread::sleep(Duration::from_millis(50));
+
+		// Waiting releases the lock, which the timed locker must notice well
+		// before its deadline
+		value.wait_while(|ready|!*ready).unwrap();
+		assert!(locker.join().unwrap()<Duration::from_secs(1));
+	}
+
+	#[test]
+	fn introspection() {
+		let space=LockSpace::<String,i32>::with_shards(KeepUnused,4);
+		assert!(space.is_empty());
+		space.with_lock("a".to_string(),||0,|_|{}).unwrap();
+		let guard=space.lock("b".to_string(),||0).unwrap();
+
+		let mut keys=space.keys();
+		keys.sort();
+		assert_eq!(keys,["a","b"]);
+		assert_eq!(space.len(),2);
+		assert!(space.contains_key("a"));
+		assert!(!space.contains_key("c"));
+		assert!(!space.is_locked("a"));
+		assert!(space.is_locked("b"));
+		assert!(!space.is_locked("c"));
+
+		let mut snapshot=space.snapshot();
+		snapshot.sort_by(|a,b|a.key.cmp(&b.key));
+		assert_eq!((snapshot[0].locked,snapshot[0].references),(false,0));
+		assert_eq!((snapshot[1].locked,snapshot[1].references),(true,1));
+		assert!(snapshot[0].last_acquired.unwrap()<=snapshot[1].last_acquired.unwrap());
+		drop(guard);
+
+		let _guard=space.lock_owned("a".to_string(),||0).unwrap();
+		assert!(space.is_locked("a"));
+	}
+
+	#[test]
+	#[cfg(feature="metrics")]
+	fn metrics() {
+		use std::time::Duration;
+
+		let space=LockSpace::<String,i32>::new(AutoCleanup);
+		let guard=space.lock("a".to_string(),||0).unwrap();
+		let space_clone=space.clone();
+		let waiter=thread::spawn(move||{
+			let _guard=space_clone.lock("a".to_string(),||0).unwrap();
+			space_clone.stats()
+		});
+		thread::sleep(Duration::from_millis(50));
+		drop(guard);
+		let stats=waiter.join().unwrap();
+		assert_eq!(stats.keys.len(),1);
+		assert_eq!((stats.keys[0].1.acquisitions,stats.keys[0].1.contended),(2,1));
+		assert!(stats.keys[0].1.max_hold>=Duration::from_millis(50));
+
+		// The key is gone, but the totals remain
+		assert!(space.try_lock("b".to_string(),||0).is_ok());
+		let stats=space.stats();
+		assert!(stats.keys.is_empty());
+		assert_eq!((stats.total.acquisitions,stats.total.contended),(3,1));
+		assert!(stats.total.max_wait>=Duration::from_millis(50));
+		assert!(stats.total.total_hold>=stats.total.max_hold);
+	}
+
+	#[test]
+	fn eviction() {
+		use std::time::Duration;
+
+		let space=LockSpace::<String,i32>::new(KeepUnused)
+			.with_eviction(Eviction{max_entries:Some(4),idle_ttl:Some(Duration::from_millis(50))});
+		let a=space.lock("a".to_string(),||0).unwrap();
+		for key in ["b","c","d","b","e","f"] {
+			space.with_lock(key.to_string(),||0,|_|{}).unwrap();
+		}
+		// Locked values are never evicted, and unused ones are evicted in LRU
+		// order
+		let mut keys=space.keys();
+		keys.sort();
+		assert_eq!(keys,["a","b","e","f"]);
+
+		thread::sleep(Duration::from_millis(60));
+		space.evict();
+		assert_eq!(space.keys(),["a"]);
+		drop(a);
+
+		// AutoCleanup spaces aren't affected
+		let space=LockSpace::<String,i32>::new(AutoCleanup)
+			.with_eviction(Eviction{max_entries:Some(0),idle_ttl:None});
+		let _a=space.lock("a".to_string(),||0).unwrap();
+		let _b=space.lock("b".to_string(),||0).unwrap();
+		assert_eq!(space.len(),2);
+	}
+
+	#[test]
+	fn eviction_lru() {
+		let space=LockSpace::<String,i32>::new(KeepUnused);
+		for key in ["a","b","c"] {
+			space.with_lock(key.to_string(),||0,|_|{}).unwrap();
+		}
+		// Values from before the policy was set are evicted too, and using a
+		// value through another handle counts
+		let evicting=space.clone().with_eviction(Eviction{max_entries:Some(3),idle_ttl:None});
+		space.with_lock("a".to_string(),||0,|_|{}).unwrap();
+		evicting.with_lock("d".to_string(),||0,|_|{}).unwrap();
+		let mut keys=space.keys();
+		keys.sort();
+		assert_eq!(keys,["a","c","d"]);
+
+		let c=space.lock("c".to_string(),||0).unwrap();
+		evicting.with_lock("e".to_string(),||0,|_|{}).unwrap();
+		let mut keys=space.keys();
+		keys.sort();
+		assert_eq!(keys,["c","d","e"]);
+		drop(c);
+		evicting.with_lock("f".to_string(),||0,|_|{}).unwrap();
+		let mut keys=space.keys();
+		keys.sort();
+		assert_eq!(keys,["c","e","f"]);
+	}
+
+	#[test]
+	fn on_evict() {
+		use std::sync::Weak;
+		use std::sync::mpsc::channel;
+
+		let (tx,rx)=channel();
+		// The hook runs after the outer lock is released, so it may use the
+		// space itself
+		let space=Arc::new_cyclic(|space: &Weak<LockSpace<String,i32>>|{
+			let space=space.clone();
+			LockSpace::new(AutoCleanup).on_evict(move|key,value|{
+				let len=space.upgrade().map(|space|space.len());
+				tx.send((key,value,len)).unwrap();
+			})
+		});
+
+		space.with_lock("a".to_string(),||1,|v|*v+=1).unwrap();
+		assert_eq!(rx.try_recv().unwrap(),("a".to_string(),2,Some(0)));
+
+		let space=LockSpace::<String,i32>::new(KeepUnused).with_eviction(Eviction{max_entries:Some(1),idle_ttl:None});
+		let (tx,rx)=channel();
+		let space=space.on_evict(move|key,value|tx.send((key,value)).unwrap());
+		space.with_lock("b".to_string(),||3,|_|{}).unwrap();
+		assert!(rx.try_recv().is_err());
+		space.with_lock("c".to_string(),||4,|_|{}).unwrap();
+		assert_eq!(rx.try_recv().unwrap(),("b".to_string(),3));
+		assert!(matches!(space.try_remove("c".to_string()),LockSpaceRemoveResult::Success));
+		assert_eq!(rx.try_recv().unwrap(),("c".to_string(),4));
+		assert!(rx.try_recv().is_err());
+	}
+
+	#[test]
+	fn lock_or_try_init() {
+		let space=LockSpace::<String,i32>::new(KeepUnused);
+		match space.lock_or_try_init("a".to_string(),||Err("failed")) {
+			Err(InitError::Init("failed")) => {},
+			_ => panic!("initializer must fail"),
+		}
+		assert!(!space.contains_key("a"));
+
+		*space.lock_or_try_init("a".to_string(),||Ok::<_,()>(1)).unwrap()+=1;
+		let value=space.lock_or_try_init("a".to_string(),||-> core::result::Result<i32,()> {panic!("Value must exist")});
+		assert_eq!(*value.unwrap(),2);
+	}
+
+	#[test]
+	fn initialize_outside_outer_lock() {
+		use std::sync::mpsc::channel;
+
+		let space=Arc::new(LockSpace::<String,i32>::with_shards(KeepUnused,1));
+		let (started_tx,started_rx)=channel();
+		let (finish_tx,finish_rx)=channel::<()>();
+		let space_clone=space.clone();
+		let initializer=thread::spawn(move||{
+			space_clone.with_lock("slow".to_string(),||{
+				started_tx.send(()).unwrap();
+				finish_rx.recv().unwrap();
+				1
+			},|v|*v).unwrap()
+		});
+		started_rx.recv().unwrap();
+
+		// Other keys in the same shard aren't blocked by the initializer
+		space.with_lock("fast".to_string(),||2,|_|{}).unwrap();
+		assert!(matches!(space.try_lock("slow".to_string(),||panic!("Value is being created")),Err(TryLockError::WouldBlock)));
+
+		// Lockers of the same key wait for the initializer
+		let space_clone=space.clone();
+		let waiter=thread::spawn(move||space_clone.with_lock("slow".to_string(),||panic!("Value is being created"),|v|*v).unwrap());
+		finish_tx.send(()).unwrap();
+		assert_eq!(initializer.join().unwrap(),1);
+		assert_eq!(waiter.join().unwrap(),1);
+
+		// A panicking initializer is rolled back, without poisoning the space
+		let space_clone=space.clone();
+		let result=thread::spawn(move||space_clone.with_lock("panic".to_string(),||panic!("Initializer panicked"),|_|{})).join();
+		assert!(result.is_err());
+		assert!(!space.contains_key("panic"));
+		space.with_lock("panic".to_string(),||3,|v|assert_eq!(*v,3)).unwrap();
+	}
+
+	#[test]
+	#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+	fn poison_recovery() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		// A panic in Clone happens while holding the outer lock
+		#[derive(PartialEq,Eq,Hash,Debug)]
+		struct Key(bool);
+		impl Clone for Key {
+			fn clone(&self) -> Key {
+				assert!(!self.0,"Clone panicked");
+				Key(self.0)
+			}
+		}
+
+		let space=LockSpace::<Key,i32>::with_shards(KeepUnused,1);
+		let result=panic::catch_unwind(AssertUnwindSafe(||space.with_lock(Key(false),||0,|v|{*v=1; panic!("Holder panicked")})));
+		assert!(result.is_err());
+		match space.lock(Key(false),||0) {
+			Err(mut err) => **err.get_mut()=2,
+			_ => panic!("Lock must be poisoned"),
+		}
+		assert!(matches!(space.try_lock(Key(false),||0),Err(TryLockError::Poisoned(_))));
+		space.clear_poison(&Key(false));
+		assert_eq!(*space.lock(Key(false),||0).unwrap(),2);
+
+		// It doesn't poison the space or the other keys in it
+		let result=panic::catch_unwind(AssertUnwindSafe(||space.with_lock(Key(true),||0,|_|{})));
+		assert!(result.is_err());
+		assert_eq!(space.keys(),[Key(false)]);
+		assert_eq!(*space.lock_owned(Key(false),||0).unwrap(),2);
+		assert_eq!(*space.lock(Key(false),||0).unwrap(),2);
+	}
+
+	#[test]
+	fn panic_isolation() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		let space=LockSpace::<String,i32>::with_shards(AutoCleanup,1);
+		let other=space.lock("other".to_string(),||1).unwrap();
+
+		// A panicking initializer only affects its own key
+		let result=panic::catch_unwind(AssertUnwindSafe(||space.with_lock("init".to_string(),||panic!("Initializer panicked"),|_|{})));
+		assert!(result.is_err());
+		assert_eq!(space.keys(),["other".to_string()]);
+		space.with_lock("init".to_string(),||2,|v|assert_eq!(*v,2)).unwrap();
+
+		// A panicking guard holder still releases and cleans up its key
+		let result=panic::catch_unwind(AssertUnwindSafe(||space.with_lock("held".to_string(),||3,|_|panic!("Holder panicked"))));
+		assert!(result.is_err());
+		assert!(!space.contains_key("held"));
+		assert_eq!(*other,1);
+		drop(other);
+		assert!(space.is_empty());
+		space.with_lock("other".to_string(),||4,|v|assert_eq!(*v,4)).unwrap();
+	}
+
+	#[test]
+	fn try_lock_would_block() {
+		let space=Arc::new(LockSpace::<String,i32>::new(AutoCleanup));
+		let guard=space.lock("test".to_string(),||1).unwrap();
+
+		let space_clone=space.clone();
+		let result=thread::spawn(move||{
+			matches!(space_clone.try_lock("test".to_string(),||panic!("Value must exist")),Err(TryLockError::WouldBlock))
+		}).join().unwrap();
+		assert!(result);
+		drop(guard);
+
+		// The failed attempt must not have kept the value alive
+		space.with_lock("test".to_string(),||2,|v|assert_eq!(*v,2)).unwrap();
+	}
+
+	#[test]
+	fn lock_timeout() {
+		use std::time::Duration;
+
+		let space=Arc::new(LockSpace::<String,i32>::new(AutoCleanup));
+		let guard=space.lock("test".to_string(),||1).unwrap();
+
+		let space_clone=space.clone();
+		let result=thread::spawn(move||{
+			matches!(space_clone.lock_timeout("test".to_string(),||panic!("Value must exist"),Duration::from_millis(50)),Err(TimedLockError::TimedOut))
+		}).join().unwrap();
+		assert!(result);
+
+		let space_clone=space.clone();
+		let waiter=thread::spawn(move||{
+			*space_clone.lock_timeout("test".to_string(),||panic!("Value must exist"),Duration::from_secs(60)).unwrap()
+		});
+		thread::sleep(Duration::from_millis(50));
+		drop(guard);
+		assert_eq!(waiter.join().unwrap(),1);
+
+		// The waiters must not have kept the value alive
+		space.with_lock("test".to_string(),||2,|v|assert_eq!(*v,2)).unwrap();
+	}
 }
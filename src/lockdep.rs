@@ -0,0 +1,158 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+// Lock order validation, like the Linux kernel's lockdep.
+//
+// Every lock belongs to a lock class. Whenever a thread blocks on a lock of
+// class B while holding a lock of class A, the order A -> B is recorded in a
+// global graph. If B -> A was recorded before, possibly through other classes,
+// the two threads could deadlock each other, even if they didn't this time.
+
+use std::cell::RefCell;
+use std::collections::{HashMap,HashSet};
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize,Ordering};
+
+#[derive(Clone,Copy,PartialEq,Eq,Hash)]
+pub enum LockClass {
+	// The default class of a LockSpace, unique to it and its clones
+	Anonymous(usize),
+	Named(&'static str),
+}
+
+impl LockClass {
+	pub fn anonymous() -> LockClass {
+		static NEXT: AtomicUsize = AtomicUsize::new(0);
+		LockClass::Anonymous(NEXT.fetch_add(1,Ordering::Relaxed))
+	}
+}
+
+impl fmt::Display for LockClass {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			LockClass::Anonymous(id) => write!(fmt,"LockSpace #{}",id),
+			LockClass::Named(name) => fmt.write_str(name),
+		}
+	}
+}
+
+// Determines the class of the keys of a LockSpace
+pub enum Classifier<K> {
+	Space(LockClass),
+	Key(fn(&K) -> &'static str),
+}
+
+// These need to be implemented manually, since #[derive] would require K to
+// implement them as well
+impl<K> Clone for Classifier<K> {
+	fn clone(&self) -> Classifier<K> {
+		*self
+	}
+}
+
+impl<K> Copy for Classifier<K> {}
+
+impl<K> Classifier<K> {
+	pub fn class(&self, key: &K) -> LockClass {
+		match *self {
+			Classifier::Space(class) => class,
+			Classifier::Key(classify) => LockClass::Named(classify(key)),
+		}
+	}
+}
+
+// For each class, the classes that have been acquired while holding it
+static ORDER: Mutex<Option<HashMap<LockClass,HashSet<LockClass>>>> = Mutex::new(None);
+
+thread_local! {
+	// The classes of the locks held by the current thread, in acquisition
+	// order. A class occurs once for every lock held.
+	static HELD: RefCell<Vec<LockClass>> = const { RefCell::new(Vec::new()) };
+}
+
+// Find a chain of recorded orders from `from` to `to`
+fn find_path(order: &HashMap<LockClass,HashSet<LockClass>>, from: LockClass, to: LockClass) -> Option<Vec<LockClass>> {
+	let mut previous=HashMap::new();
+	let mut queue=vec![from];
+	while let Some(class)=queue.pop() {
+		if class==to {
+			let mut path=vec![to];
+			let mut class=to;
+			while let Some(&prev)=previous.get(&class) {
+				path.push(prev);
+				class=prev;
+			}
+			path.reverse();
+			return Some(path);
+		}
+		for &next in order.get(&class).into_iter().flatten() {
+			if next!=from && !previous.contains_key(&next) {
+				previous.insert(next,class);
+				queue.push(next);
+			}
+		}
+	}
+	None
+}
+
+// Validate and record that the current thread is about to block on a lock of
+// `class`. On an order inversion, returns the message to panic with, so that
+// the caller can clean up first.
+//
+// Locks of the same class as `class` are ignored, since the order between
+// them can't be determined from the class alone.
+pub fn will_lock(class: LockClass) -> Result<(),String> {
+	let held=HELD.with(|held|held.borrow().clone());
+	let inversion={
+		let mut order=ORDER.lock().unwrap_or_else(|err|err.into_inner());
+		let order=order.get_or_insert_with(HashMap::new);
+		let mut inversion=None;
+		for &h in held.iter().filter(|&&h|h!=class) {
+			if let Some(path)=find_path(order,class,h) {
+				inversion=Some((h,path));
+				break;
+			}
+			order.entry(h).or_default().insert(class);
+		}
+		inversion
+		// Release ORDER, so that it's not poisoned by the caller's panic
+	};
+	match inversion {
+		Some((h,path)) => {
+			let path=path.iter().map(|c|c.to_string()).collect::<Vec<_>>().join(" -> ");
+			Err(format!("lock order inversion: locking {} while holding {}, but the order {} was seen before",class,h,path))
+		},
+		None => Ok(()),
+	}
+}
+
+// Record that the current thread acquired a lock of `class`
+pub fn acquired(class: LockClass) {
+	HELD.with(|held|held.borrow_mut().push(class));
+}
+
+// Record that the current thread released a lock of `class`
+pub fn released(class: LockClass) {
+	// This may run while thread-local storage is being destroyed
+	let _=HELD.try_with(|held|{
+		let mut held=held.borrow_mut();
+		if let Some(i)=held.iter().rposition(|&h|h==class) {
+			held.remove(i);
+		}
+	});
+}
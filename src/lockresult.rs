@@ -17,16 +17,216 @@
 
 //! A `Result` type very similar to `std::sync::LockResult`.
 use core::fmt;
+#[cfg(not(feature="std"))] use alloc::vec::Vec;
 
-pub struct PoisonError;
+/// An error returned when a lock was poisoned, very similar to
+/// `std::sync::PoisonError`.
+///
+/// A lock is poisoned when a thread panics while holding it. If the lock was
+/// acquired anyway, the error carries the guard `G`, so that the value can be
+/// inspected and repaired. Otherwise, `G` is `()`.
+pub struct PoisonError<G=()> {
+	guard: G,
+}
+
+impl<G> PoisonError<G> {
+	/// Create a PoisonError carrying `guard`.
+	pub fn new(guard: G) -> PoisonError<G> {
+		PoisonError{guard}
+	}
+
+	/// Consume this error, returning the guard.
+	pub fn into_inner(self) -> G {
+		self.guard
+	}
+
+	/// Get a reference to the guard.
+	pub fn get_ref(&self) -> &G {
+		&self.guard
+	}
+
+	/// Get a mutable reference to the guard.
+	pub fn get_mut(&mut self) -> &mut G {
+		&mut self.guard
+	}
 
-impl fmt::Debug for PoisonError {
+	pub(crate) fn map_guard<U,F: FnOnce(G) -> U>(self, f: F) -> PoisonError<U> {
+		PoisonError::new(f(self.guard))
+	}
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
 	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
 		fmt.write_str("PoisonError")
 	}
 }
+
 /// A `Result` type very similar to `std::sync::LockResult`.
 ///
 /// We can't use sync's LockResult because we can't map it's PoisonError inner
-/// guard
-pub type LockResult<T> = Result<T,PoisonError>;
+/// guard. The guard type `G` of the error defaults to `()`, for errors that
+/// don't carry one.
+pub type LockResult<T,G=()> = Result<T,PoisonError<G>>;
+
+/// An enumeration of possible errors of the non-blocking lock methods, very
+/// similar to `std::sync::TryLockError`.
+pub enum TryLockError<G=()> {
+	/// The lock was poisoned because another holder panicked.
+	Poisoned(PoisonError<G>),
+	/// The lock could not be acquired at this time because the operation would
+	/// otherwise block.
+	WouldBlock,
+}
+
+impl<G> From<PoisonError<G>> for TryLockError<G> {
+	fn from(err: PoisonError<G>) -> TryLockError<G> {
+		TryLockError::Poisoned(err)
+	}
+}
+
+impl<G> TryLockError<G> {
+	pub(crate) fn map_guard<U,F: FnOnce(G) -> U>(self, f: F) -> TryLockError<U> {
+		match self {
+			TryLockError::Poisoned(err) => TryLockError::Poisoned(err.map_guard(f)),
+			TryLockError::WouldBlock => TryLockError::WouldBlock,
+		}
+	}
+}
+
+impl<G> fmt::Debug for TryLockError<G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			TryLockError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			TryLockError::WouldBlock => fmt.write_str("WouldBlock"),
+		}
+	}
+}
+
+/// A `Result` type very similar to `std::sync::TryLockResult`.
+pub type TryLockResult<T,G=()> = Result<T,TryLockError<G>>;
+
+/// An enumeration of possible errors of the timed lock methods, such as
+/// `LockSpace::lock_timeout()`.
+pub enum TimedLockError<G=()> {
+	/// The lock was poisoned because another holder panicked.
+	Poisoned(PoisonError<G>),
+	/// The lock could not be acquired before the deadline passed.
+	TimedOut,
+}
+
+impl<G> From<PoisonError<G>> for TimedLockError<G> {
+	fn from(err: PoisonError<G>) -> TimedLockError<G> {
+		TimedLockError::Poisoned(err)
+	}
+}
+
+impl<G> fmt::Debug for TimedLockError<G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			TimedLockError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			TimedLockError::TimedOut => fmt.write_str("TimedOut"),
+		}
+	}
+}
+
+/// A `Result` type for the timed lock methods.
+pub type TimedLockResult<T,G=()> = Result<T,TimedLockError<G>>;
+
+/// An enumeration of possible errors of `LockSpace::lock_checked()`.
+pub enum LockError<K,G=()> {
+	/// The lock was poisoned because another holder panicked.
+	Poisoned(PoisonError<G>),
+	/// The lock could not be acquired because waiting for it would deadlock.
+	/// Only returned if deadlock detection is enabled for the `LockSpace`.
+	///
+	/// Contains the keys that form the cycle, starting with the requested key.
+	/// Each next key is the one that the holder of the previous key is waiting
+	/// for, and the holder of the last key is the current thread.
+	Deadlock(Vec<K>),
+}
+
+impl<K,G> From<PoisonError<G>> for LockError<K,G> {
+	fn from(err: PoisonError<G>) -> LockError<K,G> {
+		LockError::Poisoned(err)
+	}
+}
+
+impl<K,G> LockError<K,G> {
+	pub(crate) fn map_guard<U,F: FnOnce(G) -> U>(self, f: F) -> LockError<K,U> {
+		match self {
+			LockError::Poisoned(err) => LockError::Poisoned(err.map_guard(f)),
+			LockError::Deadlock(cycle) => LockError::Deadlock(cycle),
+		}
+	}
+}
+
+impl<K: fmt::Debug,G> fmt::Debug for LockError<K,G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			LockError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			LockError::Deadlock(ref cycle) => write!(fmt,"Deadlock({:?})",cycle),
+		}
+	}
+}
+
+/// A `Result` type for `LockSpace::lock_checked()`.
+pub type LockSpaceResult<T,K,G=()> = Result<T,LockError<K,G>>;
+
+/// An enumeration of possible errors of `LockSpace::lock_or_try_init()`.
+pub enum InitError<E,G=()> {
+	/// The lock was poisoned because another holder panicked.
+	Poisoned(PoisonError<G>),
+	/// The object did not exist, and creating it failed. Nothing was inserted
+	/// into the space.
+	Init(E),
+}
+
+impl<E,G> From<PoisonError<G>> for InitError<E,G> {
+	fn from(err: PoisonError<G>) -> InitError<E,G> {
+		InitError::Poisoned(err)
+	}
+}
+
+impl<E: fmt::Debug,G> fmt::Debug for InitError<E,G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			InitError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			InitError::Init(ref err) => write!(fmt,"Init({:?})",err),
+		}
+	}
+}
+
+/// A `Result` type for `LockSpace::lock_or_try_init()`.
+pub type InitResult<T,E> = Result<T,InitError<E,T>>;
+
+/// An enumeration of possible errors of
+/// `LockSpace::lock_or_try_init_checked()`.
+pub enum CheckedInitError<E,K,G=()> {
+	/// The lock was poisoned because another holder panicked.
+	Poisoned(PoisonError<G>),
+	/// The object did not exist, and creating it failed. Nothing was inserted
+	/// into the space.
+	Init(E),
+	/// The lock could not be acquired because waiting for it would deadlock.
+	/// Contains the cycle, like `LockError::Deadlock`.
+	Deadlock(Vec<K>),
+}
+
+impl<E,K,G> From<PoisonError<G>> for CheckedInitError<E,K,G> {
+	fn from(err: PoisonError<G>) -> CheckedInitError<E,K,G> {
+		CheckedInitError::Poisoned(err)
+	}
+}
+
+impl<E: fmt::Debug,K: fmt::Debug,G> fmt::Debug for CheckedInitError<E,K,G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			CheckedInitError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			CheckedInitError::Init(ref err) => write!(fmt,"Init({:?})",err),
+			CheckedInitError::Deadlock(ref cycle) => write!(fmt,"Deadlock({:?})",cycle),
+		}
+	}
+}
+
+/// A `Result` type for `LockSpace::lock_or_try_init_checked()`.
+pub type CheckedInitResult<T,E,K> = Result<T,CheckedInitError<E,K,T>>;
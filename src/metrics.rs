@@ -0,0 +1,184 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Contention and hold time metrics for LockSpaces.
+//!
+//! With the `metrics` feature, every LockSpace counts how often its keys are
+//! acquired, how long threads wait for them and how long they're held, both
+//! per key and for the whole space. Use `LockSpace::stats()` to get the
+//! numbers, and `SpaceStats::render_prometheus()` to export them.
+//!
+//! ```
+//! let space=namedlock::LockSpace::<String,i32>::new(namedlock::KeepUnused);
+//!
+//! space.with_lock("test".to_owned(),||0,|i|*i+=1).unwrap();
+//! let stats=space.stats();
+//! assert_eq!(stats.total.acquisitions,1);
+//! assert_eq!(stats.keys[0].0,"test");
+//! print!("{}",stats.render_prometheus("example"));
+//! ```
+
+use std::fmt::{Display,Write};
+use std::sync::atomic::{AtomicU64,Ordering};
+use std::time::Duration;
+
+/// Lock statistics of a key or a whole space.
+#[derive(Clone,Copy,Debug,Default,PartialEq,Eq)]
+pub struct Stats {
+	/// The number of times a lock was acquired.
+	pub acquisitions: u64,
+	/// The number of acquisitions for which another guard or waiting thread
+	/// was using the key already.
+	pub contended: u64,
+	/// The total time spent waiting for locks to be acquired.
+	pub total_wait: Duration,
+	/// The longest time spent waiting for a lock to be acquired.
+	pub max_wait: Duration,
+	/// The total time locks were held.
+	pub total_hold: Duration,
+	/// The longest time a lock was held.
+	pub max_hold: Duration,
+}
+
+/// Lock statistics of a LockSpace, as returned by `LockSpace::stats()`.
+#[derive(Clone,Debug)]
+pub struct SpaceStats<K> {
+	/// The statistics of the whole space, including keys that have been
+	/// removed since.
+	pub total: Stats,
+	/// The statistics of each key that is currently in the space. Once a key
+	/// is removed, its statistics are gone, even if it's added again later.
+	pub keys: Vec<(K,Stats)>,
+}
+
+impl<K: Display> SpaceStats<K> {
+	/// Render the statistics in the Prometheus text exposition format. All
+	/// metrics are labelled with `space`, and the per-key metrics with `key`
+	/// as well.
+	pub fn render_prometheus(&self, space: &str) -> String {
+		let space=escape(space);
+		let keys: Vec<(String,&Stats)>=self.keys.iter().map(|(key,stats)|(escape(&key.to_string()),stats)).collect();
+		let mut out=String::new();
+		for &(name,kind,help,value) in METRICS {
+			let _=writeln!(out,"# HELP namedlock_{} {}",name,help);
+			let _=writeln!(out,"# TYPE namedlock_{} {}",name,kind);
+			let _=writeln!(out,"namedlock_{}{{space=\"{}\"}} {}",name,space,value(&self.total));
+			let _=writeln!(out,"# HELP namedlock_key_{} {} Per key.",name,help);
+			let _=writeln!(out,"# TYPE namedlock_key_{} {}",name,kind);
+			for &(ref key,stats) in &keys {
+				let _=writeln!(out,"namedlock_key_{}{{space=\"{}\",key=\"{}\"}} {}",name,space,key,value(stats));
+			}
+		}
+		out
+	}
+}
+
+// Name, type, help text and value of an exported metric
+type Metric = (&'static str,&'static str,&'static str,fn(&Stats) -> f64);
+
+static METRICS: &[Metric] = &[
+	("acquisitions_total","counter","Number of times a lock was acquired.",|s|s.acquisitions as f64),
+	("contended_acquisitions_total","counter","Number of acquisitions of a lock that was in use.",|s|s.contended as f64),
+	("wait_seconds_total","counter","Total time spent waiting for locks.",|s|s.total_wait.as_secs_f64()),
+	("wait_seconds_max","gauge","Longest time spent waiting for a lock.",|s|s.max_wait.as_secs_f64()),
+	("hold_seconds_total","counter","Total time locks were held.",|s|s.total_hold.as_secs_f64()),
+	("hold_seconds_max","gauge","Longest time a lock was held.",|s|s.max_hold.as_secs_f64()),
+];
+
+// Escape a label value
+fn escape(value: &str) -> String {
+	value.replace('\\',"\\\\").replace('"',"\\\"").replace('\n',"\\n")
+}
+
+// The statistics of a key or space while they're being collected. These are
+// updated without any locking, so a snapshot may be slightly inconsistent.
+#[derive(Default)]
+pub(crate) struct Counters {
+	acquisitions: AtomicU64,
+	contended: AtomicU64,
+	total_wait: AtomicU64,
+	max_wait: AtomicU64,
+	total_hold: AtomicU64,
+	max_hold: AtomicU64,
+}
+
+fn nanos(duration: Duration) -> u64 {
+	duration.as_nanos().min(u64::MAX as u128) as u64
+}
+
+impl Counters {
+	pub fn acquired(&self, wait: Duration, contended: bool) {
+		self.acquisitions.fetch_add(1,Ordering::Relaxed);
+		if contended {
+			self.contended.fetch_add(1,Ordering::Relaxed);
+		}
+		self.total_wait.fetch_add(nanos(wait),Ordering::Relaxed);
+		self.max_wait.fetch_max(nanos(wait),Ordering::Relaxed);
+	}
+
+	pub fn released(&self, hold: Duration) {
+		self.total_hold.fetch_add(nanos(hold),Ordering::Relaxed);
+		self.max_hold.fetch_max(nanos(hold),Ordering::Relaxed);
+	}
+
+	pub fn stats(&self) -> Stats {
+		let duration=|counter: &AtomicU64|Duration::from_nanos(counter.load(Ordering::Relaxed));
+		Stats{
+			acquisitions:self.acquisitions.load(Ordering::Relaxed),
+			contended:self.contended.load(Ordering::Relaxed),
+			total_wait:duration(&self.total_wait),
+			max_wait:duration(&self.max_wait),
+			total_hold:duration(&self.total_hold),
+			max_hold:duration(&self.max_hold),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Duration;
+	use super::*;
+
+	#[test]
+	fn counters() {
+		let counters=Counters::default();
+		counters.acquired(Duration::from_millis(3),false);
+		counters.acquired(Duration::from_millis(1),true);
+		counters.released(Duration::from_millis(5));
+		assert_eq!(counters.stats(),Stats{
+			acquisitions:2,
+			contended:1,
+			total_wait:Duration::from_millis(4),
+			max_wait:Duration::from_millis(3),
+			total_hold:Duration::from_millis(5),
+			max_hold:Duration::from_millis(5),
+		});
+	}
+
+	#[test]
+	fn render_prometheus() {
+		let stats=SpaceStats{
+			total:Stats{acquisitions:3,..Stats::default()},
+			keys:vec![("a\"b",Stats{acquisitions:2,..Stats::default()})],
+		};
+		let text=stats.render_prometheus("files");
+		assert!(text.contains("# TYPE namedlock_acquisitions_total counter\n"));
+		assert!(text.contains("namedlock_acquisitions_total{space=\"files\"} 3\n"));
+		assert!(text.contains("namedlock_key_acquisitions_total{space=\"files\",key=\"a\\\"b\"} 2\n"));
+		assert!(text.contains("namedlock_key_hold_seconds_max{space=\"files\",key=\"a\\\"b\"} 0\n"));
+	}
+}
@@ -31,8 +31,10 @@
 //! use namedlock::lockresult::LockResult;
 //! use namedlock::ownedmutexguard::{OwnedMutex,OwnedMutexGuard};
 //!
+//! type Guard<'a,T> = OwnedMutexGuard<'a,T,Arc<Mutex<T>>>;
+//!
 //! // Note the return value has a lifetime distinct from the input
-//! fn get_locked<'a,T: Clone>(input: &T) -> LockResult<OwnedMutexGuard<'a,T,Arc<Mutex<T>>>> {
+//! fn get_locked<'a,T: Clone>(input: &T) -> LockResult<Guard<'a,T>,Guard<'a,T>> {
 //! 	Arc::new(Mutex::new(input.clone())).owned_lock()
 //! }
 //!
@@ -68,7 +70,8 @@ use core::ops::{Deref,DerefMut};
 #[cfg(not(feature="std"))] use alloc::arc::Arc;
 
 use lockresult::LockResult as Result;
-use private::IntoResult;
+use lockresult::TryLockResult;
+use private::{IntoResult,IntoTryResult};
 
 /// An RAII implementation of a "scoped lock" of a mutex. When this structure
 /// is dropped (falls out of scope), the lock will be unlocked, and the
@@ -119,6 +122,90 @@ impl<'a, T: 'a, M: OwnedMutex<T>> OwnedMutexGuard<'a,T,M> {
 	}
 }
 
+impl<'a, T: 'a, M: OwnedMutex<T>> OwnedMutexGuard<'a,T,M> {
+	/// Make a MappedOwnedMutexGuard for a part of the locked data, such as one
+	/// of its fields. The mutex stays locked until the new guard is dropped.
+	///
+	/// This is an associated function, so that it doesn't conflict with a
+	/// method of the same name on `T`.
+	///
+	#[cfg_attr(not(feature="parking_lot"),doc="```")]
+	#[cfg_attr(feature="parking_lot",doc="```ignore")]
+	/// use std::sync::{Mutex,Arc};
+	/// use namedlock::ownedmutexguard::{OwnedMutex,OwnedMutexGuard};
+	///
+	/// let guard=Arc::new(Mutex::new((1,2))).owned_lock().unwrap();
+	/// let second=OwnedMutexGuard::map(guard,|v|&mut v.1);
+	/// assert_eq!(*second,2);
+	/// ```
+	pub fn map<U: ?Sized,F>(mut guard: Self, f: F) -> MappedOwnedMutexGuard<'a,T,M,U>
+		where F: FnOnce(&mut T) -> &mut U
+	{
+		let value: *mut U=f(&mut *guard);
+		MappedOwnedMutexGuard{guard,value}
+	}
+
+	/// Like `map()`, but `f` may fail to find the part of the data to map to.
+	/// In that case, the original guard is returned.
+	pub fn try_map<U: ?Sized,F>(mut guard: Self, f: F) -> ::core::result::Result<MappedOwnedMutexGuard<'a,T,M,U>,Self>
+		where F: FnOnce(&mut T) -> Option<&mut U>
+	{
+		let value=f(&mut *guard).map(|value|value as *mut U);
+		match value {
+			Some(value) => Ok(MappedOwnedMutexGuard{guard,value}),
+			None => Err(guard),
+		}
+	}
+}
+
+/// An RAII implementation of a "scoped lock" of a part of the data of a
+/// mutex, as returned by `OwnedMutexGuard::map`. When this structure is
+/// dropped (falls out of scope), the lock will be unlocked, and the owner of
+/// the Mutex will be dropped, just like with the original guard.
+///
+/// The part of the data can be accessed through this guard via its Deref and
+/// DerefMut implementations.
+pub struct MappedOwnedMutexGuard<'a, T: 'a, M: OwnedMutex<T>, U: ?Sized + 'a> {
+	guard: OwnedMutexGuard<'a,T,M>,
+	// Points into the data locked by `guard`, which doesn't move
+	value: *mut U,
+}
+
+impl<'a, T: 'a, M: OwnedMutex<T>, U: ?Sized> MappedOwnedMutexGuard<'a,T,M,U> {
+	/// Make a MappedOwnedMutexGuard for a part of this guard's data. See
+	/// `OwnedMutexGuard::map`.
+	pub fn map<W: ?Sized,F>(guard: Self, f: F) -> MappedOwnedMutexGuard<'a,T,M,W>
+		where F: FnOnce(&mut U) -> &mut W
+	{
+		let value: *mut W=f(unsafe{&mut *guard.value});
+		MappedOwnedMutexGuard{guard:guard.guard,value}
+	}
+
+	/// Like `map()`, but `f` may fail to find the part of the data to map to.
+	/// In that case, the original guard is returned.
+	pub fn try_map<W: ?Sized,F>(guard: Self, f: F) -> ::core::result::Result<MappedOwnedMutexGuard<'a,T,M,W>,Self>
+		where F: FnOnce(&mut U) -> Option<&mut W>
+	{
+		match f(unsafe{&mut *guard.value}).map(|value|value as *mut W) {
+			Some(value) => Ok(MappedOwnedMutexGuard{guard:guard.guard,value}),
+			None => Err(guard),
+		}
+	}
+}
+
+impl<'a, T: 'a, M: OwnedMutex<T>, U: ?Sized> Deref for MappedOwnedMutexGuard<'a,T,M,U> {
+	type Target = U;
+	fn deref(&self) -> &U {
+		unsafe{&*self.value}
+	}
+}
+
+impl<'a, T: 'a, M: OwnedMutex<T>, U: ?Sized> DerefMut for MappedOwnedMutexGuard<'a,T,M,U> {
+	fn deref_mut(&mut self) -> &mut U {
+		unsafe{&mut *self.value}
+	}
+}
+
 /// Implements the functions to obtain `OwnedMutexGuard`s.
 ///
 /// This trait must only be implemented for types for which the memory address
@@ -130,6 +217,9 @@ pub unsafe trait OwnedMutex<T>: Sized + Deref<Target=Mutex<T>> {
 	/// Upon returning, the thread is the only thread with the mutex held. An RAII guard is
 	/// returned to allow scoped unlock of the lock. When the guard goes out of scope, the
 	/// mutex will be unlocked, and the OwnedMutex will be dropped.
+	///
+	/// If another holder panicked, the mutex is poisoned, and the guard is
+	/// returned in the `PoisonError` instead.
 	// Unsafety explanation:
 	// The MutexGuard holds a reference to it's Mutex. As such, the mutex must stay alive
 	// at that address until the guard drops. We guarantee this by storing the mutex
@@ -138,9 +228,24 @@ pub unsafe trait OwnedMutex<T>: Sized + Deref<Target=Mutex<T>> {
 	// In particular, we know that our reference to the mutex can be safely converted to
 	// lifetime 'a since we will be storing the OwnedMutex in a structure with the same
 	// lifetime 'a.
-	fn owned_lock<'a>(self) -> Result<OwnedMutexGuard<'a,T,Self>> where Self: 'a {
-		let guard=try!(unsafe{&*(&self as *const _) as &'a Mutex<T>}.lock().into_result());
-		return Ok(OwnedMutexGuard{owned_mutex:Some(self),guard:Some(guard)});
+	fn owned_lock<'a>(self) -> Result<OwnedMutexGuard<'a,T,Self>,OwnedMutexGuard<'a,T,Self>> where Self: 'a {
+		match unsafe{&*(&self as *const _) as &'a Mutex<T>}.lock().into_guard_result() {
+			Ok(guard) => Ok(OwnedMutexGuard{owned_mutex:Some(self),guard:Some(guard)}),
+			Err(err) => Err(err.map_guard(|guard|OwnedMutexGuard{owned_mutex:Some(self),guard:Some(guard)})),
+		}
+	}
+
+	/// Attempts to acquire an `OwnedMutex`, without blocking.
+	///
+	/// If the mutex is currently locked, `WouldBlock` is returned and the
+	/// OwnedMutex is dropped. Otherwise, an RAII guard is returned, just like
+	/// with `owned_lock`, including if the mutex is poisoned.
+	// Unsafety explanation: see owned_lock
+	fn try_owned_lock<'a>(self) -> TryLockResult<OwnedMutexGuard<'a,T,Self>,OwnedMutexGuard<'a,T,Self>> where Self: 'a {
+		match unsafe{&*(&self as *const _) as &'a Mutex<T>}.try_lock().into_guard_try_result() {
+			Ok(guard) => Ok(OwnedMutexGuard{owned_mutex:Some(self),guard:Some(guard)}),
+			Err(err) => Err(err.map_guard(|guard|OwnedMutexGuard{owned_mutex:Some(self),guard:Some(guard)})),
+		}
 	}
 }
 
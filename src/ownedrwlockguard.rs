@@ -0,0 +1,217 @@
+// OwnedRwLockGuard - RwLock guards that own the RwLock
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! RwLock guards that own the RwLock.
+//!
+//! This is the reader-writer lock equivalent of the `ownedmutexguard` module.
+//! The guard types `OwnedRwLockReadGuard` and `OwnedRwLockWriteGuard`
+//! guarantee that an `OwnedRwLock` stays alive until the guard is released,
+//! without any restrictions on the lifetime of the lock.
+//!
+//! `Arc<RwLock<_>>`, `Rc<RwLock<_>>` and `Box<RwLock<_>>` implement
+//! `OwnedRwLock`.
+//!
+#![cfg_attr(not(feature="parking_lot"),doc="```")]
+#![cfg_attr(feature="parking_lot",doc="```ignore")]
+//! use std::sync::{RwLock,Arc};
+//! use namedlock::ownedrwlockguard::OwnedRwLock;
+//!
+//! let lock=Arc::new(RwLock::new(5));
+//! let r1=lock.clone().owned_read().unwrap();
+//! let r2=lock.clone().owned_read().unwrap();
+//! assert_eq!(*r1+*r2,10);
+//! drop((r1,r2));
+//! *lock.clone().owned_write().unwrap()+=1;
+//! assert_eq!(*lock.owned_read().unwrap(),6);
+//! ```
+//!
+//! ## License
+//! OwnedRwLockGuard - Copyright (C) 2026  Jethro G. Beekman
+//!
+//! This program is free software; you can redistribute it and/or
+//! modify it under the terms of the GNU General Public License
+//! as published by the Free Software Foundation; either version 2
+//! of the License, or (at your option) any later version.
+//!
+//! This program is distributed in the hope that it will be useful,
+//! but WITHOUT ANY WARRANTY; without even the implied warranty of
+//! MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+//! GNU General Public License for more details.
+//!
+//! You should have received a copy of the GNU General Public License
+//! along with this program; if not, write to the Free Software Foundation,
+//! Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))] use std::sync::{RwLock,RwLockReadGuard,RwLockWriteGuard};
+#[cfg(feature="spin")] use spin::{RwLock,RwLockReadGuard,RwLockWriteGuard};
+#[cfg(feature="parking_lot")] use parking_lot::{RwLock,RwLockReadGuard,RwLockWriteGuard};
+use core::ops::{Deref,DerefMut};
+
+#[cfg(feature="std")] use std::rc::Rc;
+#[cfg(feature="std")] use std::sync::Arc;
+#[cfg(not(feature="std"))] use alloc::boxed::Box;
+#[cfg(not(feature="std"))] use alloc::rc::Rc;
+#[cfg(not(feature="std"))] use alloc::arc::Arc;
+
+use lockresult::LockResult as Result;
+use lockresult::TryLockResult;
+use private::{IntoResult,IntoTryResult};
+
+/// An RAII implementation of a "scoped shared read lock" of a RwLock. When
+/// this structure is dropped (falls out of scope), the shared lock will be
+/// released, and the owner of the RwLock will be dropped.
+///
+/// Alternatively, call `into_inner` to drop the guard and reclaim the owner.
+pub struct OwnedRwLockReadGuard<'a, T: 'a, L: OwnedRwLock<T>> {
+	owned_lock: Option<L>,
+	guard: Option<RwLockReadGuard<'a,T>>,
+}
+
+/// An RAII implementation of a "scoped exclusive write lock" of a RwLock.
+/// When this structure is dropped (falls out of scope), the lock will be
+/// unlocked, and the owner of the RwLock will be dropped.
+///
+/// Alternatively, call `into_inner` to drop the guard and reclaim the owner.
+pub struct OwnedRwLockWriteGuard<'a, T: 'a, L: OwnedRwLock<T>> {
+	owned_lock: Option<L>,
+	guard: Option<RwLockWriteGuard<'a,T>>,
+}
+
+impl<'a, T: 'a, L: OwnedRwLock<T>> Deref for OwnedRwLockReadGuard<'a,T,L> {
+	type Target = T;
+	fn deref(&self) -> &T {
+		// This is always Some, because it's initialized as Some, and only drop() and into_inner() turn it into None
+		match self.guard {
+			Some(ref value) => value,
+			None => unreachable!(),
+		}
+	}
+}
+
+impl<'a, T: 'a, L: OwnedRwLock<T>> Deref for OwnedRwLockWriteGuard<'a,T,L> {
+	type Target = T;
+	fn deref(&self) -> &T {
+		// This is always Some, because it's initialized as Some, and only drop() and into_inner() turn it into None
+		match self.guard {
+			Some(ref value) => value,
+			None => unreachable!(),
+		}
+	}
+}
+
+impl<'a, T: 'a, L: OwnedRwLock<T>> DerefMut for OwnedRwLockWriteGuard<'a,T,L> {
+	fn deref_mut(&mut self) -> &mut T {
+		// This is always Some, because it's initialized as Some, and only drop() and into_inner() turn it into None
+		match self.guard {
+			Some(ref mut value) => value,
+			None => unreachable!(),
+		}
+	}
+}
+
+impl<'a, T: 'a, L: OwnedRwLock<T>> Drop for OwnedRwLockReadGuard<'a,T,L> {
+	fn drop(&mut self) {
+		self.guard=None;
+	}
+}
+
+impl<'a, T: 'a, L: OwnedRwLock<T>> Drop for OwnedRwLockWriteGuard<'a,T,L> {
+	fn drop(&mut self) {
+		self.guard=None;
+	}
+}
+
+impl<'a, T: 'a, L: OwnedRwLock<T>> OwnedRwLockReadGuard<'a,T,L> {
+	/// Drops the guard and returns the associated `OwnedRwLock`
+	pub fn into_inner(mut self) -> L {
+		self.guard=None;
+		// This is always Some, because it's initialized as Some, and only drop() or this turns it into None
+		self.owned_lock.take().unwrap()
+	}
+}
+
+impl<'a, T: 'a, L: OwnedRwLock<T>> OwnedRwLockWriteGuard<'a,T,L> {
+	/// Drops the guard and returns the associated `OwnedRwLock`
+	pub fn into_inner(mut self) -> L {
+		self.guard=None;
+		// This is always Some, because it's initialized as Some, and only drop() or this turns it into None
+		self.owned_lock.take().unwrap()
+	}
+}
+
+/// Implements the functions to obtain `OwnedRwLockReadGuard`s and
+/// `OwnedRwLockWriteGuard`s.
+///
+/// # Safety
+/// This trait must only be implemented for types for which the memory address
+/// of the value reachable via Deref remains identical even if self gets moved.
+// Unsafety explanation: see OwnedMutex::owned_lock
+pub unsafe trait OwnedRwLock<T>: Sized + Deref<Target=RwLock<T>> {
+	/// Locks an `OwnedRwLock` with shared read access, blocking the current
+	/// thread until it can be acquired.
+	///
+	/// If a writer panicked, the lock is poisoned, and the guard is returned
+	/// in the `PoisonError` instead.
+	fn owned_read<'a>(self) -> Result<OwnedRwLockReadGuard<'a,T,Self>,OwnedRwLockReadGuard<'a,T,Self>> where Self: 'a {
+		match unsafe{&*(&self as *const _) as &'a RwLock<T>}.read().into_guard_result() {
+			Ok(guard) => Ok(OwnedRwLockReadGuard{owned_lock:Some(self),guard:Some(guard)}),
+			Err(err) => Err(err.map_guard(|guard|OwnedRwLockReadGuard{owned_lock:Some(self),guard:Some(guard)})),
+		}
+	}
+
+	/// Locks an `OwnedRwLock` with exclusive write access, blocking the
+	/// current thread until it can be acquired.
+	///
+	/// If a writer panicked, the lock is poisoned, and the guard is returned
+	/// in the `PoisonError` instead.
+	fn owned_write<'a>(self) -> Result<OwnedRwLockWriteGuard<'a,T,Self>,OwnedRwLockWriteGuard<'a,T,Self>> where Self: 'a {
+		match unsafe{&*(&self as *const _) as &'a RwLock<T>}.write().into_guard_result() {
+			Ok(guard) => Ok(OwnedRwLockWriteGuard{owned_lock:Some(self),guard:Some(guard)}),
+			Err(err) => Err(err.map_guard(|guard|OwnedRwLockWriteGuard{owned_lock:Some(self),guard:Some(guard)})),
+		}
+	}
+
+	/// Attempts to lock an `OwnedRwLock` with shared read access, without
+	/// blocking.
+	///
+	/// If the lock is currently held for writing, `WouldBlock` is returned and
+	/// the OwnedRwLock is dropped. Otherwise, an RAII guard is returned, just
+	/// like with `owned_read`, including if the lock is poisoned.
+	fn try_owned_read<'a>(self) -> TryLockResult<OwnedRwLockReadGuard<'a,T,Self>,OwnedRwLockReadGuard<'a,T,Self>> where Self: 'a {
+		match unsafe{&*(&self as *const _) as &'a RwLock<T>}.try_read().into_guard_try_result() {
+			Ok(guard) => Ok(OwnedRwLockReadGuard{owned_lock:Some(self),guard:Some(guard)}),
+			Err(err) => Err(err.map_guard(|guard|OwnedRwLockReadGuard{owned_lock:Some(self),guard:Some(guard)})),
+		}
+	}
+
+	/// Attempts to lock an `OwnedRwLock` with exclusive write access, without
+	/// blocking.
+	///
+	/// If the lock is currently held, `WouldBlock` is returned and the
+	/// OwnedRwLock is dropped. Otherwise, an RAII guard is returned, just like
+	/// with `owned_write`, including if the lock is poisoned.
+	fn try_owned_write<'a>(self) -> TryLockResult<OwnedRwLockWriteGuard<'a,T,Self>,OwnedRwLockWriteGuard<'a,T,Self>> where Self: 'a {
+		match unsafe{&*(&self as *const _) as &'a RwLock<T>}.try_write().into_guard_try_result() {
+			Ok(guard) => Ok(OwnedRwLockWriteGuard{owned_lock:Some(self),guard:Some(guard)}),
+			Err(err) => Err(err.map_guard(|guard|OwnedRwLockWriteGuard{owned_lock:Some(self),guard:Some(guard)})),
+		}
+	}
+}
+
+unsafe impl<T> OwnedRwLock<T> for Box<RwLock<T>> {}
+unsafe impl<T> OwnedRwLock<T> for Rc<RwLock<T>> {}
+unsafe impl<T> OwnedRwLock<T> for Arc<RwLock<T>> {}
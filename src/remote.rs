@@ -0,0 +1,435 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Named locks served to other processes over a Unix domain socket.
+//!
+//! A `LockServer` hosts a `LockSpace<String,()>`, and the `namedlock-server`
+//! binary runs one on a socket path. Each connection to the server is a lock
+//! owner. When it's closed, all locks it holds are released.
+//!
+//! The protocol is line based, so that shell scripts can use it with tools
+//! like `socat`. Every request is a command, a space and a key, terminated by
+//! a newline. The server answers every request with one line:
+//!
+//! | Request         | Responses                                        |
+//! |-----------------|--------------------------------------------------|
+//! | `LOCK <key>`    | `OK`, once the key is locked                     |
+//! | `TRYLOCK <key>` | `OK`, or `WOULDBLOCK` if any connection holds it |
+//! | `UNLOCK <key>`  | `OK`                                             |
+//! | `STATUS <key>`  | `LOCKED` or `UNLOCKED`                           |
+//! | `REMOVE <key>`  | `OK`, `NOTFOUND` or `WOULDBLOCK`                 |
+//!
+//! Any request can also be answered with `ERR <message>`, for example when
+//! locking a key that the connection already holds, or unlocking a key it
+//! doesn't hold. A request line may be at most 4096 bytes long, including
+//! the newline. A longer line is answered with `ERR request too long`, and
+//! the connection is closed. `RemoteLockSpace` is a client for Rust code.
+//!
+//! A `LOCK` request that is still waiting keeps waiting if its client
+//! disconnects, since the server only reads the next request after
+//! answering. Once the key is granted, answering fails and the key is
+//! released again, so the server holds it for a dead client only briefly.
+//! Other clients waiting for the key are delayed by that, but not blocked.
+//!
+//! ```
+//! use std::os::unix::net::UnixListener;
+//! use std::sync::Arc;
+//! use std::thread;
+//! use namedlock::AutoCleanup;
+//! use namedlock::remote::{LockServer,RemoteLockSpace};
+//!
+//! let path=std::env::temp_dir().join(format!("namedlock-doctest-{}.sock",std::process::id()));
+//! let listener=UnixListener::bind(&path).unwrap();
+//! let server=Arc::new(LockServer::new(AutoCleanup));
+//! thread::spawn(move||server.serve(&listener,|_|{}));
+//!
+//! let client=RemoteLockSpace::connect(&path).unwrap();
+//! let other=RemoteLockSpace::connect(&path).unwrap();
+//! let guard=client.lock("backup".to_owned()).unwrap();
+//! assert!(other.is_locked("backup").unwrap());
+//! drop(guard);
+//! assert!(!other.is_locked("backup").unwrap());
+//! # std::fs::remove_file(&path).unwrap();
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self,BufRead,BufReader,Read,Write};
+use std::os::unix::net::{UnixListener,UnixStream};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use lockresult::{PoisonError,TryLockError as SpaceTryLockError};
+use {LockSpace,LockSpaceGuard,LockSpaceRemoveResult,Cleanup};
+
+/// An error returned by the non-blocking `RemoteLockSpace` methods.
+#[derive(Debug)]
+pub enum TryLockError {
+	/// The key is locked by another connection.
+	WouldBlock,
+	/// The request failed, or the server returned an error.
+	Io(io::Error),
+}
+
+impl From<io::Error> for TryLockError {
+	fn from(err: io::Error) -> TryLockError {
+		TryLockError::Io(err)
+	}
+}
+
+// How long to wait before accepting again after a failure that likely
+// persists for a while, like running out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+// The maximum length of a request line, including the newline, so that a
+// client can't make the server buffer an arbitrary amount of data
+const MAX_REQUEST: usize = 4096;
+
+/// A server for named locks, see the module documentation for the protocol.
+pub struct LockServer {
+	space: LockSpace<String,()>,
+}
+
+impl LockServer {
+	/// Create a new LockServer, hosting a space with the given cleanup mode.
+	pub fn new(cleanup: Cleanup) -> LockServer {
+		LockServer{space:LockSpace::new(cleanup)}
+	}
+
+	/// Serve every connection to `listener` on its own thread. This never
+	/// returns. If accepting a connection fails, the error is passed to
+	/// `on_accept_error`, and the server keeps accepting connections.
+	pub fn serve<F: FnMut(&io::Error)>(&self, listener: &UnixListener, mut on_accept_error: F) -> ! {
+		thread::scope(|scope| -> ! {
+			loop {
+				match listener.accept() {
+					Ok((stream,_)) => {
+						// Errors only affect the connection itself
+						scope.spawn(move||self.serve_connection(stream));
+					},
+					Err(err) => {
+						on_accept_error(&err);
+						match err.kind() {
+							io::ErrorKind::ConnectionAborted | io::ErrorKind::Interrupted => {},
+							_ => thread::sleep(ACCEPT_BACKOFF),
+						}
+					},
+				}
+			}
+		})
+	}
+
+	// Handle the requests of a single connection. When it returns, the
+	// locks held by the connection are released.
+	fn serve_connection(&self, stream: UnixStream) -> io::Result<()> {
+		let mut held=HashMap::new();
+		let mut writer=stream.try_clone()?;
+		let mut reader=BufReader::new(stream);
+		let mut line=String::new();
+		loop {
+			line.clear();
+			if reader.by_ref().take(MAX_REQUEST as u64).read_line(&mut line)?==0 {
+				return Ok(());
+			}
+			let request=match line.strip_suffix('\n') {
+				Some(request) => request.strip_suffix('\r').unwrap_or(request),
+				// The last line may be missing its newline
+				None if line.len()<MAX_REQUEST => &line,
+				None => {
+					// The rest of the line can't be told apart from the next
+					// request, so give up on the connection
+					writeln!(writer,"ERR request too long")?;
+					return Ok(());
+				},
+			};
+			let response=self.respond(&mut held,request);
+			writeln!(writer,"{}",response)?;
+		}
+	}
+
+	fn respond<'a>(&'a self, held: &mut HashMap<String,LockSpaceGuard<'a,String,()>>, request: &str) -> String {
+		let (command,key)=match request.split_once(' ') {
+			Some(parts) => parts,
+			None => return "ERR missing key".to_owned(),
+		};
+		// Locking a key twice from the same thread would never return
+		if held.contains_key(key) {
+			match command {
+				"LOCK" => return "ERR key is already held by this connection".to_owned(),
+				"TRYLOCK" => return "WOULDBLOCK".to_owned(),
+				_ => {},
+			}
+		}
+		// There's no value that a panic could have left inconsistent, so
+		// poison is ignored
+		let response=match command {
+			"LOCK" => {
+				let guard=self.space.lock(key.to_owned(),||()).unwrap_or_else(PoisonError::into_inner);
+				held.insert(key.to_owned(),guard);
+				"OK"
+			},
+			"TRYLOCK" => {
+				let guard=match self.space.try_lock(key.to_owned(),||()) {
+					Ok(guard) => guard,
+					Err(SpaceTryLockError::Poisoned(err)) => err.into_inner(),
+					Err(_) => return "WOULDBLOCK".to_owned(),
+				};
+				held.insert(key.to_owned(),guard);
+				"OK"
+			},
+			"UNLOCK" => match held.remove(key) {
+				Some(_) => "OK",
+				None => "ERR key is not held by this connection",
+			},
+			"STATUS" => match self.space.is_locked(key) {
+				true => "LOCKED",
+				false => "UNLOCKED",
+			},
+			"REMOVE" => match self.space.try_remove_ref(key) {
+				LockSpaceRemoveResult::Success => "OK",
+				LockSpaceRemoveResult::NotFound => "NOTFOUND",
+				LockSpaceRemoveResult::WouldBlock => "WOULDBLOCK",
+				LockSpaceRemoveResult::PoisonError => "ERR poisoned",
+			},
+			_ => "ERR unknown command",
+		};
+		response.to_owned()
+	}
+}
+
+// The client side of a connection
+struct Connection {
+	reader: BufReader<UnixStream>,
+	writer: UnixStream,
+}
+
+impl Connection {
+	// Send a request and return the response, without its newline
+	fn request(&mut self, command: &str, key: &str) -> io::Result<String> {
+		if key.contains('\n') {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput,"Key contains a newline"));
+		}
+		writeln!(self.writer,"{} {}",command,key)?;
+		let mut response=String::new();
+		if self.reader.read_line(&mut response)?==0 {
+			return Err(io::ErrorKind::UnexpectedEof.into());
+		}
+		response.pop();
+		match response.strip_prefix("ERR ") {
+			Some(message) => Err(io::Error::other(message)),
+			None => Ok(response),
+		}
+	}
+}
+
+fn unexpected(response: String) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData,format!("Unexpected response: {}",response))
+}
+
+/// An RAII implementation of a "scoped lock" of a key held through a
+/// RemoteLockSpace. When this structure is dropped (falls out of scope), the
+/// key will be unlocked.
+pub struct RemoteLockGuard<'a> {
+	owner: &'a RemoteLockSpace,
+	key: String,
+}
+
+impl<'a> RemoteLockGuard<'a> {
+	/// The key this guard was locked for.
+	pub fn key(&self) -> &str {
+		&self.key
+	}
+}
+
+impl<'a> Drop for RemoteLockGuard<'a> {
+	fn drop(&mut self) {
+		// Ignore errors on drop here. If the connection is broken, the server
+		// releases the lock anyway.
+		let _=self.owner.request("UNLOCK",&self.key);
+	}
+}
+
+/// A client of a `LockServer`.
+///
+/// The connection is the owner of the locks acquired through it, so a key
+/// can't be locked again through the same RemoteLockSpace while it's held.
+/// Since `lock()` keeps the connection busy until the lock is acquired, a
+/// RemoteLockSpace can't be shared between threads. Connect once per thread
+/// instead.
+pub struct RemoteLockSpace {
+	connection: RefCell<Connection>,
+}
+
+impl RemoteLockSpace {
+	/// Connect to the server listening on `path`.
+	pub fn connect<P: AsRef<Path>>(path: P) -> io::Result<RemoteLockSpace> {
+		let writer=UnixStream::connect(path)?;
+		let reader=BufReader::new(writer.try_clone()?);
+		Ok(RemoteLockSpace{connection:RefCell::new(Connection{reader,writer})})
+	}
+
+	fn request(&self, command: &str, key: &str) -> io::Result<String> {
+		self.connection.borrow_mut().request(command,key)
+	}
+
+	/// Lock `key`, blocking the current thread until no other connection
+	/// holds it, and return a RemoteLockGuard over it.
+	pub fn lock<'a>(&'a self, key: String) -> io::Result<RemoteLockGuard<'a>> {
+		let response=self.request("LOCK",&key)?;
+		match &*response {
+			"OK" => Ok(RemoteLockGuard{owner:self,key}),
+			_ => Err(unexpected(response)),
+		}
+	}
+
+	/// Lock `key` without blocking, and return a RemoteLockGuard over it.
+	///
+	/// If another connection holds `key`, `WouldBlock` is returned.
+	pub fn try_lock<'a>(&'a self, key: String) -> Result<RemoteLockGuard<'a>,TryLockError> {
+		let response=self.request("TRYLOCK",&key)?;
+		match &*response {
+			"OK" => Ok(RemoteLockGuard{owner:self,key}),
+			"WOULDBLOCK" => Err(TryLockError::WouldBlock),
+			_ => Err(unexpected(response).into()),
+		}
+	}
+
+	/// Whether `key` is currently locked by any connection.
+	pub fn is_locked(&self, key: &str) -> io::Result<bool> {
+		let response=self.request("STATUS",key)?;
+		match &*response {
+			"LOCKED" => Ok(true),
+			"UNLOCKED" => Ok(false),
+			_ => Err(unexpected(response)),
+		}
+	}
+
+	/// Delete `key` from the server's space if it is not locked, and return
+	/// whether it was there. If it is locked, `WouldBlock` is returned.
+	///
+	/// This is only useful if the server's space is of the `KeepUnused` kind.
+	pub fn try_remove(&self, key: String) -> Result<bool,TryLockError> {
+		let response=self.request("REMOVE",&key)?;
+		match &*response {
+			"OK" => Ok(true),
+			"NOTFOUND" => Ok(false),
+			"WOULDBLOCK" => Err(TryLockError::WouldBlock),
+			_ => Err(unexpected(response).into()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::fs;
+	use std::io::Read;
+	use std::mem;
+	use std::time::Duration;
+	use super::*;
+	use {AutoCleanup,KeepUnused};
+	use testutil::start_server;
+
+	#[test]
+	fn protocol() {
+		let path=start_server("protocol",KeepUnused);
+		let mut stream=UnixStream::connect(&path).unwrap();
+		stream.write_all(b"TRYLOCK a b\nLOCK a b\nTRYLOCK a b\nSTATUS a b\nREMOVE a b\nUNLOCK a b\nUNLOCK a b\nREMOVE a b\nSTATUS a b\nPING\nFOO a\n").unwrap();
+		stream.shutdown(::std::net::Shutdown::Write).unwrap();
+		let mut responses=String::new();
+		stream.read_to_string(&mut responses).unwrap();
+		assert_eq!(responses,"OK\n\
+			ERR key is already held by this connection\n\
+			WOULDBLOCK\n\
+			LOCKED\n\
+			WOULDBLOCK\n\
+			OK\n\
+			ERR key is not held by this connection\n\
+			OK\n\
+			UNLOCKED\n\
+			ERR missing key\n\
+			ERR unknown command\n");
+	}
+
+	#[test]
+	fn request_too_long() {
+		let path=start_server("toolong",AutoCleanup);
+		let mut stream=UnixStream::connect(&path).unwrap();
+		stream.write_all(b"LOCK a\n").unwrap();
+		stream.write_all(format!("LOCK {}\n",&"a".repeat(MAX_REQUEST)).as_bytes()).unwrap();
+		let mut responses=String::new();
+		stream.read_to_string(&mut responses).unwrap();
+		assert_eq!(responses,"OK\nERR request too long\n");
+
+		// The connection was closed, releasing its locks
+		let client=RemoteLockSpace::connect(&path).unwrap();
+		assert!(client.lock("a".to_owned()).is_ok());
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn blocks_until_released() {
+		let path=start_server("blocks",AutoCleanup);
+		let client=RemoteLockSpace::connect(&path).unwrap();
+		let guard=client.lock("test".to_owned()).unwrap();
+		assert_eq!(guard.key(),"test");
+		assert!(client.lock("test".to_owned()).is_err());
+
+		let path_clone=path.clone();
+		let waiter=thread::spawn(move||{
+			let other=RemoteLockSpace::connect(&path_clone).unwrap();
+			assert!(matches!(other.try_lock("test".to_owned()),Err(TryLockError::WouldBlock)));
+			assert!(other.lock("test".to_owned()).is_ok());
+		});
+		thread::sleep(Duration::from_millis(50));
+		assert!(!waiter.is_finished());
+		drop(guard);
+		waiter.join().unwrap();
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn released_on_disconnect() {
+		let path=start_server("disconnect",AutoCleanup);
+		let client=RemoteLockSpace::connect(&path).unwrap();
+		mem::forget(client.lock("test".to_owned()).unwrap());
+		let other=RemoteLockSpace::connect(&path).unwrap();
+		assert!(other.is_locked("test").unwrap());
+
+		drop(client);
+		assert!(other.lock("test".to_owned()).is_ok());
+		fs::remove_file(&path).unwrap();
+	}
+
+	#[test]
+	fn waiter_disconnects() {
+		let path=start_server("waiter",AutoCleanup);
+		let client=RemoteLockSpace::connect(&path).unwrap();
+		let guard=client.lock("test".to_owned()).unwrap();
+		let mut stream=UnixStream::connect(&path).unwrap();
+		stream.write_all(b"LOCK test\n").unwrap();
+		thread::sleep(Duration::from_millis(50));
+		drop(stream);
+
+		// The key is granted to the dead connection, and released again when
+		// answering it fails
+		drop(guard);
+		let other=RemoteLockSpace::connect(&path).unwrap();
+		assert!(other.lock("test".to_owned()).is_ok());
+		fs::remove_file(&path).unwrap();
+	}
+}
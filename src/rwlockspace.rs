@@ -0,0 +1,424 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Namespaces for named reader-writer locks.
+
+#[cfg(feature="std")] use std::collections::HashMap;
+#[cfg(not(feature="std"))] use core_collections::HashMap;
+#[cfg(feature="std")] use std::sync::Arc;
+#[cfg(not(feature="std"))] use alloc::arc::Arc;
+#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))] use std::sync::{Mutex,MutexGuard,RwLock};
+#[cfg(feature="spin")] use spin::{Mutex,MutexGuard,RwLock};
+#[cfg(feature="parking_lot")] use parking_lot::{Mutex,MutexGuard,RwLock};
+use core::borrow::Borrow;
+use core::hash::Hash;
+use core::ops::{Deref,DerefMut};
+use core::mem::drop;
+
+use lockresult::LockResult as Result;
+use lockresult::{TryLockError,TryLockResult};
+use ownedrwlockguard::{OwnedRwLock,OwnedRwLockReadGuard,OwnedRwLockWriteGuard};
+use {Cleanup,AutoCleanup,LockSpaceRemoveResult,NamedRef,SpaceValue,Initializing,get_or_create,try_remove_internal,release,lock_unpoisoned};
+use core::convert::Infallible;
+
+/// An RAII implementation of a "scoped shared read lock" of a RwLockSpace
+/// value. When this structure is dropped (falls out of scope), the shared lock
+/// will be released, and the reference count to the key will be decreased by
+/// 1.
+///
+/// The actual value can be accessed through this guard via its Deref
+/// implementation.
+pub struct ReadGuard<'a,K: 'a + Eq + Hash + Clone,V: 'a> {
+	owner: &'a RwLockSpace<K,V>,
+	guard: Option<OwnedRwLockReadGuard<'a,V,NamedRef<K,RwLock<V>>>>,
+}
+
+/// An RAII implementation of a "scoped exclusive write lock" of a RwLockSpace
+/// value. When this structure is dropped (falls out of scope), the lock will
+/// be unlocked, and the reference count to the key will be decreased by 1.
+///
+/// The actual value can be accessed through this guard via its Deref and
+/// DerefMut implementations.
+pub struct WriteGuard<'a,K: 'a + Eq + Hash + Clone,V: 'a> {
+	owner: &'a RwLockSpace<K,V>,
+	guard: Option<OwnedRwLockWriteGuard<'a,V,NamedRef<K,RwLock<V>>>>,
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> Deref for ReadGuard<'a,K,V> {
+	type Target = V;
+	fn deref(&self) -> &V {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		match self.guard {
+			Some(ref value) => value,
+			None => unreachable!(),
+		}
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> Deref for WriteGuard<'a,K,V> {
+	type Target = V;
+	fn deref(&self) -> &V {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		match self.guard {
+			Some(ref value) => value,
+			None => unreachable!(),
+		}
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> DerefMut for WriteGuard<'a,K,V> {
+	fn deref_mut(&mut self) -> &mut V {
+		// This is always Some, because it's initialized as Some, and only drop() turns it into None
+		match self.guard {
+			Some(ref mut value) => value,
+			None => unreachable!(),
+		}
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> Drop for ReadGuard<'a,K,V> {
+	fn drop(&mut self) {
+		// release inner lock
+		let arc=self.guard.take().unwrap().into_inner().0;
+		release(&self.owner.names,self.owner.cleanup,arc);
+	}
+}
+
+impl<'a,K: Eq + Hash + Clone,V: 'a> Drop for WriteGuard<'a,K,V> {
+	fn drop(&mut self) {
+		// release inner lock
+		let arc=self.guard.take().unwrap().into_inner().0;
+		release(&self.owner.names,self.owner.cleanup,arc);
+	}
+}
+
+type RwLockSpaceValue<K,V> = SpaceValue<K,RwLock<V>>;
+
+/// A `RwLockSpace<K,V>` holds many `RwLock<V>`'s, keyed by `K`.
+///
+/// This is the reader-writer lock equivalent of `LockSpace<K,V>`: any number
+/// of `ReadGuard`s can be held for a key at the same time, but `WriteGuard`s
+/// are exclusive. Cleanup works exactly the same as for `LockSpace`, and so
+/// does creating values: `initial` is called without holding the lock of the
+/// space.
+///
+/// ```
+/// use namedlock::{RwLockSpace,AutoCleanup};
+///
+/// let space=RwLockSpace::<String,i32>::new(AutoCleanup);
+///
+/// *space.write("test".to_owned(),||0).unwrap()+=1;
+/// // The value was cleaned up when the write guard was dropped
+/// assert_eq!(*space.read("test".to_owned(),||5).unwrap(),5);
+///
+/// let r1=space.read("test".to_owned(),||0).unwrap();
+/// let r2=space.read("test".to_owned(),||0).unwrap();
+/// assert_eq!(*r1,*r2);
+/// assert!(space.try_write("test".to_owned(),||0).is_err());
+/// # drop((r1,r2));
+/// ```
+///
+/// If a `WriteGuard` is dropped during a panic, the object is poisoned. Like
+/// with `LockSpace`, it's locked anyway, and the guard is returned in the
+/// `Poisoned` error, until `clear_poison()` is called.
+pub struct RwLockSpace<K: Eq + Hash,V> {
+	// IMPORTANT: The same locking rules and reference counting invariants as
+	// for `LockSpace` apply here, with each read or write guard and each
+	// waiting thread holding 1 reference to the inner RwLock.
+	names: Arc<Mutex<HashMap<K,RwLockSpaceValue<K,V>>>>,
+	// Values are created without holding the outer lock, like with
+	// `LockSpace`
+	initializing: Arc<Initializing<K>>,
+	cleanup: Cleanup,
+}
+
+// This needs to be implemented manually, since #[derive(Clone)] doesn't
+// understand that the type parameters are only used within the Arc<_>
+impl<K: Eq + Hash,V> Clone for RwLockSpace<K,V> {
+	fn clone(&self) -> RwLockSpace<K,V> {
+		RwLockSpace{names:self.names.clone(),initializing:self.initializing.clone(),cleanup:self.cleanup}
+	}
+}
+
+impl<K: Eq + Hash + Clone,V> RwLockSpace<K,V> {
+	/// Create a new RwLockSpace.
+	///
+	/// If `cleanup` is `AutoCleanup`, values will be deleted automatically when
+	/// the last lock is released. Otherwise, values will remain in the space
+	/// until `try_remove()` returns `Success`.
+	pub fn new(cleanup: Cleanup) -> RwLockSpace<K,V> {
+		RwLockSpace{names:Arc::new(Mutex::new(HashMap::new())),initializing:Arc::new(Initializing::new()),cleanup}
+	}
+
+	// Find or create the inner RwLock for `key` and call `acquire` on it
+	// while holding the outer lock. `acquire` must not block. If it fails, the
+	// value is cleaned up if necessary. If the value is being created by
+	// someone else, `WouldBlock` is returned.
+	fn acquire<G,C,A>(&self, key: K, initial: C, acquire: A) -> TryLockResult<G,G>
+		where C: FnOnce() -> V, A: FnOnce(NamedRef<K,RwLock<V>>) -> TryLockResult<G,G>
+	{
+		let map=lock_unpoisoned(&self.names); // Acquire outer lock
+
+		// Don't wait for the value to be created by someone else
+		let (mut map,target)=get_or_create(&self.names,&self.initializing,map,&key,||Ok(RwLock::new(initial())),|_|Err(TryLockError::WouldBlock))?;
+		// Acquire inner lock, moving our reference. If it's poisoned, the guard
+		// in the error keeps the reference. Otherwise, on failure, our
+		// reference is dropped while still holding the outer lock.
+		match acquire(NamedRef(target)) {
+			Ok(guard) => {
+				drop::<MutexGuard<_>>(map); // Explicitly release outer lock
+				Ok(guard)
+			},
+			Err(TryLockError::Poisoned(err)) => {
+				drop::<MutexGuard<_>>(map); // Explicitly release outer lock
+				Err(TryLockError::Poisoned(err))
+			},
+			Err(err) => {
+				if self.cleanup==AutoCleanup {
+					try_remove_internal(&mut *map,&key,0);
+				}
+				Err(err)
+			}
+		}
+		// Release outer lock
+	}
+
+	// Find or create the inner RwLock for `key`, then release the outer lock
+	// and call `acquire` on it, which may block.
+	fn acquire_blocking<G,C,A>(&self, key: K, initial: C, acquire: A) -> Result<G,G>
+		where C: FnOnce() -> V, A: FnOnce(NamedRef<K,RwLock<V>>) -> Result<G,G>
+	{
+		let target={
+			let map=lock_unpoisoned(&self.names); // Acquire outer lock
+			match get_or_create(&self.names,&self.initializing,map,&key,||Ok::<_,Infallible>(RwLock::new(initial())),|map|Ok(self.initializing.wait(&self.names,map))) {
+				Ok((_,target)) => target,
+				Err(never) => match never {},
+			}
+			// Release outer lock
+		};
+
+		// While waiting, `target` is an additional reference to the inner
+		// RwLock, which keeps it from being removed. See the locking rule of
+		// `Shard` in the crate root. Once acquired, the guard holds it, even
+		// if the RwLock is poisoned.
+		acquire(NamedRef(target))
+	}
+
+	/// Find the object by `key`, or create it by calling `initial` if it does
+	/// not exist. Then, lock it with shared read access and return a ReadGuard
+	/// over the object. Once the guard is dropped, its object is unlocked, and
+	/// if `AutoCleanup` is specified for this space, removed if this is the
+	/// last use.
+	pub fn read<'a,C>(&'a self, key: K, initial: C) -> Result<ReadGuard<'a,K,V>,ReadGuard<'a,K,V>>
+		where C: FnOnce() -> V
+	{
+		let wrap=|guard|ReadGuard{owner:self,guard:Some(guard)};
+		self.acquire_blocking(key,initial,|target|target.owned_read()).map(wrap).map_err(|err|err.map_guard(wrap))
+	}
+
+	/// Find the object by `key`, or create it by calling `initial` if it does
+	/// not exist. Then, lock it with exclusive write access and return a
+	/// WriteGuard over the object. Once the guard is dropped, its object is
+	/// unlocked, and if `AutoCleanup` is specified for this space, removed if
+	/// this is the last use.
+	pub fn write<'a,C>(&'a self, key: K, initial: C) -> Result<WriteGuard<'a,K,V>,WriteGuard<'a,K,V>>
+		where C: FnOnce() -> V
+	{
+		let wrap=|guard|WriteGuard{owner:self,guard:Some(guard)};
+		self.acquire_blocking(key,initial,|target|target.owned_write()).map(wrap).map_err(|err|err.map_guard(wrap))
+	}
+
+	/// Like `read()`, but returns `WouldBlock` instead of blocking if the
+	/// object is currently locked for writing.
+	pub fn try_read<'a,C>(&'a self, key: K, initial: C) -> TryLockResult<ReadGuard<'a,K,V>,ReadGuard<'a,K,V>>
+		where C: FnOnce() -> V
+	{
+		let wrap=|guard|ReadGuard{owner:self,guard:Some(guard)};
+		self.acquire(key,initial,|target|target.try_owned_read()).map(wrap).map_err(|err|err.map_guard(wrap))
+	}
+
+	/// Like `write()`, but returns `WouldBlock` instead of blocking if the
+	/// object is currently locked.
+	pub fn try_write<'a,C>(&'a self, key: K, initial: C) -> TryLockResult<WriteGuard<'a,K,V>,WriteGuard<'a,K,V>>
+		where C: FnOnce() -> V
+	{
+		let wrap=|guard|WriteGuard{owner:self,guard:Some(guard)};
+		self.acquire(key,initial,|target|target.try_owned_write()).map(wrap).map_err(|err|err.map_guard(wrap))
+	}
+
+	/// Clear the poisoned state of the object for `key`, if it exists, so
+	/// that locking it no longer returns a `Poisoned` error. Fix up the value
+	/// first, through the guard in the error.
+	///
+	/// With the `spin` and `parking_lot` features, panics don't poison
+	/// objects, so this does nothing.
+	pub fn clear_poison<Q>(&self, key: &Q)
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		let map=lock_unpoisoned(&self.names); // Acquire outer lock
+		#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+		if let Some(target)=map.get(key) {
+			target.lock.clear_poison();
+		}
+		#[cfg(any(feature="spin",feature="parking_lot"))]
+		let _=(map,key);
+		// Release outer lock
+	}
+
+	/// Find the object by `key`, then delete it if it is not actively being
+	/// used. If it is actually being used, `WouldBlock` will be returned.
+	///
+	/// This is only useful if this `RwLockSpace` is of the `KeepUnused` kind.
+	pub fn try_remove(&self, key: K) -> LockSpaceRemoveResult
+	{
+		let mut map=lock_unpoisoned(&self.names); // Acquire outer lock
+		try_remove_internal(&mut *map,&key,0)
+		// Release outer lock
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::prelude::v1::*;
+	use std::thread;
+	use std::sync::{Arc,Barrier};
+	use super::*;
+	use {KeepUnused,AutoCleanup};
+
+	#[test]
+	fn concurrent_readers() {
+		let space=RwLockSpace::<String,i32>::new(AutoCleanup);
+		let barrier=Arc::new(Barrier::new(10));
+		let mut threads=vec![];
+
+		// All readers must be able to hold the lock at the same time, or the
+		// barrier will never be passed
+		for _ in 0..10 {
+			let space_clone=space.clone();
+			let barrier_clone=barrier.clone();
+			threads.push(thread::spawn(move||{
+				let value=space_clone.read("test".to_string(),||1).unwrap();
+				barrier_clone.wait();
+				*value
+			}));
+		}
+
+		for t in threads.into_iter() {
+			assert_eq!(t.join().unwrap(),1);
+		}
+	}
+
+	#[test]
+	fn writer_excluded() {
+		let space=RwLockSpace::<String,i32>::new(KeepUnused);
+
+		let reader=space.read("test".to_string(),||1).unwrap();
+		assert!(space.try_read("test".to_string(),||2).is_ok());
+		assert!(matches!(space.try_write("test".to_string(),||2),Err(TryLockError::WouldBlock)));
+		assert!(matches!(space.try_remove("test".to_string()),LockSpaceRemoveResult::WouldBlock));
+		drop(reader);
+
+		let mut writer=space.write("test".to_string(),||2).unwrap();
+		*writer+=1;
+		assert!(matches!(space.try_read("test".to_string(),||2),Err(TryLockError::WouldBlock)));
+		drop(writer);
+
+		assert_eq!(*space.read("test".to_string(),||5).unwrap(),2);
+		assert!(matches!(space.try_remove("test".to_string()),LockSpaceRemoveResult::Success));
+	}
+
+	#[test]
+	fn initial_outside_lock() {
+		use std::sync::mpsc;
+
+		let space=RwLockSpace::<String,i32>::new(KeepUnused);
+		// The space isn't locked while creating a value
+		let value=space.read("a".to_string(),||*space.read("b".to_string(),||1).unwrap()+1).unwrap();
+		assert_eq!(*value,2);
+		drop(value);
+
+		let (created_tx,created_rx)=mpsc::channel();
+		let (done_tx,done_rx)=mpsc::channel();
+		let space_clone=space.clone();
+		let creator=thread::spawn(move||*space_clone.write("c".to_string(),||{
+			created_tx.send(()).unwrap();
+			done_rx.recv().unwrap();
+			3
+		}).unwrap());
+		created_rx.recv().unwrap();
+		// Only the key that's being created has to wait
+		assert!(matches!(space.try_read("c".to_string(),||0),Err(TryLockError::WouldBlock)));
+		assert!(space.try_write("a".to_string(),||0).is_ok());
+		done_tx.send(()).unwrap();
+		assert_eq!(creator.join().unwrap(),3);
+		assert_eq!(*space.read("c".to_string(),||0).unwrap(),3);
+	}
+
+	#[test]
+	#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+	fn panic_isolation() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		// A panic in Clone happens while holding the outer lock
+		#[derive(PartialEq,Eq,Hash)]
+		struct Key(bool);
+		impl Clone for Key {
+			fn clone(&self) -> Key {
+				assert!(!self.0,"Clone panicked");
+				Key(self.0)
+			}
+		}
+
+		let space=RwLockSpace::<Key,i32>::new(KeepUnused);
+		*space.write(Key(false),||0).unwrap()+=1;
+		let result=panic::catch_unwind(AssertUnwindSafe(||space.read(Key(true),||0).map(drop)));
+		assert!(result.is_err());
+
+		// It doesn't poison the space
+		assert_eq!(*space.read(Key(false),||0).unwrap(),1);
+		assert!(space.try_write(Key(false),||0).is_ok());
+		assert!(matches!(space.try_remove(Key(false)),LockSpaceRemoveResult::Success));
+	}
+
+	#[test]
+	#[cfg(all(feature="std",not(feature="spin"),not(feature="parking_lot")))]
+	fn poison_recovery() {
+		use std::panic::{self,AssertUnwindSafe};
+
+		let space=RwLockSpace::<String,i32>::new(KeepUnused);
+		let _=panic::catch_unwind(AssertUnwindSafe(||{
+			let mut value=space.write("a".to_string(),||0).unwrap();
+			*value=7;
+			panic!("writer panicked");
+		}));
+
+		// The guard is handed back, also for readers and non-blocking locks
+		assert_eq!(*space.read("a".to_string(),||0).err().unwrap().into_inner(),7);
+		match space.try_read("a".to_string(),||0) {
+			Err(TryLockError::Poisoned(err)) => assert_eq!(*err.into_inner(),7),
+			_ => panic!("The lock must be poisoned"),
+		}
+		let reader=space.read("a".to_string(),||0).err().unwrap().into_inner();
+		assert!(matches!(space.try_write("a".to_string(),||0),Err(TryLockError::WouldBlock)));
+		drop(reader);
+		*space.write("a".to_string(),||0).err().unwrap().into_inner()=0;
+
+		space.clear_poison("a");
+		assert_eq!(*space.write("a".to_string(),||0).unwrap(),0);
+		assert!(space.try_read("a".to_string(),||0).is_ok());
+	}
+}
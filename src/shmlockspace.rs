@@ -0,0 +1,750 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Named locks that live in shared memory, for low-latency locking between
+//! processes.
+//!
+//! A `ShmLockSpace` is a fixed-capacity hash table of keys in a named POSIX
+//! shared-memory segment. Every key has a robust, process-shared pthread
+//! mutex. If a process dies while holding a key, the next locker gets a
+//! `Poisoned` error, just like with a `LockSpace` whose holder panicked.
+//!
+//! Keys stay in the table until they are removed with `try_remove()`, like in
+//! a `KeepUnused` space.
+//!
+//! ```
+//! use namedlock::ShmLockSpace;
+//! use namedlock::shmlockspace::{self,TryLockError};
+//!
+//! let name=format!("/namedlock-doctest-{}",std::process::id());
+//! let space=ShmLockSpace::<&str>::open(&name,64,32).unwrap();
+//! // Another process opening the same name shares the locks
+//! let other=ShmLockSpace::<&str>::open(&name,64,32).unwrap();
+//!
+//! let guard=space.lock("resource").unwrap();
+//! match other.try_lock("resource") {
+//!     Err(TryLockError::WouldBlock) => {},
+//!     _ => panic!("The key is locked"),
+//! }
+//! drop(guard);
+//! assert!(other.try_lock("resource").is_ok());
+//! shmlockspace::unlink(&name).unwrap();
+//! ```
+
+use std::cell::UnsafeCell;
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool,AtomicU32,AtomicU64,Ordering};
+use std::thread;
+use std::time::{Duration,Instant};
+
+use libc;
+
+use lockresult::PoisonError;
+use {LockSpaceRemoveResult,fnv1a};
+
+/// An enumeration of possible errors of `ShmLockSpace::lock()`.
+pub enum LockError<G=()> {
+	/// The previous holder of the lock panicked or its process died.
+	Poisoned(PoisonError<G>),
+	/// The key is longer than the maximum key length of the space.
+	KeyTooLong,
+	/// The key is not in the space yet, and there's no free slot for it.
+	Full,
+	/// The current thread already holds the key, so waiting for it would
+	/// never return.
+	Deadlock,
+	/// Locking a mutex in the segment failed. For example, with
+	/// `ENOTRECOVERABLE` if a process died while holding it and the next
+	/// holder didn't make it consistent again.
+	Io(io::Error),
+}
+
+impl<G> From<PoisonError<G>> for LockError<G> {
+	fn from(err: PoisonError<G>) -> LockError<G> {
+		LockError::Poisoned(err)
+	}
+}
+
+impl<G> From<io::Error> for LockError<G> {
+	fn from(err: io::Error) -> LockError<G> {
+		LockError::Io(err)
+	}
+}
+
+impl<G> fmt::Debug for LockError<G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			LockError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			LockError::KeyTooLong => fmt.write_str("KeyTooLong"),
+			LockError::Full => fmt.write_str("Full"),
+			LockError::Deadlock => fmt.write_str("Deadlock"),
+			LockError::Io(ref err) => write!(fmt,"Io({:?})",err),
+		}
+	}
+}
+
+/// An enumeration of possible errors of `ShmLockSpace::try_lock()`.
+pub enum TryLockError<G=()> {
+	/// The previous holder of the lock panicked or its process died.
+	Poisoned(PoisonError<G>),
+	/// The lock could not be acquired at this time because the operation would
+	/// otherwise block.
+	WouldBlock,
+	/// The key is longer than the maximum key length of the space.
+	KeyTooLong,
+	/// The key is not in the space yet, and there's no free slot for it.
+	Full,
+	/// Locking a mutex in the segment failed, see `LockError::Io`.
+	Io(io::Error),
+}
+
+impl<G> From<PoisonError<G>> for TryLockError<G> {
+	fn from(err: PoisonError<G>) -> TryLockError<G> {
+		TryLockError::Poisoned(err)
+	}
+}
+
+impl<G> From<io::Error> for TryLockError<G> {
+	fn from(err: io::Error) -> TryLockError<G> {
+		TryLockError::Io(err)
+	}
+}
+
+impl<G> fmt::Debug for TryLockError<G> {
+	fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+		match *self {
+			TryLockError::Poisoned(ref err) => write!(fmt,"Poisoned({:?})",err),
+			TryLockError::WouldBlock => fmt.write_str("WouldBlock"),
+			TryLockError::KeyTooLong => fmt.write_str("KeyTooLong"),
+			TryLockError::Full => fmt.write_str("Full"),
+			TryLockError::Io(ref err) => write!(fmt,"Io({:?})",err),
+		}
+	}
+}
+
+// Stored in the header once the segment has been initialized
+const MAGIC: u64 = 0x6e61_6d65_646c_6f63;
+
+// How long `open()` waits for another process to initialize the segment
+const INIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+// The states of a slot
+const EMPTY: u32 = 0;
+const USED: u32 = 1;
+const REMOVED: u32 = 2;
+
+#[repr(C)]
+struct Header {
+	magic: AtomicU64,
+	capacity: u64,
+	max_key: u64,
+	// The outer lock, which protects the state and the key of every slot
+	lock: UnsafeCell<libc::pthread_mutex_t>,
+}
+
+#[repr(C)]
+struct Slot {
+	// The inner lock of the key
+	lock: UnsafeCell<libc::pthread_mutex_t>,
+	state: AtomicU32,
+	poisoned: AtomicBool,
+	// Incremented whenever the key is removed, so that a locker that was
+	// waiting for the slot can tell that it doesn't belong to its key anymore
+	generation: AtomicU64,
+	key_len: AtomicU64,
+	// Followed by `max_key` bytes of key
+}
+
+fn align_up(len: usize, align: usize) -> usize {
+	len.div_ceil(align)*align
+}
+
+fn slots_offset() -> usize {
+	align_up(mem::size_of::<Header>(),mem::align_of::<Slot>())
+}
+
+fn slot_stride(max_key: usize) -> usize {
+	align_up(mem::size_of::<Slot>()+max_key,mem::align_of::<Slot>())
+}
+
+fn segment_len(capacity: usize, max_key: usize) -> Option<usize> {
+	slot_stride(max_key).checked_mul(capacity)?.checked_add(slots_offset())
+}
+
+fn check(ret: libc::c_int) -> io::Result<()> {
+	match ret {
+		0 => Ok(()),
+		err => Err(io::Error::from_raw_os_error(err)),
+	}
+}
+
+// Initialize a robust, process-shared mutex in place
+unsafe fn init_mutex(mutex: *mut libc::pthread_mutex_t) -> io::Result<()> {
+	let mut attr: libc::pthread_mutexattr_t=mem::zeroed();
+	check(libc::pthread_mutexattr_init(&mut attr))?;
+	let result=check(libc::pthread_mutexattr_setpshared(&mut attr,libc::PTHREAD_PROCESS_SHARED))
+		.and_then(|()|check(libc::pthread_mutexattr_setrobust(&mut attr,libc::PTHREAD_MUTEX_ROBUST)))
+		// Report relocking by the owner instead of hanging
+		.and_then(|()|check(libc::pthread_mutexattr_settype(&mut attr,libc::PTHREAD_MUTEX_ERRORCHECK)))
+		.and_then(|()|check(libc::pthread_mutex_init(mutex,&attr)));
+	libc::pthread_mutexattr_destroy(&mut attr);
+	result
+}
+
+enum Acquired {
+	Locked,
+	// The previous owner died while holding the mutex
+	OwnerDied,
+	WouldBlock,
+	// The current thread already holds the mutex
+	Deadlock,
+}
+
+// Lock a robust mutex. If its previous owner died, the mutex is made
+// consistent again. Any other error is returned, since the mutex is shared
+// with other processes, which may have left it in any state.
+unsafe fn lock_mutex(mutex: *mut libc::pthread_mutex_t, wait: bool) -> io::Result<Acquired> {
+	let ret=if wait { libc::pthread_mutex_lock(mutex) } else { libc::pthread_mutex_trylock(mutex) };
+	match ret {
+		0 => Ok(Acquired::Locked),
+		libc::EOWNERDEAD => {
+			libc::pthread_mutex_consistent(mutex);
+			Ok(Acquired::OwnerDied)
+		},
+		libc::EBUSY | libc::EDEADLK if !wait => Ok(Acquired::WouldBlock),
+		libc::EDEADLK => Ok(Acquired::Deadlock),
+		err => Err(io::Error::from_raw_os_error(err)),
+	}
+}
+
+// Unlock a mutex held by the current thread. Only its owner may unlock an
+// error-checking mutex, otherwise this fails with EPERM.
+unsafe fn unlock_mutex(mutex: *mut libc::pthread_mutex_t) {
+	let ret=libc::pthread_mutex_unlock(mutex);
+	debug_assert_eq!(ret,0,"Unlocking a shared-memory mutex failed: {}",io::Error::from_raw_os_error(ret));
+}
+
+// A mapping of a shared-memory segment
+struct Segment {
+	ptr: *mut u8,
+	len: usize,
+	capacity: usize,
+	max_key: usize,
+}
+
+// The segment is only accessed through atomics and process-shared mutexes
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl Drop for Segment {
+	fn drop(&mut self) {
+		unsafe {
+			libc::munmap(self.ptr as *mut libc::c_void,self.len);
+		}
+	}
+}
+
+impl Segment {
+	// Map the shared-memory object `fd`. If `created` is set, it was just
+	// created and is initialized here. Otherwise, wait until the process that
+	// created it has initialized it.
+	unsafe fn map(fd: libc::c_int, created: bool, capacity: usize, max_key: usize) -> io::Result<Segment> {
+		let invalid=|msg: &str|io::Error::new(io::ErrorKind::InvalidInput,msg);
+		let expected=segment_len(capacity,max_key).ok_or_else(||invalid("Shared-memory segment too large"))?;
+		let deadline=Instant::now()+INIT_TIMEOUT;
+		let len=if created {
+			if libc::ftruncate(fd,expected as libc::off_t)<0 {
+				return Err(io::Error::last_os_error());
+			}
+			expected
+		} else {
+			loop {
+				let mut stat: libc::stat=mem::zeroed();
+				if libc::fstat(fd,&mut stat)<0 {
+					return Err(io::Error::last_os_error());
+				}
+				if stat.st_size>0 {
+					break stat.st_size as usize;
+				}
+				if Instant::now()>deadline {
+					return Err(io::Error::new(io::ErrorKind::TimedOut,"Shared-memory segment was not initialized"));
+				}
+				thread::sleep(Duration::from_millis(1));
+			}
+		};
+		if len!=expected {
+			return Err(invalid("Shared-memory segment has a different capacity or maximum key length"));
+		}
+
+		let ptr=libc::mmap(ptr::null_mut(),len,libc::PROT_READ|libc::PROT_WRITE,libc::MAP_SHARED,fd,0);
+		if ptr==libc::MAP_FAILED {
+			return Err(io::Error::last_os_error());
+		}
+		let segment=Segment{ptr:ptr as *mut u8,len,capacity,max_key};
+		let header=segment.ptr as *mut Header;
+		if created {
+			// Nobody else looks at the segment until the magic is stored
+			(*header).capacity=capacity as u64;
+			(*header).max_key=max_key as u64;
+			init_mutex(segment.header().lock.get())?;
+			for index in 0..capacity {
+				init_mutex(segment.slot(index).lock.get())?;
+			}
+			segment.header().magic.store(MAGIC,Ordering::Release);
+		} else {
+			while segment.header().magic.load(Ordering::Acquire)!=MAGIC {
+				if Instant::now()>deadline {
+					return Err(io::Error::new(io::ErrorKind::TimedOut,"Shared-memory segment was not initialized"));
+				}
+				thread::sleep(Duration::from_millis(1));
+			}
+			if (*header).capacity!=capacity as u64 || (*header).max_key!=max_key as u64 {
+				return Err(invalid("Shared-memory segment has a different capacity or maximum key length"));
+			}
+		}
+		Ok(segment)
+	}
+
+	fn header(&self) -> &Header {
+		unsafe { &*(self.ptr as *const Header) }
+	}
+
+	fn slot(&self, index: usize) -> &Slot {
+		unsafe { &*(self.ptr.add(slots_offset()+index*slot_stride(self.max_key)) as *const Slot) }
+	}
+
+	fn key_ptr(&self, index: usize) -> *mut u8 {
+		unsafe { (self.slot(index) as *const Slot as *mut u8).add(mem::size_of::<Slot>()) }
+	}
+
+	// IMPORTANT: The caller must hold the outer lock, or the inner lock of
+	// `index` for the key that's in the slot now. `TableGuard::find()` writes
+	// keys while holding only the outer lock, but only into free slots, and
+	// `try_remove()` only frees a slot while holding both locks.
+	fn key(&self, index: usize) -> &[u8] {
+		let len=self.slot(index).key_len.load(Ordering::Relaxed) as usize;
+		unsafe { slice::from_raw_parts(self.key_ptr(index),len) }
+	}
+
+	// Acquire the outer lock. If its previous owner died, the table is still
+	// consistent, since every change is completed by a single store. The
+	// outer lock is never held while calling this, so it can't deadlock.
+	fn lock_table<'a>(&'a self) -> io::Result<TableGuard<'a>> {
+		unsafe {
+			lock_mutex(self.header().lock.get(),true)?;
+		}
+		Ok(TableGuard{segment:self})
+	}
+}
+
+// The outer lock of a segment
+struct TableGuard<'a> {
+	segment: &'a Segment,
+}
+
+impl<'a> TableGuard<'a> {
+	// Find the slot of `key`, and its generation. If `insert` is set, and
+	// `key` is not in the table yet, it's given a free slot. Returns `None` if
+	// `key` is not found, or if there's no free slot.
+	fn find(&self, key: &[u8], insert: bool) -> Option<(usize,u64)> {
+		let segment=self.segment;
+		let start=(fnv1a(key)%segment.capacity as u64) as usize;
+		let mut free=None;
+		for index in (0..segment.capacity).map(|i|(start+i)%segment.capacity) {
+			let slot=segment.slot(index);
+			match slot.state.load(Ordering::Relaxed) {
+				EMPTY => {
+					free=free.or(Some(index));
+					break;
+				},
+				REMOVED => free=free.or(Some(index)),
+				_ => if segment.key(index)==key {
+					return Some((index,slot.generation.load(Ordering::Relaxed)));
+				},
+			}
+		}
+		if !insert {
+			return None;
+		}
+		let index=free?;
+		let slot=segment.slot(index);
+		unsafe {
+			ptr::copy_nonoverlapping(key.as_ptr(),segment.key_ptr(index),key.len());
+		}
+		slot.key_len.store(key.len() as u64,Ordering::Relaxed);
+		slot.state.store(USED,Ordering::Relaxed);
+		Some((index,slot.generation.load(Ordering::Relaxed)))
+	}
+}
+
+impl<'a> Drop for TableGuard<'a> {
+	fn drop(&mut self) {
+		unsafe {
+			unlock_mutex(self.segment.header().lock.get());
+		}
+	}
+}
+
+/// An RAII implementation of a "scoped lock" of a key in a ShmLockSpace.
+/// When this structure is dropped (falls out of scope), the lock will be
+/// unlocked.
+///
+/// If the guard is dropped during a panic, the key is poisoned. The guard
+/// can't be sent to another thread, since only the thread that locked a
+/// mutex may unlock it.
+pub struct ShmLockGuard<'a> {
+	segment: &'a Segment,
+	index: usize,
+	// Segment is Send and Sync, but the guard must not be Send
+	_not_send: PhantomData<*const ()>,
+}
+
+impl<'a> ShmLockGuard<'a> {
+	/// The key this guard was locked for.
+	pub fn key(&self) -> &[u8] {
+		self.segment.key(self.index)
+	}
+}
+
+impl<'a> Drop for ShmLockGuard<'a> {
+	fn drop(&mut self) {
+		let slot=self.segment.slot(self.index);
+		if thread::panicking() {
+			slot.poisoned.store(true,Ordering::Relaxed);
+		}
+		unsafe {
+			unlock_mutex(slot.lock.get());
+		}
+	}
+}
+
+/// A `ShmLockSpace<K>` is a namespace for locks that are shared with every
+/// process that opens the same shared-memory segment.
+///
+/// The segment has room for a fixed number of keys of a fixed maximum
+/// length, which are compared byte for byte.
+pub struct ShmLockSpace<K> {
+	segment: Arc<Segment>,
+	phantom: PhantomData<fn(K)>,
+}
+
+// This needs to be implemented manually, since #[derive(Clone)] doesn't
+// understand that the type parameters are only used within the Arc<_>
+impl<K> Clone for ShmLockSpace<K> {
+	fn clone(&self) -> ShmLockSpace<K> {
+		ShmLockSpace{segment:self.segment.clone(),phantom:PhantomData}
+	}
+}
+
+impl<K: AsRef<[u8]>> ShmLockSpace<K> {
+	/// Open the shared-memory segment `name`, which has room for `capacity`
+	/// keys of at most `max_key` bytes, creating it if it doesn't exist yet.
+	///
+	/// `name` is passed to `shm_open`, so it should start with a `/`. Every
+	/// process must open the segment with the same `capacity` and `max_key`.
+	pub fn open(name: &str, capacity: usize, max_key: usize) -> io::Result<ShmLockSpace<K>> {
+		if capacity==0 {
+			return Err(io::Error::new(io::ErrorKind::InvalidInput,"Capacity must not be 0"));
+		}
+		let name=c_name(name)?;
+		unsafe {
+			let mut created=true;
+			let mut fd=libc::shm_open(name.as_ptr(),libc::O_RDWR|libc::O_CREAT|libc::O_EXCL,0o600);
+			if fd<0 && io::Error::last_os_error().raw_os_error()==Some(libc::EEXIST) {
+				created=false;
+				fd=libc::shm_open(name.as_ptr(),libc::O_RDWR,0);
+			}
+			if fd<0 {
+				return Err(io::Error::last_os_error());
+			}
+			let result=Segment::map(fd,created,capacity,max_key);
+			libc::close(fd);
+			if result.is_err() && created {
+				libc::shm_unlink(name.as_ptr());
+			}
+			Ok(ShmLockSpace{segment:Arc::new(result?),phantom:PhantomData})
+		}
+	}
+
+	/// Lock `key`, blocking the current thread until no other thread or
+	/// process holds it, and return a ShmLockGuard over it. The key is added
+	/// to the space if it's not in it yet.
+	///
+	/// If the previous holder of the key panicked or its process died, the
+	/// key is poisoned. It's locked anyway, and the guard is returned in the
+	/// `Poisoned` error, until `clear_poison()` is called.
+	///
+	/// If the current thread already holds `key`, `Deadlock` is returned.
+	pub fn lock<'a>(&'a self, key: K) -> Result<ShmLockGuard<'a>,LockError<ShmLockGuard<'a>>> {
+		match self.acquire(key.as_ref(),true) {
+			Ok(Some(guard)) => Ok(guard),
+			Err(err) => Err(err),
+			// We only give up if we're not supposed to wait
+			Ok(None) => unreachable!(),
+		}
+	}
+
+	/// Like `lock()`, but returns `WouldBlock` instead of blocking if another
+	/// thread or process holds `key`, or if the current thread does.
+	pub fn try_lock<'a>(&'a self, key: K) -> Result<ShmLockGuard<'a>,TryLockError<ShmLockGuard<'a>>> {
+		match self.acquire(key.as_ref(),false) {
+			Ok(Some(guard)) => Ok(guard),
+			Ok(None) => Err(TryLockError::WouldBlock),
+			Err(LockError::Poisoned(err)) => Err(TryLockError::Poisoned(err)),
+			Err(LockError::KeyTooLong) => Err(TryLockError::KeyTooLong),
+			Err(LockError::Full) => Err(TryLockError::Full),
+			Err(LockError::Io(err)) => Err(TryLockError::Io(err)),
+			// Only waiting for a key we hold deadlocks
+			Err(LockError::Deadlock) => unreachable!(),
+		}
+	}
+
+	// Returns `None` if `wait` is not set and the key is held
+	fn acquire<'a>(&'a self, key: &[u8], wait: bool) -> Result<Option<ShmLockGuard<'a>>,LockError<ShmLockGuard<'a>>> {
+		let segment=&*self.segment;
+		if key.len()>segment.max_key {
+			return Err(LockError::KeyTooLong);
+		}
+		loop {
+			let (index,generation)=match segment.lock_table()?.find(key,true) {
+				Some(found) => found,
+				None => return Err(LockError::Full),
+			};
+			let slot=segment.slot(index);
+			let owner_died=match unsafe { lock_mutex(slot.lock.get(),wait)? } {
+				Acquired::Locked => false,
+				Acquired::OwnerDied => true,
+				Acquired::WouldBlock => return Ok(None),
+				Acquired::Deadlock => return Err(LockError::Deadlock),
+			};
+			if slot.generation.load(Ordering::Relaxed)!=generation {
+				// The key was removed while we were waiting for it. Whoever
+				// held the slot in between didn't hold our key.
+				unsafe {
+					unlock_mutex(slot.lock.get());
+				}
+				continue;
+			}
+			if owner_died {
+				slot.poisoned.store(true,Ordering::Relaxed);
+			}
+			let guard=ShmLockGuard{segment,index,_not_send:PhantomData};
+			return match slot.poisoned.load(Ordering::Relaxed) {
+				false => Ok(Some(guard)),
+				true => Err(LockError::Poisoned(PoisonError::new(guard))),
+			};
+		}
+	}
+
+	/// Clear the poisoned state of `key`, after the value it protects has
+	/// been repaired, or is known to be fine.
+	///
+	/// Fails if locking the table fails, see `LockError::Io`.
+	pub fn clear_poison(&self, key: &K) -> io::Result<()> {
+		let table=self.segment.lock_table()?;
+		if let Some((index,_))=table.find(key.as_ref(),false) {
+			self.segment.slot(index).poisoned.store(false,Ordering::Relaxed);
+		}
+		Ok(())
+	}
+
+	/// Find `key`, then delete it if it is not locked. If it is locked,
+	/// `WouldBlock` will be returned. Deleting a key frees its slot for other
+	/// keys.
+	///
+	/// Fails if locking the table or the key fails, see `LockError::Io`.
+	pub fn try_remove(&self, key: K) -> io::Result<LockSpaceRemoveResult> {
+		let table=self.segment.lock_table()?;
+		let index=match table.find(key.as_ref(),false) {
+			Some((index,_)) => index,
+			None => return Ok(LockSpaceRemoveResult::NotFound),
+		};
+		let slot=self.segment.slot(index);
+		// Only try the inner lock while holding the outer lock, since lockers
+		// acquire them in the opposite order
+		if let Acquired::WouldBlock=unsafe { lock_mutex(slot.lock.get(),false)? } {
+			// Also if the current thread holds it
+			return Ok(LockSpaceRemoveResult::WouldBlock);
+		}
+		slot.generation.fetch_add(1,Ordering::Relaxed);
+		slot.poisoned.store(false,Ordering::Relaxed);
+		slot.state.store(REMOVED,Ordering::Relaxed);
+		unsafe {
+			unlock_mutex(slot.lock.get());
+		}
+		Ok(LockSpaceRemoveResult::Success)
+	}
+}
+
+fn c_name(name: &str) -> io::Result<CString> {
+	CString::new(name).map_err(|_|io::Error::new(io::ErrorKind::InvalidInput,"Name contains a NUL byte"))
+}
+
+/// Remove the shared-memory segment `name`, so that the next `open()` creates
+/// a new one. Spaces that are already open keep using the old segment.
+pub fn unlink(name: &str) -> io::Result<()> {
+	let name=c_name(name)?;
+	match unsafe { libc::shm_unlink(name.as_ptr()) } {
+		0 => Ok(()),
+		_ => Err(io::Error::last_os_error()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::panic::{self,AssertUnwindSafe};
+	use std::process;
+	use super::*;
+
+	// Opens a new segment that's unlinked again when the test is done
+	struct TestSpace {
+		name: String,
+	}
+
+	impl TestSpace {
+		fn new(name: &str) -> TestSpace {
+			let name=format!("/namedlock-test-{}-{}",process::id(),name);
+			let _=unlink(&name);
+			TestSpace{name}
+		}
+
+		fn open(&self, capacity: usize) -> ShmLockSpace<&'static str> {
+			ShmLockSpace::open(&self.name,capacity,8).unwrap()
+		}
+	}
+
+	impl Drop for TestSpace {
+		fn drop(&mut self) {
+			let _=unlink(&self.name);
+		}
+	}
+
+	#[test]
+	fn capacity() {
+		let test=TestSpace::new("capacity");
+		let space=test.open(2);
+		assert!(matches!(space.lock("too long key"),Err(LockError::KeyTooLong)));
+		drop(space.lock("a").unwrap());
+		let b=space.lock("b").unwrap();
+		assert_eq!(b.key(),b"b");
+		assert!(matches!(space.try_lock("c"),Err(TryLockError::Full)));
+
+		assert!(matches!(space.try_remove("b").unwrap(),LockSpaceRemoveResult::WouldBlock));
+		assert!(matches!(space.try_remove("a").unwrap(),LockSpaceRemoveResult::Success));
+		assert!(matches!(space.try_remove("a").unwrap(),LockSpaceRemoveResult::NotFound));
+		drop(space.lock("c").unwrap());
+		drop(b);
+
+		assert!(ShmLockSpace::<&str>::open(&test.name,3,8).is_err());
+	}
+
+	#[test]
+	fn shared_between_mappings() {
+		// Separate mappings of the segment behave like different processes
+		let test=TestSpace::new("shared");
+		let first=test.open(16);
+		let second=test.open(16);
+		let guard=first.lock("test").unwrap();
+		assert!(matches!(second.try_lock("test"),Err(TryLockError::WouldBlock)));
+		assert!(second.try_lock("other").is_ok());
+		drop(guard);
+		assert!(second.try_lock("test").is_ok());
+	}
+
+	#[test]
+	fn removed_while_waiting() {
+		let test=TestSpace::new("removed");
+		let space=test.open(16);
+		let guard=space.lock("test").unwrap();
+		let space_clone=space.clone();
+		let waiter=thread::spawn(move||space_clone.lock("test").is_ok());
+		thread::sleep(Duration::from_millis(50));
+		drop(guard);
+		// Whether the waiter got the key first or not, it's locked for the
+		// key that's in the space now
+		let _=space.try_remove("test");
+		assert!(waiter.join().unwrap());
+		assert!(space.try_lock("test").is_ok());
+	}
+
+	#[test]
+	fn relock() {
+		let test=TestSpace::new("relock");
+		let space=test.open(16);
+		let guard=space.lock("test").unwrap();
+		assert!(matches!(space.lock("test"),Err(LockError::Deadlock)));
+		assert!(matches!(space.try_lock("test"),Err(TryLockError::WouldBlock)));
+		assert!(matches!(space.try_remove("test").unwrap(),LockSpaceRemoveResult::WouldBlock));
+		drop(guard);
+		assert!(space.lock("test").is_ok());
+	}
+
+	#[test]
+	fn owner_died() {
+		let test=TestSpace::new("died");
+		let space=test.open(16);
+
+		// A robust mutex also notices when its owning thread exits
+		let space_clone=space.clone();
+		thread::spawn(move||mem::forget(space_clone.lock("died").unwrap())).join().unwrap();
+		match space.lock("died") {
+			Err(LockError::Poisoned(err)) => assert_eq!(err.into_inner().key(),b"died"),
+			_ => panic!("The lock must be poisoned"),
+		}
+		assert!(matches!(space.try_lock("died"),Err(TryLockError::Poisoned(_))));
+		space.clear_poison(&"died").unwrap();
+		assert!(space.lock("died").is_ok());
+
+		let result=panic::catch_unwind(AssertUnwindSafe(||{
+			let _guard=space.lock("panic").unwrap();
+			panic!("Holder panicked");
+		}));
+		assert!(result.is_err());
+		assert!(matches!(space.lock("panic"),Err(LockError::Poisoned(_))));
+		assert!(space.try_lock("died").is_ok());
+	}
+
+	#[test]
+	fn unrecoverable() {
+		let test=TestSpace::new("unrecoverable");
+		let space=test.open(16);
+		let index=space.lock("test").unwrap().index;
+		let mutex=space.segment.slot(index).lock.get() as usize;
+
+		// Another process could leave the mutex like this, by unlocking it
+		// after its owner died without making it consistent
+		thread::spawn(move||unsafe { libc::pthread_mutex_lock(mutex as *mut _) }).join().unwrap();
+		thread::spawn(move||unsafe {
+			assert_eq!(libc::pthread_mutex_lock(mutex as *mut _),libc::EOWNERDEAD);
+			libc::pthread_mutex_unlock(mutex as *mut _);
+		}).join().unwrap();
+
+		match space.lock("test") {
+			Err(LockError::Io(err)) => assert_eq!(err.raw_os_error(),Some(libc::ENOTRECOVERABLE)),
+			_ => panic!("Locking must fail"),
+		}
+		// Other keys aren't affected
+		assert!(space.lock("other").is_ok());
+	}
+}
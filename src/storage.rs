@@ -0,0 +1,257 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Pluggable storage for the keys of a LockSpace.
+//!
+//! Every shard of a `LockSpace<K,V,S>` keeps its keys in a map that is
+//! created by the storage backend `S`. By default, that's a `HashMap` with
+//! std's hasher, see `HashStorage`. Another hasher, or an entirely different
+//! map, can be plugged in with `LockSpace::with_storage()`, without changing
+//! the code that locks the keys.
+//!
+//! The space stores its own bookkeeping, such as the per-key lock and the
+//! reference count that `AutoCleanup` relies on, as the values of the map.
+//! A backend only needs to store and find them by key.
+//!
+//! ```
+//! use std::collections::hash_map::DefaultHasher;
+//! use std::hash::BuildHasherDefault;
+//! use namedlock::{LockSpace,KeepUnused};
+//! use namedlock::storage::HashStorage;
+//!
+//! // The same hash function in every run, for reproducible benchmarks
+//! let storage=HashStorage::with_hasher(BuildHasherDefault::<DefaultHasher>::default());
+//! let space=LockSpace::<String,i32,_>::with_storage(KeepUnused,1,storage);
+//!
+//! space.with_lock("test".to_owned(),||0,|i|*i+=1).unwrap();
+//! assert_eq!(space.with_lock("test".to_owned(),||0,|i|*i).unwrap(),1);
+//! ```
+
+#[cfg(feature="std")] use std::collections::{hash_map,HashMap};
+#[cfg(not(feature="std"))] use core_collections::{hash_map,HashMap};
+use core::borrow::Borrow;
+use core::hash::{Hash,BuildHasher};
+
+/// A storage backend for the keys of a LockSpace. It creates a map for each
+/// shard of the space.
+///
+/// The maps are only accessed while holding the lock of their shard, so they
+/// don't need any synchronization of their own.
+pub trait Storage<K> {
+	/// A map from keys to values of type `T`.
+	type Map<T>: StorageMap<K,T>;
+
+	/// Create a new, empty map.
+	fn new_map<T>(&self) -> Self::Map<T>;
+}
+
+/// A map from keys to values of type `T`, as created by a `Storage`.
+///
+/// The methods behave like those of the same name on `HashMap`. A key is only
+/// ever inserted if it's not in the map.
+pub trait StorageMap<K,T> {
+	/// An iterator over the values of the map.
+	type Values<'a>: Iterator<Item=&'a T> where Self: 'a, T: 'a;
+
+	/// Returns a reference to the value of `key`.
+	fn get<Q>(&self, key: &Q) -> Option<&T>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash;
+
+	/// Inserts `value` for `key`, which is not in the map yet.
+	fn insert(&mut self, key: K, value: T);
+
+	/// Removes `key` from the map, returning its value.
+	fn remove<Q>(&mut self, key: &Q) -> Option<T>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash;
+
+	/// The number of keys in the map.
+	fn len(&self) -> usize;
+
+	/// Whether the map is empty.
+	fn is_empty(&self) -> bool {
+		self.len()==0
+	}
+
+	/// Iterate over the values of the map, in any order.
+	fn values(&self) -> Self::Values<'_>;
+
+	/// Whether `key` is in the map.
+	fn contains_key<Q>(&self, key: &Q) -> bool
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		self.get(key).is_some()
+	}
+}
+
+/// The default storage backend of a LockSpace, which keeps the keys in a
+/// `HashMap` that uses the hasher `H`.
+#[derive(Clone,Debug,Default)]
+pub struct HashStorage<H=hash_map::RandomState> {
+	hasher: H,
+}
+
+impl HashStorage {
+	/// Create a HashStorage that uses std's default hasher.
+	pub fn new() -> HashStorage {
+		HashStorage::default()
+	}
+}
+
+impl<H> HashStorage<H> {
+	/// Create a HashStorage whose maps use `hasher` to hash the keys.
+	pub fn with_hasher(hasher: H) -> HashStorage<H> {
+		HashStorage{hasher}
+	}
+}
+
+impl<K: Eq + Hash,H: BuildHasher + Clone> Storage<K> for HashStorage<H> {
+	type Map<T> = HashMap<K,T,H>;
+
+	fn new_map<T>(&self) -> HashMap<K,T,H> {
+		HashMap::with_hasher(self.hasher.clone())
+	}
+}
+
+impl<K: Eq + Hash,T,H: BuildHasher> StorageMap<K,T> for HashMap<K,T,H> {
+	type Values<'a> = hash_map::Values<'a,K,T> where Self: 'a, T: 'a;
+
+	fn get<Q>(&self, key: &Q) -> Option<&T>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		HashMap::get(self,key)
+	}
+
+	fn insert(&mut self, key: K, value: T) {
+		HashMap::insert(self,key,value);
+	}
+
+	fn remove<Q>(&mut self, key: &Q) -> Option<T>
+		where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+	{
+		HashMap::remove(self,key)
+	}
+
+	fn len(&self) -> usize {
+		HashMap::len(self)
+	}
+
+	fn values(&self) -> hash_map::Values<'_,K,T> {
+		HashMap::values(self)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::prelude::v1::*;
+	use std::borrow::Borrow;
+	use std::hash::Hash;
+	use std::slice;
+	use std::sync::{Arc,Mutex};
+	use super::*;
+	use {LockSpace,AutoCleanup,KeepUnused,LockSpaceRemoveResult};
+
+	// A mock backend that keeps the keys in a list, and records how many
+	// maps were created and how many keys were inserted
+	#[derive(Clone,Default)]
+	struct MockStorage {
+		maps: Arc<Mutex<usize>>,
+		inserts: Arc<Mutex<usize>>,
+	}
+
+	struct MockMap<K,T> {
+		entries: Vec<(K,T)>,
+		inserts: Arc<Mutex<usize>>,
+	}
+
+	impl<K: Eq> Storage<K> for MockStorage {
+		type Map<T> = MockMap<K,T>;
+
+		fn new_map<T>(&self) -> MockMap<K,T> {
+			*self.maps.lock().unwrap()+=1;
+			MockMap{entries:Vec::new(),inserts:self.inserts.clone()}
+		}
+	}
+
+	impl<K: Eq,T> MockMap<K,T> {
+		fn position<Q>(&self, key: &Q) -> Option<usize>
+			where K: Borrow<Q>, Q: ?Sized + Eq
+		{
+			self.entries.iter().position(|(k,_)|k.borrow()==key)
+		}
+	}
+
+	impl<K: Eq,T> StorageMap<K,T> for MockMap<K,T> {
+		type Values<'a> = std::iter::Map<slice::Iter<'a,(K,T)>,fn(&'a (K,T)) -> &'a T> where Self: 'a, T: 'a;
+
+		fn get<Q>(&self, key: &Q) -> Option<&T>
+			where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+		{
+			self.position(key).map(|i|&self.entries[i].1)
+		}
+
+		fn insert(&mut self, key: K, value: T) {
+			assert!(self.position(&key).is_none(),"Key inserted twice");
+			*self.inserts.lock().unwrap()+=1;
+			self.entries.push((key,value));
+		}
+
+		fn remove<Q>(&mut self, key: &Q) -> Option<T>
+			where K: Borrow<Q>, Q: ?Sized + Eq + Hash
+		{
+			self.position(key).map(|i|self.entries.swap_remove(i).1)
+		}
+
+		fn len(&self) -> usize {
+			self.entries.len()
+		}
+
+		fn values(&self) -> Self::Values<'_> {
+			self.entries.iter().map(|entry|&entry.1)
+		}
+	}
+
+	#[test]
+	fn mock_storage() {
+		let storage=MockStorage::default();
+		let space=LockSpace::<String,i32,_>::with_storage(AutoCleanup,4,storage.clone());
+		assert_eq!(*storage.maps.lock().unwrap(),4);
+
+		{
+			let mut a=space.lock_ref("a",||0).unwrap();
+			*a+=1;
+			assert!(space.contains_key("a"));
+			assert_eq!(space.keys(),["a"]);
+			assert!(space.try_lock("a".to_owned(),||0).is_err());
+		}
+		// The last guard removed the key from the backend
+		assert!(space.is_empty());
+		assert_eq!(*space.lock_ref("a",||2).unwrap(),2);
+		assert_eq!(*storage.inserts.lock().unwrap(),2);
+	}
+
+	#[test]
+	fn mock_storage_keep_unused() {
+		let space=LockSpace::<String,i32,_>::with_storage(KeepUnused,1,MockStorage::default());
+
+		let set=space.lock_many(vec!["a".to_owned(),"b".to_owned()],|_|1).unwrap();
+		assert_eq!(set["a"]+set["b"],2);
+		drop(set);
+		assert_eq!(space.len(),2);
+		assert!(matches!(space.try_remove("a".to_owned()),LockSpaceRemoveResult::Success));
+		assert_eq!(space.keys(),["b"]);
+	}
+}
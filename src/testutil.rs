@@ -0,0 +1,39 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+//! Helpers shared by the tests of several modules.
+
+use std::env;
+use std::fs;
+use std::os::unix::net::UnixListener;
+use std::path::PathBuf;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+use Cleanup;
+use remote::LockServer;
+
+// Start a lock server on a new socket, and return its path
+pub fn start_server(name: &str, cleanup: Cleanup) -> PathBuf {
+	let path=env::temp_dir().join(format!("namedlock-{}-{}.sock",process::id(),name));
+	let _=fs::remove_file(&path);
+	let listener=UnixListener::bind(&path).unwrap();
+	let server=Arc::new(LockServer::new(cleanup));
+	thread::spawn(move||server.serve(&listener,|err|panic!("{}",err)));
+	path
+}
@@ -0,0 +1,81 @@
+// namedlock - Namespaces for named locks
+// Copyright (C) 2026  Jethro G. Beekman
+//
+// This program is free software; you can redistribute it and/or
+// modify it under the terms of the GNU General Public License
+// as published by the Free Software Foundation; either version 2
+// of the License, or (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program; if not, write to the Free Software Foundation,
+// Inc., 51 Franklin Street, Fifth Floor, Boston, MA 02110-1301, USA.
+
+// Bookkeeping for the deadlock detection of `LockSpace`.
+//
+// The wait-for graph has two kinds of edges: from a key to the thread holding
+// it, and from a blocked thread to the key it is waiting for. Since a key has
+// at most one holder and a thread waits for at most one key, every path
+// through the graph is unique. A cycle can only be created by adding a wait,
+// so checking every new wait keeps the graph acyclic.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::thread::{self,ThreadId};
+
+pub struct WaitGraph<K> {
+	holders: HashMap<K,ThreadId>,
+	waiting: HashMap<ThreadId,K>,
+}
+
+impl<K: Eq + Hash + Clone> WaitGraph<K> {
+	pub fn new() -> WaitGraph<K> {
+		WaitGraph{holders:HashMap::new(),waiting:HashMap::new()}
+	}
+
+	// Record that the current thread is about to block on `key`. If that would
+	// close a cycle, nothing is recorded and the keys in the cycle are
+	// returned, starting with `key`.
+	pub fn start_wait(&mut self, key: &K) -> Result<(),Vec<K>> {
+		let me=thread::current().id();
+		let mut cycle=vec![key.clone()];
+		let mut next=key;
+		while let Some(holder)=self.holders.get(next) {
+			if *holder==me {
+				return Err(cycle);
+			}
+			next=match self.waiting.get(holder) {
+				Some(key) => key,
+				None => break,
+			};
+			cycle.push(next.clone());
+		}
+		self.waiting.insert(me,key.clone());
+		Ok(())
+	}
+
+	// Record that the current thread stopped waiting without acquiring the key
+	pub fn stop_wait(&mut self) {
+		self.waiting.remove(&thread::current().id());
+	}
+
+	// Record that the current thread now holds `key`
+	pub fn acquired(&mut self, key: &K) {
+		let me=thread::current().id();
+		self.waiting.remove(&me);
+		self.holders.insert(key.clone(),me);
+	}
+
+	// Record that the current thread released `key`. This may be recorded
+	// after another thread already recorded acquiring it, in which case the
+	// new holder is kept.
+	pub fn released(&mut self, key: &K) {
+		if self.holders.get(key)==Some(&thread::current().id()) {
+			self.holders.remove(key);
+		}
+	}
+}